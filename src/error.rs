@@ -195,6 +195,20 @@ pub enum Error {
         /// Original error returned by the Rust code.
         cause: Arc<Error>,
     },
+    /// Lua code was stopped through an [`InterruptHandle`].
+    ///
+    /// [`InterruptHandle`]: crate::InterruptHandle
+    Interrupted,
+    /// A value could not be transferred to another Lua state by [`Value::transfer_to`].
+    ///
+    /// [`Value::transfer_to`]: crate::Value::transfer_to
+    TransferError {
+        /// Path to the value which could not be transferred, relative to the transferred value,
+        /// for example `value.items[3].owner`.
+        path: StdString,
+        /// The reason why the value could not be transferred.
+        cause: Arc<Error>,
+    },
     /// A Rust panic that was previously resumed, returned again.
     ///
     /// This error can occur only when a Rust panic resumed previously was recovered
@@ -312,6 +326,10 @@ impl fmt::Display for Error {
                 }
                 write!(fmt, "caused by: {}", cause)
             }
+            Error::Interrupted => write!(fmt, "execution of Lua code was interrupted"),
+            Error::TransferError { ref path, ref cause } => {
+                write!(fmt, "cannot transfer {} to another Lua state: {}", path, cause)
+            }
             Error::PreviouslyResumedPanic => {
                 write!(fmt, "previously resumed panic returned again")
             }
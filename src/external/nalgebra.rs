@@ -3,7 +3,13 @@ use hv_math::Normed;
 use nalgebra::{Isometry2, Isometry3, Point2, Point3, RealField, Unit, Vector2, Vector3};
 use std::fmt;
 
+#[cfg(feature = "hv-ecs")]
+use crate::hv::LuaUserDataTypeTypeExt;
 use crate::{
+    hv::{
+        lazy::{lazy_table, LazyLoader},
+        TypeFamily,
+    },
     AnyUserData, FromLua, Lua, MetaMethod, Result, Table, ToLua, UserData, UserDataFields,
     UserDataMethods, Value,
 };
@@ -32,6 +38,11 @@ impl<T: LuaRealField> UserData for Vector2<T> {
             .add::<dyn fmt::Debug>();
     }
 
+    #[cfg(feature = "hv-ecs")]
+    fn on_type_metatable_init(table: Type<Type<Self>>) {
+        table.mark_component_type().mark_flat_component_type();
+    }
+
     fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
         get_set_coords!(fields, x, y);
     }
@@ -98,6 +109,11 @@ impl<T: LuaRealField> UserData for Vector3<T> {
             .add_conversion_from::<Vector3<T>>();
     }
 
+    #[cfg(feature = "hv-ecs")]
+    fn on_type_metatable_init(table: Type<Type<Self>>) {
+        table.mark_component_type().mark_flat_component_type();
+    }
+
     fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
         get_set_coords!(fields, x, y, z);
     }
@@ -154,6 +170,28 @@ impl<T: LuaRealField> UserData for Vector3<T> {
     }
 }
 
+#[cfg(feature = "hv-ecs")]
+impl<T: LuaRealField> crate::hv::ecs::FromNumbers for Vector2<T> {
+    const LEN: usize = 2;
+
+    fn from_numbers(numbers: &[f64]) -> Self {
+        Self::new(nalgebra::convert(numbers[0]), nalgebra::convert(numbers[1]))
+    }
+}
+
+#[cfg(feature = "hv-ecs")]
+impl<T: LuaRealField> crate::hv::ecs::FromNumbers for Vector3<T> {
+    const LEN: usize = 3;
+
+    fn from_numbers(numbers: &[f64]) -> Self {
+        Self::new(
+            nalgebra::convert(numbers[0]),
+            nalgebra::convert(numbers[1]),
+            nalgebra::convert(numbers[2]),
+        )
+    }
+}
+
 impl<'lua, T: LuaRealField> FromLua<'lua> for Point2<T> {
     fn from_lua(lua_value: Value<'lua>, lua: &'lua Lua) -> Result<Self> {
         Vector2::from_lua(lua_value, lua).map(Point2::from)
@@ -213,6 +251,11 @@ impl<T: LuaRealField> UserData for Isometry3<T> {
             .add::<dyn fmt::Debug>();
     }
 
+    #[cfg(feature = "hv-ecs")]
+    fn on_type_metatable_init(table: Type<Type<Self>>) {
+        table.mark_component_type();
+    }
+
     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_function(
             "mul",
@@ -281,29 +324,33 @@ impl<'lua> ToLua<'lua> for Module {
 }
 
 pub fn table(lua: &Lua) -> Result<Table> {
-    let src = "return family[...]";
-
-    macro_rules! e {
-            ($lua:ident, $name:ident($($ty:ty),*)) => {{
-                let t = $lua.create_table()?;
-                $(t.set(stringify!($ty), lua.create_userdata_type::<$name<$ty>>()?)?;)*
-                let env = lua.create_table_from(vec![("family", t)])?;
-                let f = lua.load(src).set_environment(env)?.into_function()?;
-                (stringify!($name), f)
-            }};
-        }
+    lazy_table(lua, "nalgebra", entries())
+}
 
-    macro_rules! types {
-            ($lua:ident, $($name:ident($($field:ty),*)),* $(,)?) => { vec![$(e!($lua, $name($($field),*))),*] };
+/// Each generic type is exposed as a [`TypeFamily`] of its instances, such as `Vector3(f32)`.
+macro_rules! family {
+    ($name:ident($($ty:ty),*)) => { $crate::external::nalgebra::family!($name($($ty),*) =>) };
+    ($name:ident($($ty:ty),*) => $($from:ty => $to:ty),*) => {{
+        fn load<'lua>(lua: &'lua Lua, path: &str) -> Result<Value<'lua>> {
+            TypeFamily::new(stringify!($name))
+                $(.member::<$name<$ty>>(stringify!($ty)))*
+                $(.conversion::<$name<$from>, $name<$to>>(|x| ::nalgebra::convert(*x)))*
+                .into_table(lua, path)
+                .map(Value::Table)
         }
 
-    let es = types! {lua,
-        Vector2(f32, f64),
-        Vector3(f32, f64),
+        (stringify!($name), load as LazyLoader)
+    }};
+}
 
-        Isometry2(f32, f64),
-        Isometry3(f32, f64),
-    };
+pub(crate) use family;
 
-    lua.create_table_from(es)
+/// The lazily loaded entries of the nalgebra module table, which `hv.math` extends.
+pub(crate) fn entries() -> Vec<(&'static str, LazyLoader)> {
+    vec![
+        family!(Vector2(f32, f64) => f32 => f64, f64 => f32),
+        family!(Vector3(f32, f64) => f32 => f64, f64 => f32),
+        family!(Isometry2(f32, f64) => f32 => f64, f64 => f32),
+        family!(Isometry3(f32, f64) => f32 => f64, f64 => f32),
+    ]
 }
@@ -10,11 +10,20 @@ use parry3d::{
 
 use crate::{
     from_table::Sequence,
-    hv::{LuaUserDataTypeExt, LuaUserDataTypeTypeExt},
+    hv::{
+        lazy::{lazy_table, userdata_type, LazyLoader},
+        LuaUserDataTypeExt, LuaUserDataTypeTypeExt,
+    },
     AnyUserData, Error, ExternalResult, Lua, Result, Table, ToLua, UserData, UserDataFields,
     UserDataMethods, Value,
 };
 
+#[cfg(feature = "hv-ecs")]
+mod collision;
+
+#[cfg(feature = "hv-ecs")]
+pub use collision::{CollisionFilter, CollisionSystem, ContactPair};
+
 trait LuaShapeTypeExt<T> {
     fn mark_shape(self) -> Self
     where
@@ -330,30 +339,39 @@ impl<'lua> ToLua<'lua> for Module {
 }
 
 pub fn table(lua: &Lua) -> Result<Table> {
-    use Module as LF;
-
-    #[rustfmt::skip]
-    let shape = vec![
-        ("Ball", lua.create_userdata_type::<Ball>()?),
-        ("Capsule", lua.create_userdata_type::<Capsule>()?),
-        ("Compound", lua.create_userdata_type::<Compound>()?),
-        ("ConvexPolyhedron", lua.create_userdata_type::<ConvexPolyhedron>()?),
-        ("Cuboid", lua.create_userdata_type::<Cuboid>()?),
-        ("HalfSpace", lua.create_userdata_type::<HalfSpace>()?),
-        ("SharedShape", lua.create_userdata_type::<SharedShape>()?),
-    ];
-
-    #[rustfmt::skip]
-    let query = vec![
-        ("contact", lua.create_function(LF::query_contact)?),
-        ("distance", lua.create_function(LF::query_distance)?),
-        ("intersection_test", lua.create_function(LF::query_intersection_test)?),
-    ];
-
-    let module = vec![
-        ("shape", lua.create_table_from(shape)?),
-        ("query", lua.create_table_from(query)?),
-    ];
-
-    lua.create_table_from(module)
+    fn shape<'lua>(lua: &'lua Lua, path: &str) -> Result<Value<'lua>> {
+        #[rustfmt::skip]
+        let shape = vec![
+            ("Ball", userdata_type::<Ball> as LazyLoader),
+            ("Capsule", userdata_type::<Capsule>),
+            ("Compound", userdata_type::<Compound>),
+            ("ConvexPolyhedron", userdata_type::<ConvexPolyhedron>),
+            ("Cuboid", userdata_type::<Cuboid>),
+            ("HalfSpace", userdata_type::<HalfSpace>),
+            ("SharedShape", userdata_type::<SharedShape>),
+        ];
+
+        lazy_table(lua, path, shape).map(Value::Table)
+    }
+
+    fn query<'lua>(lua: &'lua Lua, _: &str) -> Result<Value<'lua>> {
+        use Module as LF;
+
+        #[rustfmt::skip]
+        let query = vec![
+            ("contact", lua.create_function(LF::query_contact)?),
+            ("distance", lua.create_function(LF::query_distance)?),
+            ("intersection_test", lua.create_function(LF::query_intersection_test)?),
+        ];
+
+        lua.create_table_from(query).map(Value::Table)
+    }
+
+    #[allow(unused_mut)]
+    let mut module = vec![("shape", shape as LazyLoader), ("query", query)];
+
+    #[cfg(feature = "hv-ecs")]
+    module.push(("collision", collision::table));
+
+    lazy_table(lua, "parry3d", module)
 }
@@ -0,0 +1,274 @@
+use hv_alchemy::Type;
+use hv_ecs as ecs;
+use hv_math::Isometry3;
+use parry3d::{
+    bounding_volume::{BoundingVolume, AABB},
+    math::Real,
+    query::Contact,
+    shape::{Ball, Capsule, Compound, ConvexPolyhedron, Cuboid, HalfSpace, Shape, SharedShape},
+};
+
+use crate::{
+    hv::{
+        ecs::borrow_world,
+        lazy::{lazy_table, userdata_type, LazyLoader},
+        LuaUserDataTypeExt, LuaUserDataTypeTypeExt,
+    },
+    AnyUserData, Function, Lua, Result, UserData, UserDataFields, UserDataMethods, Value,
+};
+
+/// Collision layers and flags for an entity taking part in collision detection. Entities without
+/// a `CollisionFilter` belong to every layer, collide with every layer, and aren't sensors.
+///
+/// Two entities are tested for contact only if each one's `filter` shares a layer with the other's
+/// `memberships`. Contacts involving a sensor are still reported, but flagged as sensor contacts so
+/// that they can be treated as triggers rather than physical contacts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollisionFilter {
+    pub memberships: u32,
+    pub filter: u32,
+    pub sensor: bool,
+}
+
+impl Default for CollisionFilter {
+    fn default() -> Self {
+        Self {
+            memberships: u32::MAX,
+            filter: u32::MAX,
+            sensor: false,
+        }
+    }
+}
+
+impl CollisionFilter {
+    pub fn interacts_with(&self, other: &Self) -> bool {
+        self.memberships & other.filter != 0 && other.memberships & self.filter != 0
+    }
+}
+
+impl UserData for CollisionFilter {
+    fn on_metatable_init(table: Type<Self>) {
+        table.add_clone().add_copy().mark_component();
+    }
+
+    fn on_type_metatable_init(table: Type<Type<Self>>) {
+        table.mark_component_type();
+    }
+
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("memberships", |_, this| Ok(this.memberships));
+        fields.add_field_method_set("memberships", |_, this, memberships| {
+            this.memberships = memberships;
+            Ok(())
+        });
+        fields.add_field_method_get("filter", |_, this| Ok(this.filter));
+        fields.add_field_method_set("filter", |_, this, filter| {
+            this.filter = filter;
+            Ok(())
+        });
+        fields.add_field_method_get("sensor", |_, this| Ok(this.sensor));
+        fields.add_field_method_set("sensor", |_, this, sensor| {
+            this.sensor = sensor;
+            Ok(())
+        });
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M) {
+        methods.add_function(
+            "new",
+            |_, (memberships, filter, sensor): (Option<u32>, Option<u32>, Option<bool>)| {
+                let default = Self::default();
+                Ok(Self {
+                    memberships: memberships.unwrap_or(default.memberships),
+                    filter: filter.unwrap_or(default.filter),
+                    sensor: sensor.unwrap_or(default.sensor),
+                })
+            },
+        );
+    }
+}
+
+/// A pair of entities found to be in contact.
+#[derive(Debug, Clone, Copy)]
+pub struct ContactPair {
+    pub entity1: ecs::Entity,
+    pub entity2: ecs::Entity,
+    pub contact: Contact,
+    /// Whether either entity is a sensor.
+    pub sensor: bool,
+}
+
+/// A borrowed shape component of any of the shape types which can be used for collision detection.
+enum ShapeRef<'a> {
+    Shared(ecs::Ref<'a, SharedShape>),
+    Ball(ecs::Ref<'a, Ball>),
+    Capsule(ecs::Ref<'a, Capsule>),
+    Compound(ecs::Ref<'a, Compound>),
+    ConvexPolyhedron(ecs::Ref<'a, ConvexPolyhedron>),
+    Cuboid(ecs::Ref<'a, Cuboid>),
+    HalfSpace(ecs::Ref<'a, HalfSpace>),
+}
+
+impl<'a> ShapeRef<'a> {
+    fn of(entity: &ecs::EntityRef<'a>) -> Option<Self> {
+        entity
+            .get::<SharedShape>()
+            .map(Self::Shared)
+            .or_else(|| entity.get::<Ball>().map(Self::Ball))
+            .or_else(|| entity.get::<Capsule>().map(Self::Capsule))
+            .or_else(|| entity.get::<Compound>().map(Self::Compound))
+            .or_else(|| entity.get::<ConvexPolyhedron>().map(Self::ConvexPolyhedron))
+            .or_else(|| entity.get::<Cuboid>().map(Self::Cuboid))
+            .or_else(|| entity.get::<HalfSpace>().map(Self::HalfSpace))
+    }
+
+    fn shape(&self) -> &dyn Shape {
+        match self {
+            Self::Shared(shape) => &***shape,
+            Self::Ball(shape) => &**shape,
+            Self::Capsule(shape) => &**shape,
+            Self::Compound(shape) => &**shape,
+            Self::ConvexPolyhedron(shape) => &**shape,
+            Self::Cuboid(shape) => &**shape,
+            Self::HalfSpace(shape) => &**shape,
+        }
+    }
+}
+
+struct Collider<'a> {
+    entity: ecs::Entity,
+    position: Isometry3<Real>,
+    shape: ShapeRef<'a>,
+    filter: CollisionFilter,
+    aabb: AABB,
+}
+
+/// Finds every pair of entities with an `Isometry3<f32>` position and a shape component which are
+/// in contact.
+///
+/// A sweep-and-prune broad phase over the shapes' bounding boxes finds candidate pairs, which are
+/// then filtered by their [`CollisionFilter`]s and tested with [`parry3d::query::contact`]. Pairs
+/// of shapes which parry3d can't test against each other are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct CollisionSystem {
+    /// Shapes closer than this distance are reported as being in contact.
+    pub prediction: Real,
+    contacts: Vec<ContactPair>,
+}
+
+impl CollisionSystem {
+    pub fn new(prediction: Real) -> Self {
+        Self {
+            prediction,
+            contacts: Vec::new(),
+        }
+    }
+
+    /// Detect all contacts in a world, returning them along with the pairs of entities involved.
+    pub fn detect(&mut self, world: &ecs::World) -> &[ContactPair] {
+        self.contacts.clear();
+
+        let mut colliders = Vec::new();
+        for entity in world.iter() {
+            let position = match entity.get::<Isometry3<Real>>() {
+                Some(position) => *position,
+                None => continue,
+            };
+            let shape = match ShapeRef::of(&entity) {
+                Some(shape) => shape,
+                None => continue,
+            };
+            let filter = entity
+                .get::<CollisionFilter>()
+                .map(|filter| *filter)
+                .unwrap_or_default();
+            let aabb = shape
+                .shape()
+                .compute_aabb(&position)
+                .loosened(self.prediction);
+
+            colliders.push(Collider {
+                entity: entity.entity(),
+                position,
+                shape,
+                filter,
+                aabb,
+            });
+        }
+
+        colliders.sort_by(|a, b| a.aabb.mins.x.total_cmp(&b.aabb.mins.x));
+
+        for (i, a) in colliders.iter().enumerate() {
+            for b in &colliders[i + 1..] {
+                if b.aabb.mins.x > a.aabb.maxs.x {
+                    break;
+                }
+
+                if !a.aabb.intersects(&b.aabb) || !a.filter.interacts_with(&b.filter) {
+                    continue;
+                }
+
+                let contact = parry3d::query::contact(
+                    &a.position,
+                    a.shape.shape(),
+                    &b.position,
+                    b.shape.shape(),
+                    self.prediction,
+                );
+
+                if let Ok(Some(contact)) = contact {
+                    self.contacts.push(ContactPair {
+                        entity1: a.entity,
+                        entity2: b.entity,
+                        contact,
+                        sensor: a.filter.sensor || b.filter.sensor,
+                    });
+                }
+            }
+        }
+
+        &self.contacts
+    }
+
+    /// The contacts found by the last call to [`CollisionSystem::detect`].
+    pub fn contacts(&self) -> &[ContactPair] {
+        &self.contacts
+    }
+}
+
+impl UserData for CollisionSystem {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("prediction", |_, this| Ok(this.prediction));
+        fields.add_field_method_set("prediction", |_, this, prediction| {
+            this.prediction = prediction;
+            Ok(())
+        });
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Calls `f(entity1, entity2, contact, sensor)` for every contact found.
+        methods.add_method_mut("detect", |_, this, (world, f): (AnyUserData, Function)| {
+            let contacts = this.detect(&borrow_world(&world)?).to_vec();
+            for pair in contacts {
+                f.call::<_, ()>((pair.entity1, pair.entity2, pair.contact, pair.sensor))?;
+            }
+            Ok(())
+        });
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M) {
+        methods.add_function("new", |_, prediction: Option<Real>| {
+            Ok(Self::new(prediction.unwrap_or(0.)))
+        });
+    }
+}
+
+pub(super) fn table<'lua>(lua: &'lua Lua, path: &str) -> Result<Value<'lua>> {
+    #[rustfmt::skip]
+    let collision = vec![
+        ("CollisionFilter", userdata_type::<CollisionFilter> as LazyLoader),
+        ("CollisionSystem", userdata_type::<CollisionSystem>),
+    ];
+
+    lazy_table(lua, path, collision).map(Value::Table)
+}
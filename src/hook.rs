@@ -2,11 +2,21 @@ use std::cell::UnsafeCell;
 use std::ffi::CStr;
 use std::ops::{BitOr, BitOrAssign};
 use std::os::raw::{c_char, c_int};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use crate::error::Error;
 use crate::ffi::{self, lua_Debug, lua_State};
 use crate::lua::Lua;
 use crate::util::callback_error;
 
+#[cfg(any(feature = "lua54", feature = "lua53", doc))]
+use crate::util::get_main_state;
+
+// Number of VM instructions between two interrupt checks, unless the hook callback has a smaller
+// instruction count.
+pub(crate) const INTERRUPT_CHECK_INTERVAL: u32 = 1000;
+
 /// Contains information about currently executing Lua code.
 ///
 /// The `Debug` structure is provided as a parameter to the hook function set with
@@ -283,11 +293,51 @@ impl BitOrAssign for HookTriggers {
     }
 }
 
+/// A handle to interrupt the Lua code running in a [`Lua`] state, obtained from
+/// [`Lua::interrupt_handle`].
+///
+/// The handle can be cloned and shared with other threads. See [`Lua::interrupt_handle`] for
+/// details on how interrupts are delivered.
+///
+/// [`Lua`]: crate::Lua
+/// [`Lua::interrupt_handle`]: crate::Lua::interrupt_handle
+#[derive(Clone, Debug)]
+pub struct InterruptHandle(pub(crate) Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Requests the running Lua code to stop. Lua code run from now on fails as well, until the
+    /// handle is [`reset`].
+    ///
+    /// [`reset`]: #method.reset
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if an interrupt was requested and the handle has not been reset since.
+    pub fn is_interrupted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears a pending interrupt, allowing Lua code to run again.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
 pub(crate) unsafe extern "C" fn hook_proc(state: *mut lua_State, ar: *mut lua_Debug) {
     callback_error(state, |_| {
         let lua = mlua_expect!(Lua::make_from_ptr(state), "cannot make Lua instance");
+        if lua.is_interrupted() {
+            return Err(Error::Interrupted);
+        }
+
+        // Count events may also come from interrupt checks only
+        let hook_cb = match lua.hook_callback() {
+            Some(_) if (*ar).event == ffi::LUA_HOOKCOUNT && !lua.hook_count_due() => return Ok(()),
+            Some(hook_cb) => hook_cb,
+            None => return Ok(()),
+        };
         let debug = Debug::new(&lua, ar);
-        let hook_cb = mlua_expect!(lua.hook_callback(), "no hook callback set in hook_proc");
 
         #[allow(clippy::match_wild_err_arm)]
         match hook_cb.try_borrow_mut() {
@@ -306,3 +356,51 @@ unsafe fn ptr_to_str<'a>(input: *const c_char) -> Option<&'a [u8]> {
         Some(CStr::from_ptr(input).to_bytes())
     }
 }
+
+// Registry key of the (weak-keyed) table holding the threads that opted in to preemption.
+#[cfg(any(feature = "lua54", feature = "lua53", doc))]
+pub(crate) static PREEMPTIBLE_REGISTRY_KEY: u8 = 0;
+
+#[cfg(any(feature = "lua54", feature = "lua53", doc))]
+pub(crate) unsafe extern "C" fn preemption_hook_proc(state: *mut lua_State, ar: *mut lua_Debug) {
+    if (*ar).event != ffi::LUA_HOOKCOUNT || ffi::lua_checkstack(state, 2) == 0 {
+        return;
+    }
+
+    let preemptible_key = &PREEMPTIBLE_REGISTRY_KEY as *const u8 as *const std::os::raw::c_void;
+    let preemptible =
+        if ffi::lua_rawgetp(state, ffi::LUA_REGISTRYINDEX, preemptible_key) == ffi::LUA_TTABLE {
+            ffi::lua_pushthread(state);
+            ffi::lua_rawget(state, -2) != ffi::LUA_TNIL
+        } else {
+            ffi::lua_pushnil(state);
+            false
+        };
+    ffi::lua_pop(state, 2);
+
+    // Interrupts must still be delivered to preemptible threads
+    callback_error(state, |_| {
+        let lua = mlua_expect!(Lua::make_from_ptr(state), "cannot make Lua instance");
+        if lua.is_interrupted() {
+            return Err(Error::Interrupted);
+        }
+        Ok(())
+    });
+
+    if !preemptible {
+        // Coroutines inherit hooks from the thread that created them; don't preempt them and
+        // give them the hook of the main thread instead.
+        match get_main_state(state) {
+            Some(main_state) => ffi::lua_sethook(
+                state,
+                ffi::lua_gethook(main_state),
+                ffi::lua_gethookmask(main_state),
+                ffi::lua_gethookcount(main_state),
+            ),
+            None => ffi::lua_sethook(state, None, 0, 0),
+        }
+    } else if ffi::lua_isyieldable(state) != 0 {
+        // A hook yielding from a count event must return right after `lua_yield`.
+        ffi::lua_yield(state, 0);
+    }
+}
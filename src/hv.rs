@@ -1,8 +1,11 @@
 use hv_alchemy::Type;
 
 use crate::{
-    hv::ecs::{ComponentType, DynamicBundleProxy},
-    Lua, RegistryKey, Result, Table, ToLua, UserData,
+    hv::{
+        ecs::{ComponentType, DynamicBundleProxy},
+        lazy::{lazy_table, userdata_type, LazyLoader},
+    },
+    Lua, RegistryKey, Result, Table, UserData, Value,
 };
 
 #[cfg(feature = "hv-ecs")]
@@ -10,8 +13,14 @@ pub mod ecs;
 pub mod math;
 
 pub mod alchemy;
+mod family;
+pub(crate) mod lazy;
+pub(crate) mod registry;
 mod sync;
 
+pub use family::{type_family_of, TypeFamily};
+pub use lazy::force_all;
+
 pub trait LuaUserDataTypeExt<T> {
     /// Mark `Send + Sync` ([`Component`](hv_ecs::Component) equivalent.)
     fn mark_component(self) -> Self
@@ -29,6 +38,12 @@ pub trait LuaUserDataTypeTypeExt<T> {
     fn mark_component_type(self) -> Self
     where
         T: hv_ecs::Component;
+
+    /// Mark [`Type<T>: FlatComponentType`](ecs::FlatComponentType) (allows filling column batches
+    /// with plain numbers)
+    fn mark_flat_component_type(self) -> Self
+    where
+        T: hv_ecs::Component + ecs::FromNumbers;
 }
 
 impl<T: 'static + UserData> LuaUserDataTypeExt<T> for Type<T> {
@@ -54,16 +69,38 @@ impl<T: 'static + UserData> LuaUserDataTypeTypeExt<T> for Type<Type<T>> {
     {
         self.add::<dyn ComponentType>()
     }
+
+    fn mark_flat_component_type(self) -> Self
+    where
+        T: hv_ecs::Component + ecs::FromNumbers,
+    {
+        self.add::<dyn ecs::FlatComponentType>()
+    }
 }
 
+/// The Lua types of `hv`, as a table whose submodules are only built when first indexed.
 pub fn types(lua: &Lua) -> Result<Table> {
-    use crate::Value::*;
-    lua.create_table_from(vec![
-        ("ecs", Table(self::ecs::types(lua)?)),
-        ("math", self::math::Module.to_lua(lua)?),
-        (
-            "RegistryKey",
-            UserData(lua.create_userdata_type::<RegistryKey>()?),
-        ),
-    ])
+    fn ecs<'lua>(lua: &'lua Lua, _: &str) -> Result<Value<'lua>> {
+        self::ecs::types(lua).map(Value::Table)
+    }
+
+    fn math<'lua>(lua: &'lua Lua, _: &str) -> Result<Value<'lua>> {
+        self::math::table(lua).map(Value::Table)
+    }
+
+    fn force_all<'lua>(lua: &'lua Lua, _: &str) -> Result<Value<'lua>> {
+        lua.create_function(|_, table: Table| self::force_all(&table))
+            .map(Value::Function)
+    }
+
+    lazy_table(
+        lua,
+        "hv",
+        vec![
+            ("ecs", ecs as LazyLoader),
+            ("math", math),
+            ("force_all", force_all),
+            ("RegistryKey", userdata_type::<RegistryKey>),
+        ],
+    )
 }
@@ -1,8 +1,10 @@
+use std::any::TypeId;
+
 use hv_alchemy::{Type, TypeTable};
 
 use crate::{
-    types::MaybeSend, AnyUserData, Error, FromLua, LightUserData, Lua, Result, ToLua, UserData,
-    UserDataFields, UserDataMethods, Value,
+    hv::type_family_of, types::MaybeSend, AnyUserData, Error, FromLua, LightUserData, Lua, Result,
+    ToLua, UserData, UserDataFields, UserDataMethods, Value,
 };
 
 impl<'lua> ToLua<'lua> for &'static TypeTable {
@@ -45,6 +47,13 @@ impl<T: 'static + UserData + MaybeSend> UserData for Type<T> {
     }
 
     fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_function_get("family", |lua, _| {
+            Ok(type_family_of(lua, TypeId::of::<T>()).map(|(family, _)| family))
+        });
+        fields.add_field_function_get("parameter", |lua, _| {
+            Ok(type_family_of(lua, TypeId::of::<T>()).map(|(_, parameter)| parameter))
+        });
+
         T::add_type_fields(fields);
     }
 
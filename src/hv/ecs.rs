@@ -1,37 +1,58 @@
-use std::{any::TypeId, cell::Ref, mem::MaybeUninit};
+use std::{
+    any::{Any, TypeId},
+    cell::{Ref, RefCell},
+    mem::MaybeUninit,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError, TryLockError,
+    },
+};
 
 use hv_alchemy::{AlchemicalAny, Type};
 use hv_ecs as ecs;
-use hv_elastic::{external::ecs::StretchedBatchWriter, Elastic};
+use hv_elastic::{external::ecs::StretchedBatchWriter, Elastic, StretchedMut, StretchedRef};
 
 use crate::{
+    hv::lazy::{lazy_table, userdata_type, LazyLoader},
     userdata::{UserDataFieldsProxy, UserDataMethodsProxy},
-    AnyUserData, Error, ExternalResult, FromLua, Function, LightUserData, Lua, MultiValue, Result,
-    Table, ToLua, ToLuaMulti, UserData, UserDataFields, UserDataMethods, Value,
+    AnyUserData, Error, ExternalResult, FromLua, Function, LightUserData, Lua, MetaMethod,
+    MultiValue, RegistryKey, Result, Table, ToLua, ToLuaMulti, TryCloneToUserDataExt, UserData,
+    UserDataFields, UserDataMethods, Value, Variadic,
+};
+
+mod batch;
+mod entity;
+mod events;
+mod lua_component;
+mod resources;
+#[cfg(feature = "serialize")]
+mod scene;
+mod schedule;
+mod script;
+mod shared_world;
+mod type_objects;
+
+pub use batch::{
+    column_batch_from_columns, column_batch_from_rows, FlatComponentType, FromNumbers,
 };
+pub use entity::{set_typed_entities, LuaEntity};
+pub(crate) use events::SharedWorldEventLogs;
+pub use events::{flush_events, unsubscribe_all};
+pub use lua_component::{LuaComponent, LuaComponentType, MAX_LUA_COMPONENT_TYPES};
+pub use resources::Resources;
+#[cfg(feature = "serialize")]
+pub use scene::{EntityMap, MapEntities, SceneRegistry, SerializeWorld};
+pub use schedule::{Schedule, SystemReport, DEFAULT_STAGE};
+pub use script::{run_scripts, Script};
+pub use shared_world::SharedWorld;
+pub(crate) use shared_world::{borrow_world, borrow_world_mut, WorldRef};
+pub use type_objects::component_type_object;
 
 impl<'lua> FromLua<'lua> for ecs::EntityBuilder {
     fn from_lua(lua_value: Value<'lua>, _lua: &'lua Lua) -> Result<Self> {
         let mut builder = ecs::EntityBuilder::new();
         match lua_value {
-            Value::Table(table) => {
-                builder.clear();
-                for component in table.sequence_values::<AnyUserData>() {
-                    let component = component?;
-                    if let Ok(bundle) = component
-                        .clone()
-                        .dyn_clone_or_take::<dyn DynamicBundleProxy>()
-                    {
-                        builder.add_bundle(bundle);
-                    } else if let Ok(single) = LuaSingleBundle::from_lua_userdata(&component) {
-                        builder.add_bundle(single);
-                    } else {
-                        return Err(Error::external(
-                            "expected a table of bundles and components",
-                        ));
-                    }
-                }
-            }
+            Value::Table(table) => add_table_to_builder(&mut builder, table)?,
             Value::UserData(ud) => {
                 builder.add_bundle(ud.dyn_clone_or_take::<dyn DynamicBundleProxy>()?);
             }
@@ -46,6 +67,27 @@ impl<'lua> FromLua<'lua> for ecs::EntityBuilder {
     }
 }
 
+/// Add every bundle or component in a sequence table to an entity builder.
+fn add_table_to_builder(builder: &mut ecs::EntityBuilder, table: Table) -> Result<()> {
+    for component in table.sequence_values::<AnyUserData>() {
+        let component = component?;
+        if let Ok(bundle) = component
+            .clone()
+            .dyn_clone_or_take::<dyn DynamicBundleProxy>()
+        {
+            builder.add_bundle(bundle);
+        } else if let Ok(single) = LuaSingleBundle::from_lua_userdata(&component) {
+            builder.add_bundle(single);
+        } else {
+            return Err(Error::external(
+                "expected a table of bundles and components",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 impl UserData for ecs::ColumnBatchType {
     fn on_metatable_init(table: Type<Self>) {
         table.add_clone().add_send().add_sync();
@@ -89,7 +131,20 @@ impl UserData for ecs::ColumnBatchBuilder {
     }
 }
 
-impl UserData for ecs::ColumnBatch {}
+impl UserData for ecs::ColumnBatch {
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M)
+    where
+        Self: 'static,
+    {
+        methods.add_function("from_columns", |_, (types, columns): (Table, Table)| {
+            batch::column_batch_from_columns(types, columns)
+        });
+
+        methods.add_function("from_rows", |_, (types, rows): (Table, Table)| {
+            batch::column_batch_from_rows(types, rows)
+        });
+    }
+}
 
 impl<T: 'static + UserData> UserData for Elastic<StretchedBatchWriter<T>> {
     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
@@ -274,16 +329,20 @@ impl Drop for LuaSingleBundle<'_> {
 
 impl<'lua> ToLua<'lua> for ecs::Entity {
     #[inline]
-    fn to_lua(self, _lua: &'lua Lua) -> Result<Value<'lua>> {
-        Ok(Value::LightUserData(LightUserData(
-            self.to_bits().get() as *mut _
-        )))
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        entity::entity_to_lua(lua, self)
     }
 }
 
 impl<'lua> FromLua<'lua> for ecs::Entity {
     #[inline]
     fn from_lua(lua_value: Value<'lua>, lua: &'lua Lua) -> Result<Self> {
+        if let Value::UserData(ud) = &lua_value {
+            if let Ok(entity) = ud.borrow::<LuaEntity>() {
+                return Ok(entity.0);
+            }
+        }
+
         LightUserData::from_lua(lua_value, lua).and_then(|lud| {
             ecs::Entity::from_bits(lud.0 as u64)
                 .ok_or_else(|| Error::external("invalid entity ID (zero)"))
@@ -296,6 +355,10 @@ pub trait ComponentType: Send + Sync {
 
     fn read(&self) -> ecs::DynamicQuery;
     fn write(&self) -> ecs::DynamicQuery;
+    fn read_optional(&self) -> ecs::DynamicQuery;
+    fn write_optional(&self) -> ecs::DynamicQuery;
+    fn with(&self) -> ecs::DynamicQuery;
+    fn without(&self) -> ecs::DynamicQuery;
 
     fn column_batch_type_add(&self, column_batch_type: &mut ecs::ColumnBatchType);
 
@@ -309,11 +372,102 @@ pub trait ComponentType: Send + Sync {
         column_batch: &'a mut ecs::ColumnBatchBuilder,
     ) -> Result<(Box<dyn Send + 'a>, AnyUserData<'lua>)>;
 
+    /// Fill this type's column in a batch with components taken or cloned out of userdata.
+    fn column_batch_builder_fill<'lua>(
+        &self,
+        column_batch_builder: &mut ecs::ColumnBatchBuilder,
+        components: &[AnyUserData<'lua>],
+    ) -> Result<()>;
+
     fn dynamic_item_take<'lua>(
         &self,
         lua: &'lua Lua,
         dynamic_item: &mut ecs::DynamicItem,
     ) -> Result<Option<AnyUserData<'lua>>>;
+
+    /// Take this type's component out of a query item without converting it to userdata, so that
+    /// it can be borrowed any number of times with [`ComponentType::dynamic_component_get`].
+    fn dynamic_item_take_component(
+        &self,
+        dynamic_item: &mut ecs::DynamicItem,
+    ) -> Option<Box<dyn Any + Send>>;
+
+    /// Convert a component taken with [`ComponentType::dynamic_item_take_component`] to userdata,
+    /// as [`ComponentType::dynamic_item_take`] does.
+    fn dynamic_component_to_userdata<'lua>(
+        &self,
+        lua: &'lua Lua,
+        component: Box<dyn Any + Send>,
+    ) -> Result<AnyUserData<'lua>>;
+
+    /// Borrow a component taken with [`ComponentType::dynamic_item_take_component`], returning an
+    /// elastic reference to it along with the loan which keeps it valid. The reference is revoked
+    /// when the loan is dropped.
+    fn dynamic_component_get<'lua>(
+        &self,
+        lua: &'lua Lua,
+        component: &(dyn Any + Send),
+    ) -> Result<(Box<dyn Send>, AnyUserData<'lua>)>;
+
+    /// Mutably borrow a component taken with [`ComponentType::dynamic_item_take_component`]. See
+    /// [`ComponentType::dynamic_component_get`].
+    ///
+    /// # Safety
+    ///
+    /// The query the component came from must have write access to it (through
+    /// [`ComponentType::write`] or [`ComponentType::write_optional`]), and the loan must be dropped
+    /// before the query's borrow of the world is released.
+    unsafe fn dynamic_component_get_mut<'lua>(
+        &self,
+        lua: &'lua Lua,
+        component: &mut (dyn Any + Send),
+    ) -> Result<(Box<dyn Send>, AnyUserData<'lua>)>;
+
+    /// Clone this type's component out of an entity. Fails if the component type isn't `Clone`.
+    fn world_get<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &ecs::World,
+        entity: ecs::Entity,
+    ) -> Result<AnyUserData<'lua>>;
+
+    /// Whether an entity has a component of this type. Fails if the entity doesn't exist.
+    fn world_has(&self, world: &ecs::World, entity: ecs::Entity) -> Result<bool>;
+
+    /// Borrow this type's component from an entity for the duration of `f`, passing `f` an elastic
+    /// reference to it which is revoked once `f` returns.
+    fn world_borrow<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &ecs::World,
+        entity: ecs::Entity,
+        f: &mut dyn FnMut(AnyUserData<'lua>) -> Result<MultiValue<'lua>>,
+    ) -> Result<MultiValue<'lua>>;
+
+    /// Mutably borrow this type's component from an entity for the duration of `f`. See
+    /// [`ComponentType::world_borrow`].
+    fn world_borrow_mut<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &ecs::World,
+        entity: ecs::Entity,
+        f: &mut dyn FnMut(AnyUserData<'lua>) -> Result<MultiValue<'lua>>,
+    ) -> Result<MultiValue<'lua>>;
+
+    /// Remove this type's component from an entity, returning it as userdata. Fails if the
+    /// entity doesn't exist or has no such component.
+    fn world_remove_one<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &mut ecs::World,
+        entity: ecs::Entity,
+    ) -> Result<AnyUserData<'lua>>;
+
+    /// Remove this type's component from an entity and drop it, returning whether it was there.
+    fn world_discard_one(&self, world: &mut ecs::World, entity: ecs::Entity) -> bool;
+
+    /// Get an owned copy of this component type object, to be used outside of Lua.
+    fn boxed(&self) -> Box<dyn ComponentType>;
 }
 
 impl<T: ecs::Component + UserData> ComponentType for Type<T> {
@@ -329,6 +483,22 @@ impl<T: ecs::Component + UserData> ComponentType for Type<T> {
         ecs::DynamicQuery::lift::<&mut T>()
     }
 
+    fn read_optional(&self) -> ecs::DynamicQuery {
+        ecs::DynamicQuery::lift::<Option<&T>>()
+    }
+
+    fn write_optional(&self) -> ecs::DynamicQuery {
+        ecs::DynamicQuery::lift::<Option<&mut T>>()
+    }
+
+    fn with(&self) -> ecs::DynamicQuery {
+        ecs::DynamicQuery::lift::<ecs::With<T, ()>>()
+    }
+
+    fn without(&self) -> ecs::DynamicQuery {
+        ecs::DynamicQuery::lift::<ecs::Without<T, ()>>()
+    }
+
     fn column_batch_type_add(&self, column_batch_type: &mut ecs::ColumnBatchType) {
         column_batch_type.add::<T>();
     }
@@ -347,6 +517,23 @@ impl<T: ecs::Component + UserData> ComponentType for Type<T> {
         Ok((Box::new(guard), lua.create_userdata(elastic)?))
     }
 
+    fn column_batch_builder_fill<'lua>(
+        &self,
+        column_batch_builder: &mut ecs::ColumnBatchBuilder,
+        components: &[AnyUserData<'lua>],
+    ) -> Result<()> {
+        let mut writer = column_batch_builder
+            .writer::<T>()
+            .ok_or_else(|| Error::external("not in ColumnBatch"))?;
+        for component in components {
+            writer
+                .push(component.clone_or_take::<T>()?)
+                .ok()
+                .ok_or_else(|| Error::external("BatchWriter is full!"))?;
+        }
+        Ok(())
+    }
+
     fn dynamic_item_take<'lua>(
         &self,
         lua: &'lua Lua,
@@ -357,6 +544,125 @@ impl<T: ecs::Component + UserData> ComponentType for Type<T> {
             .map(|c| lua.create_userdata(c))
             .transpose()
     }
+
+    fn dynamic_item_take_component(
+        &self,
+        dynamic_item: &mut ecs::DynamicItem,
+    ) -> Option<Box<dyn Any + Send>> {
+        dynamic_item
+            .take::<T>()
+            .map(|c| Box::new(c) as Box<dyn Any + Send>)
+    }
+
+    fn dynamic_component_to_userdata<'lua>(
+        &self,
+        lua: &'lua Lua,
+        component: Box<dyn Any + Send>,
+    ) -> Result<AnyUserData<'lua>> {
+        let component = component
+            .downcast::<ecs::DynamicComponent<T>>()
+            .map_err(|_| Error::UserDataTypeMismatch)?;
+        lua.create_userdata(*component)
+    }
+
+    fn dynamic_component_get<'lua>(
+        &self,
+        lua: &'lua Lua,
+        component: &(dyn Any + Send),
+    ) -> Result<(Box<dyn Send>, AnyUserData<'lua>)> {
+        let component = component
+            .downcast_ref::<ecs::DynamicComponent<T>>()
+            .ok_or(Error::UserDataTypeMismatch)?;
+        let elastic = <Elastic<StretchedRef<T>>>::new();
+        // safety: the component refers into the world's storage rather than to the box holding it,
+        // and the query keeps that storage borrowed until its items are released, which drops the
+        // guard and so revokes the loan.
+        let guard = elastic.loan(unsafe { &*(&**component as *const T) });
+        Ok((Box::new(guard), lua.create_userdata(elastic)?))
+    }
+
+    unsafe fn dynamic_component_get_mut<'lua>(
+        &self,
+        lua: &'lua Lua,
+        component: &mut (dyn Any + Send),
+    ) -> Result<(Box<dyn Send>, AnyUserData<'lua>)> {
+        let component = component
+            .downcast_mut::<ecs::DynamicComponent<T>>()
+            .ok_or(Error::UserDataTypeMismatch)?;
+        let elastic = <Elastic<StretchedMut<T>>>::new();
+        // safety: see `dynamic_component_get`; the caller guarantees the query has write access.
+        let guard = elastic.loan(&mut *(&mut **component as *mut T));
+        Ok((Box::new(guard), lua.create_userdata(elastic)?))
+    }
+
+    fn world_get<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &ecs::World,
+        entity: ecs::Entity,
+    ) -> Result<AnyUserData<'lua>> {
+        world
+            .get::<T>(entity)
+            .to_lua_err()?
+            .try_clone_to_user_data(lua)
+            .map_err(|_| {
+                Error::external(format!(
+                    "component type `{}` is not registered as Clone!",
+                    std::any::type_name::<T>()
+                ))
+            })
+    }
+
+    fn world_has(&self, world: &ecs::World, entity: ecs::Entity) -> Result<bool> {
+        Ok(world.entity(entity).to_lua_err()?.has::<T>())
+    }
+
+    fn world_borrow<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &ecs::World,
+        entity: ecs::Entity,
+        f: &mut dyn FnMut(AnyUserData<'lua>) -> Result<MultiValue<'lua>>,
+    ) -> Result<MultiValue<'lua>> {
+        let component = world.get::<T>(entity).to_lua_err()?;
+        let elastic = <Elastic<StretchedRef<T>>>::new();
+        let guard = elastic.loan(&*component);
+        let out = f(lua.create_userdata(elastic)?);
+        drop(guard);
+        out
+    }
+
+    fn world_borrow_mut<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &ecs::World,
+        entity: ecs::Entity,
+        f: &mut dyn FnMut(AnyUserData<'lua>) -> Result<MultiValue<'lua>>,
+    ) -> Result<MultiValue<'lua>> {
+        let mut component = world.get_mut::<T>(entity).to_lua_err()?;
+        let elastic = <Elastic<StretchedMut<T>>>::new();
+        let guard = elastic.loan(&mut *component);
+        let out = f(lua.create_userdata(elastic)?);
+        drop(guard);
+        out
+    }
+
+    fn world_remove_one<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &mut ecs::World,
+        entity: ecs::Entity,
+    ) -> Result<AnyUserData<'lua>> {
+        lua.create_userdata(world.remove_one::<T>(entity).to_lua_err()?)
+    }
+
+    fn world_discard_one(&self, world: &mut ecs::World, entity: ecs::Entity) -> bool {
+        world.remove_one::<T>(entity).is_ok()
+    }
+
+    fn boxed(&self) -> Box<dyn ComponentType> {
+        Box::new(Type::<T>::of())
+    }
 }
 
 impl UserData for ecs::DynamicQuery {
@@ -386,6 +692,185 @@ impl UserData for ecs::DynamicQuery {
     }
 }
 
+/// A filter on the set of components an entity has, for what can't be expressed through a
+/// [`DynamicQuery`](ecs::DynamicQuery) built at runtime (or-combinations.)
+#[derive(Debug, Clone)]
+enum QueryFilter {
+    With(TypeId),
+    Without(TypeId),
+    Any(Vec<QueryFilter>),
+    All(Vec<QueryFilter>),
+}
+
+impl QueryFilter {
+    fn matches(&self, entity: &ecs::EntityRef) -> bool {
+        match self {
+            Self::With(type_id) => entity.component_types().any(|t| t == *type_id),
+            Self::Without(type_id) => entity.component_types().all(|t| t != *type_id),
+            Self::Any(filters) => filters.iter().any(|filter| filter.matches(entity)),
+            Self::All(filters) => filters.iter().all(|filter| filter.matches(entity)),
+        }
+    }
+}
+
+/// The Lua-side query type: a [`DynamicQuery`](ecs::DynamicQuery), plus any filters which have to
+/// be checked per entity because they can't be expressed as part of the dynamic query.
+///
+/// Queries are composed from Lua with `Query.new { ... }`, which requires all of its elements to
+/// match, out of `Query.read`/`Query.write` (required components), `Query.optional`/
+/// `Query.optional_mut` (optional components), `Query.with`/`Query.without` (filters which don't
+/// fetch anything) and `Query.any { ... }`, which matches if any of its elements do and only
+/// accepts filters.
+#[derive(Clone)]
+pub struct Query {
+    query: ecs::DynamicQuery,
+    filter: Option<QueryFilter>,
+    // What this query means as a filter, if it doesn't fetch any components.
+    as_filter: Option<QueryFilter>,
+    // The component types this query has write access to.
+    writes: Arc<[TypeId]>,
+}
+
+/// Bare dynamic queries don't record which of their components they have write access to, so
+/// items from them only allow immutable borrows.
+impl From<ecs::DynamicQuery> for Query {
+    fn from(query: ecs::DynamicQuery) -> Self {
+        Self {
+            query,
+            filter: None,
+            as_filter: None,
+            writes: Arc::new([]),
+        }
+    }
+}
+
+impl Query {
+    /// Accepts either a [`Query`] or a bare [`DynamicQuery`](ecs::DynamicQuery).
+    fn from_user_data(ud: &AnyUserData) -> Result<Self> {
+        match ud.borrow::<Self>() {
+            Ok(query) => Ok(query.clone()),
+            Err(_) => Ok(ud.borrow::<ecs::DynamicQuery>()?.clone().into()),
+        }
+    }
+
+    fn matches(&self, world: &ecs::World, entity: ecs::Entity) -> bool {
+        match &self.filter {
+            Some(filter) => world
+                .entity(entity)
+                .map_or(false, |entity| filter.matches(&entity)),
+            None => true,
+        }
+    }
+
+    /// Wrap an item produced by this query for use from Lua.
+    fn item(&self, item: ecs::DynamicItem) -> QueryItem {
+        QueryItem::new(item, self.writes.clone())
+    }
+
+    fn writing(query: ecs::DynamicQuery, ty: &dyn ComponentType) -> Self {
+        Self {
+            writes: Arc::new([ComponentType::type_id(ty)]),
+            ..Self::from(query)
+        }
+    }
+}
+
+impl UserData for Query {
+    fn on_metatable_init(table: Type<Self>) {
+        table.add_clone().add_send().add_sync();
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M) {
+        methods.add_function("new", move |_, table: Table| {
+            let mut elements = Vec::new();
+            let mut filters = Vec::new();
+            let mut as_filters = Some(Vec::new());
+            let mut writes = Vec::new();
+            for try_element in table.sequence_values::<AnyUserData>() {
+                let element = Query::from_user_data(&try_element?)?;
+                elements.push(element.query);
+                writes.extend_from_slice(&element.writes);
+                filters.extend(element.filter);
+                as_filters = as_filters.zip(element.as_filter).map(|(mut fs, f)| {
+                    fs.push(f);
+                    fs
+                });
+            }
+
+            let filter = match filters.len() {
+                0 => None,
+                1 => filters.pop(),
+                _ => Some(QueryFilter::All(filters)),
+            };
+
+            Ok(Query {
+                query: ecs::DynamicQuery::new(elements),
+                filter,
+                as_filter: as_filters.map(QueryFilter::All),
+                writes: writes.into(),
+            })
+        });
+
+        methods.add_function("read", move |_, ty: AnyUserData| {
+            Ok(Query::from(ty.dyn_borrow::<dyn ComponentType>()?.read()))
+        });
+
+        methods.add_function("write", move |_, ty: AnyUserData| {
+            let ty = ty.dyn_borrow::<dyn ComponentType>()?;
+            Ok(Query::writing(ty.write(), &*ty))
+        });
+
+        methods.add_function("optional", move |_, ty: AnyUserData| {
+            Ok(Query::from(
+                ty.dyn_borrow::<dyn ComponentType>()?.read_optional(),
+            ))
+        });
+
+        methods.add_function("optional_mut", move |_, ty: AnyUserData| {
+            let ty = ty.dyn_borrow::<dyn ComponentType>()?;
+            Ok(Query::writing(ty.write_optional(), &*ty))
+        });
+
+        methods.add_function("with", move |_, ty: AnyUserData| {
+            let ty = ty.dyn_borrow::<dyn ComponentType>()?;
+            Ok(Query {
+                query: ty.with(),
+                filter: None,
+                as_filter: Some(QueryFilter::With(ComponentType::type_id(&*ty))),
+                writes: Arc::new([]),
+            })
+        });
+
+        methods.add_function("without", move |_, ty: AnyUserData| {
+            let ty = ty.dyn_borrow::<dyn ComponentType>()?;
+            Ok(Query {
+                query: ty.without(),
+                filter: None,
+                as_filter: Some(QueryFilter::Without(ComponentType::type_id(&*ty))),
+                writes: Arc::new([]),
+            })
+        });
+
+        methods.add_function("any", move |_, table: Table| {
+            let mut filters = Vec::new();
+            for try_element in table.sequence_values::<AnyUserData>() {
+                let element = Query::from_user_data(&try_element?)?;
+                filters.push(element.as_filter.ok_or_else(|| {
+                    Error::external("`Query.any` only accepts filters (`with`, `without`, `any`)")
+                })?);
+            }
+
+            let filter = QueryFilter::Any(filters);
+            Ok(Query {
+                query: ecs::DynamicQuery::new(Vec::new()),
+                filter: Some(filter.clone()),
+                as_filter: Some(filter),
+                writes: Arc::new([]),
+            })
+        });
+    }
+}
+
 impl UserData for ecs::DynamicItem {
     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_method_mut("take", move |lua, this, ty: AnyUserData| {
@@ -395,6 +880,303 @@ impl UserData for ecs::DynamicItem {
     }
 }
 
+/// A query item as seen from Lua. Components borrowed from the item through `get`/`get_mut` are
+/// only valid until the query callback or loop iteration which produced the item is over; after
+/// that, the item is released and any further use of it or its borrowed components is an error.
+pub struct QueryItem(Arc<QueryItemShared>);
+
+struct QueryItemShared {
+    state: Mutex<QueryItemState>,
+    // Set when the item is released while its state is locked, for the holder of the lock to
+    // finish releasing it.
+    released: AtomicBool,
+}
+
+struct QueryItemState {
+    item: Option<ecs::DynamicItem>,
+    // Components taken out of the item to be borrowed, by component type.
+    components: Vec<(TypeId, Box<dyn Any + Send>)>,
+    loans: Vec<ItemLoan>,
+    // The component types the item's query has write access to.
+    writes: Arc<[TypeId]>,
+}
+
+struct ItemLoan {
+    type_id: TypeId,
+    mutable: bool,
+    component: RegistryKey,
+    _guard: Box<dyn Send>,
+}
+
+impl QueryItem {
+    fn new(item: ecs::DynamicItem, writes: Arc<[TypeId]>) -> Self {
+        Self(Arc::new(QueryItemShared {
+            state: Mutex::new(QueryItemState {
+                item: Some(item),
+                components: Vec::new(),
+                loans: Vec::new(),
+                writes,
+            }),
+            released: AtomicBool::new(false),
+        }))
+    }
+
+    /// Run `f` on the item's state, releasing the item afterwards if that was asked for while `f`
+    /// was running.
+    fn with_state<R>(&self, f: impl FnOnce(&mut QueryItemState) -> R) -> R {
+        let mut state = self.0.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let result = f(&mut state);
+        if self.0.released.load(Ordering::Acquire) {
+            state.release();
+        }
+        result
+    }
+
+    /// A handle which can be used to release the item once its query is over.
+    fn handle(&self) -> QueryItemHandle {
+        QueryItemHandle(self.0.clone())
+    }
+}
+
+impl QueryItemState {
+    fn item_mut(&mut self) -> Result<&mut ecs::DynamicItem> {
+        self.item
+            .as_mut()
+            .ok_or_else(|| Error::external("query item used outside of its query!"))
+    }
+
+    /// Revoke all borrowed components and invalidate the item. The loans are dropped before the
+    /// components they borrow from.
+    fn release(&mut self) {
+        self.loans.clear();
+        self.components.clear();
+        self.item = None;
+    }
+
+    /// Take the component of type `ty` out of the item, revoking any borrows of it.
+    fn take_component<'lua>(
+        &mut self,
+        lua: &'lua Lua,
+        ty: &AnyUserData<'lua>,
+    ) -> Result<Option<AnyUserData<'lua>>> {
+        let ty = ty.dyn_borrow::<dyn ComponentType>()?;
+        let type_id = ComponentType::type_id(&*ty);
+
+        self.item_mut()?;
+        match self.components.iter().position(|(id, _)| *id == type_id) {
+            Some(i) => {
+                self.loans.retain(|loan| loan.type_id != type_id);
+                let (_, component) = self.components.swap_remove(i);
+                ty.dynamic_component_to_userdata(lua, component).map(Some)
+            }
+            None => ty.dynamic_item_take(lua, self.item_mut()?),
+        }
+    }
+
+    fn borrow_component<'lua>(
+        &mut self,
+        lua: &'lua Lua,
+        ty: &AnyUserData<'lua>,
+        mutable: bool,
+    ) -> Result<Value<'lua>> {
+        let ty = ty.dyn_borrow::<dyn ComponentType>()?;
+        let type_id = ComponentType::type_id(&*ty);
+
+        if let Some(loan) = self.loans.iter().find(|loan| loan.type_id == type_id) {
+            if mutable && !loan.mutable {
+                return Err(Error::external(
+                    "component already borrowed immutably from this query item!",
+                ));
+            }
+            return lua.registry_value(&loan.component);
+        }
+
+        if mutable && !self.writes.contains(&type_id) {
+            return Err(Error::external(
+                "component can't be borrowed mutably, its query only has read access to it!",
+            ));
+        }
+
+        let i = match self.components.iter().position(|(id, _)| *id == type_id) {
+            Some(i) => i,
+            None => match ty.dynamic_item_take_component(self.item_mut()?) {
+                Some(component) => {
+                    self.components.push((type_id, component));
+                    self.components.len() - 1
+                }
+                None => return Ok(Value::Nil),
+            },
+        };
+
+        let component = &mut *self.components[i].1;
+        let (guard, component) = if mutable {
+            // safety: we just checked that the item's query has write access to the component, and
+            // the loan is dropped when the item is released, before the query is over.
+            unsafe { ty.dynamic_component_get_mut(lua, component)? }
+        } else {
+            ty.dynamic_component_get(lua, component)?
+        };
+
+        self.loans.push(ItemLoan {
+            type_id,
+            mutable,
+            component: lua.create_registry_value(component.clone())?,
+            _guard: guard,
+        });
+        Ok(Value::UserData(component))
+    }
+}
+
+impl UserData for QueryItem {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("take", move |lua, this, ty: AnyUserData| {
+            this.with_state(|state| state.take_component(lua, &ty))
+        });
+
+        methods.add_method("get", move |lua, this, ty: AnyUserData| {
+            this.with_state(|state| state.borrow_component(lua, &ty, false))
+        });
+
+        methods.add_method("get_mut", move |lua, this, ty: AnyUserData| {
+            this.with_state(|state| state.borrow_component(lua, &ty, true))
+        });
+    }
+}
+
+struct QueryItemHandle(Arc<QueryItemShared>);
+
+impl QueryItemHandle {
+    /// Revoke all borrowed components and invalidate the item.
+    fn release(&self) {
+        self.0.released.store(true, Ordering::Release);
+        // The state can only be locked already if we're being called from a finalizer while one of
+        // the item's methods is running on this thread. That method releases the item as soon as
+        // it's done, since `released` is set.
+        match self.0.state.try_lock() {
+            Ok(mut state) => state.release(),
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner().release(),
+            Err(TryLockError::WouldBlock) => {}
+        }
+    }
+}
+
+/// The state of a generic-for loop over a query, created by `World:iter`. Holds the world borrowed
+/// for the duration of the loop, and only lets each query item live for one iteration.
+///
+/// The world is released as soon as the loop runs to completion. On Lua 5.4, `World:iter` also
+/// returns the iterator as a to-be-closed value, so the world is released as well when the loop
+/// is broken out of or raises an error. Other versions have no to-be-closed values: a loop which
+/// doesn't run to completion there keeps the world borrowed until `close` is called on the
+/// iterator, or until it's garbage collected.
+pub struct WorldIter {
+    state: Option<WorldIterState>,
+    current: Option<QueryItemHandle>,
+}
+
+// Field order is drop order: the iterator borrows the query borrow, which borrows the query and
+// the world.
+struct WorldIterState {
+    iter: Box<dyn Iterator<Item = (ecs::Entity, ecs::DynamicItem)>>,
+    _borrow: Box<dyn Any>,
+    query: Box<Query>,
+    world: WorldRef<'static>,
+    _world_key: RegistryKey,
+}
+
+// safety: the borrow of the world never leaves the Lua state which owns the world, and the state
+// can only be accessed from one thread at a time.
+#[cfg(feature = "send")]
+unsafe impl Send for WorldIter {}
+
+impl WorldIter {
+    fn new<'lua>(lua: &'lua Lua, world: &AnyUserData<'lua>, query: Query) -> Result<Self> {
+        let world_key = lua.create_registry_value(world.clone())?;
+        // safety: the registry key keeps the world userdata alive for as long as the borrow, and
+        // everything borrowing from the world, the query, or the query borrow is dropped before
+        // them (see `WorldIterState`).
+        unsafe {
+            let world_ref =
+                std::mem::transmute::<WorldRef, WorldRef<'static>>(borrow_world(world)?);
+            let query = Box::new(query);
+            let mut borrow = Box::new(
+                (*(&*world_ref as *const ecs::World))
+                    .dynamic_query(&*(&query.query as *const ecs::DynamicQuery)),
+            );
+            let iter = Box::new((*(&mut *borrow as *mut _)).iter());
+
+            Ok(Self {
+                state: Some(WorldIterState {
+                    iter,
+                    _borrow: borrow,
+                    query,
+                    world: world_ref,
+                    _world_key: world_key,
+                }),
+                current: None,
+            })
+        }
+    }
+
+    fn release_current(&mut self) {
+        if let Some(current) = self.current.take() {
+            current.release();
+        }
+    }
+
+    fn close(&mut self) {
+        self.release_current();
+        self.state = None;
+    }
+}
+
+impl Drop for WorldIter {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl UserData for WorldIter {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method_mut(MetaMethod::Call, |lua, this, _: MultiValue| {
+            this.release_current();
+            let next = match &mut this.state {
+                Some(state) => {
+                    let (query, world) = (&state.query, &*state.world);
+                    state
+                        .iter
+                        .find(|(entity, _)| query.matches(world, *entity))
+                        .map(|(entity, item)| (entity, query.item(item)))
+                }
+                None => None,
+            };
+
+            match next {
+                Some((entity, item)) => {
+                    this.current = Some(item.handle());
+                    (entity, item).to_lua_multi(lua)
+                }
+                None => {
+                    this.close();
+                    Value::Nil.to_lua_multi(lua)
+                }
+            }
+        });
+
+        // Release the world before the loop is over. Only needed when breaking out of a loop
+        // early on versions without to-be-closed values.
+        methods.add_method_mut("close", |_, this, ()| {
+            this.close();
+            Ok(())
+        });
+
+        #[cfg(feature = "lua54")]
+        methods.add_meta_method_mut(MetaMethod::Close, |_, this, _: MultiValue| {
+            this.close();
+            Ok(())
+        });
+    }
+}
+
 impl<T: 'static + UserData + Send + Sync> UserData for ecs::DynamicComponent<T> {
     fn on_metatable_init(table: Type<Self>) {
         table.add_send().add_sync();
@@ -417,67 +1199,361 @@ impl UserData for ecs::World {
 
         methods.add_method("len", |_lua, this, ()| Ok(this.len()));
 
+        // Methods which may cause events take the world userdata rather than the world, since its
+        // event log is kept beside it.
         let mut builder = ecs::EntityBuilder::new();
-        methods.add_method_mut("spawn", move |_, this, args: MultiValue| {
-            assert_eq!(args.len(), 1, "spawn only takes one argument!");
-            let arg = args.into_iter().next().unwrap();
-            match arg {
-                Value::Table(table) => {
-                    builder.clear();
-                    for component in table.sequence_values::<AnyUserData>() {
-                        let component = component?;
-                        if let Ok(bundle) = component
-                            .clone()
-                            .dyn_clone_or_take::<dyn DynamicBundleProxy>()
-                        {
-                            builder.add_bundle(bundle);
-                        } else if let Ok(single) = LuaSingleBundle::from_lua_userdata(&component) {
-                            builder.add_bundle(single);
-                        } else {
-                            return Err(Error::external(
-                                "expected a table of bundles and components",
-                            ));
-                        }
+        methods.add_function_mut(
+            "spawn",
+            move |lua, (this, args): (AnyUserData, MultiValue)| {
+                assert_eq!(args.len(), 1, "spawn only takes one argument!");
+                let arg = args.into_iter().next().unwrap();
+                let log = events::log_of(lua, &this)?;
+                let mut this = borrow_world_mut(&this)?;
+                let entity = match arg {
+                    Value::Table(table) => {
+                        builder.clear();
+                        add_table_to_builder(&mut builder, table)?;
+                        this.spawn(builder.build())
+                    }
+                    Value::UserData(ud) => {
+                        this.spawn(ud.dyn_clone_or_take::<dyn DynamicBundleProxy>()?)
+                    }
+                    _ => {
+                        return Err(Error::external(
+                            "expected either a bundle or a table of bundles and components",
+                        ))
+                    }
+                };
+                events::spawned(log.as_ref(), &this, entity)?;
+                Ok(entity)
+            },
+        );
+
+        let mut builder = ecs::EntityBuilder::new();
+        methods.add_function_mut(
+            "spawn_at",
+            move |lua, (this, entity, components): (AnyUserData, ecs::Entity, Value)| {
+                let log = events::log_of(lua, &this)?;
+                let mut this = borrow_world_mut(&this)?;
+                match components {
+                    Value::Table(table) => {
+                        builder.clear();
+                        add_table_to_builder(&mut builder, table)?;
+                        this.spawn_at(entity, builder.build());
+                    }
+                    Value::UserData(ud) => {
+                        this.spawn_at(entity, ud.dyn_clone_or_take::<dyn DynamicBundleProxy>()?);
+                    }
+                    _ => {
+                        return Err(Error::external(
+                            "expected either a bundle or a table of bundles and components",
+                        ))
                     }
-                    Ok(this.spawn(builder.build()))
                 }
-                Value::UserData(ud) => {
-                    Ok(this.spawn(ud.dyn_clone_or_take::<dyn DynamicBundleProxy>()?))
+                events::spawned(log.as_ref(), &this, entity)
+            },
+        );
+
+        methods.add_function(
+            "spawn_column_batch",
+            |lua, (this, batch): (AnyUserData, AnyUserData)| {
+                let log = events::log_of(lua, &this)?;
+                let mut this = borrow_world_mut(&this)?;
+                let entities = this
+                    .spawn_column_batch(batch.take::<ecs::ColumnBatch>()?)
+                    .collect::<Vec<_>>();
+                for &entity in &entities {
+                    events::spawned(log.as_ref(), &this, entity)?;
                 }
-                _ => Err(Error::external(
-                    "expected either a bundle or a table of bundles and components",
-                )),
-            }
+                Ok(entities)
+            },
+        );
+
+        methods.add_method("reserve_entity", |_lua, this, ()| Ok(this.reserve_entity()));
+
+        methods.add_function(
+            "despawn",
+            |lua, (this, entity): (AnyUserData, ecs::Entity)| {
+                let log = events::log_of(lua, &this)?;
+                events::tracked(
+                    log.as_ref(),
+                    &mut borrow_world_mut(&this)?,
+                    entity,
+                    |world| world.despawn(entity).to_lua_err(),
+                )
+            },
+        );
+
+        methods.add_function("clear", |lua, this: AnyUserData| {
+            let log = events::log_of(lua, &this)?;
+            let mut this = borrow_world_mut(&this)?;
+            events::clearing(log.as_ref(), &this)?;
+            this.clear();
+            Ok(())
+        });
+
+        let mut builder = ecs::EntityBuilder::new();
+        methods.add_function_mut(
+            "insert",
+            move |lua, (this, entity, components): (AnyUserData, ecs::Entity, Value)| {
+                let log = events::log_of(lua, &this)?;
+                let mut this = borrow_world_mut(&this)?;
+                match components {
+                    Value::Table(table) => {
+                        builder.clear();
+                        add_table_to_builder(&mut builder, table)?;
+                        events::tracked(log.as_ref(), &mut this, entity, |world| {
+                            world.insert(entity, builder.build()).to_lua_err()
+                        })
+                    }
+                    Value::UserData(ud) => {
+                        let bundle = ud.dyn_clone_or_take::<dyn DynamicBundleProxy>()?;
+                        events::tracked(log.as_ref(), &mut this, entity, |world| {
+                            world.insert(entity, bundle).to_lua_err()
+                        })
+                    }
+                    _ => Err(Error::external(
+                        "expected either a bundle or a table of bundles and components",
+                    )),
+                }
+            },
+        );
+
+        methods.add_function(
+            "insert_one",
+            |lua, (this, entity, component): (AnyUserData, ecs::Entity, AnyUserData)| {
+                let single = LuaSingleBundle::from_lua_userdata(&component)?;
+                let log = events::log_of(lua, &this)?;
+                events::tracked(
+                    log.as_ref(),
+                    &mut borrow_world_mut(&this)?,
+                    entity,
+                    |world| world.insert(entity, single).to_lua_err(),
+                )
+            },
+        );
+
+        methods.add_function(
+            "remove_one",
+            |lua, (this, entity, ty): (AnyUserData, ecs::Entity, AnyUserData)| {
+                let ty = ty.dyn_borrow::<dyn ComponentType>()?;
+                let log = events::log_of(lua, &this)?;
+                events::tracked(
+                    log.as_ref(),
+                    &mut borrow_world_mut(&this)?,
+                    entity,
+                    |world| ty.world_remove_one(lua, world, entity),
+                )
+            },
+        );
+
+        methods.add_function(
+            "remove",
+            |lua, (this, entity, tys): (AnyUserData, ecs::Entity, Variadic<AnyUserData>)| {
+                let log = events::log_of(lua, &this)?;
+                let mut this = borrow_world_mut(&this)?;
+
+                // Check everything is present and requested only once first, so that a failure
+                // doesn't leave the entity with only some of the components removed.
+                let mut seen = Vec::with_capacity(tys.len());
+                for ty in tys.iter() {
+                    let ty = ty.dyn_borrow::<dyn ComponentType>()?;
+                    let type_id = ComponentType::type_id(&*ty);
+                    if seen.contains(&type_id) {
+                        return Err(Error::external(format!(
+                            "component type passed more than once when removing from entity {:?}",
+                            entity
+                        )));
+                    }
+                    seen.push(type_id);
+
+                    if !ty.world_has(&this, entity)? {
+                        return Err(Error::external(format!(
+                            "entity {:?} has no component of one of the types to remove",
+                            entity
+                        )));
+                    }
+                }
+
+                events::tracked(log.as_ref(), &mut this, entity, |world| {
+                    tys.iter()
+                        .map(|ty| {
+                            ty.dyn_borrow::<dyn ComponentType>()?
+                                .world_remove_one(lua, world, entity)
+                        })
+                        .collect::<Result<Variadic<_>>>()
+                })
+            },
+        );
+
+        methods.add_method(
+            "get",
+            |lua, this, (entity, ty): (ecs::Entity, AnyUserData)| {
+                ty.dyn_borrow::<dyn ComponentType>()?
+                    .world_get(lua, this, entity)
+            },
+        );
+
+        methods.add_method(
+            "has",
+            |_lua, this, (entity, ty): (ecs::Entity, AnyUserData)| {
+                ty.dyn_borrow::<dyn ComponentType>()?
+                    .world_has(this, entity)
+            },
+        );
+
+        methods.add_function(
+            "on_add",
+            |lua, (this, ty, f): (AnyUserData, AnyUserData, Function)| {
+                let ty = ComponentType::type_id(&*ty.dyn_borrow::<dyn ComponentType>()?);
+                events::subscribe_add(lua, &this, ty, f)
+            },
+        );
+
+        methods.add_function(
+            "on_remove",
+            |lua, (this, ty, f): (AnyUserData, AnyUserData, Function)| {
+                let ty = ComponentType::type_id(&*ty.dyn_borrow::<dyn ComponentType>()?);
+                events::subscribe_remove(lua, &this, ty, f)
+            },
+        );
+
+        methods.add_function("on_despawn", |lua, (this, f): (AnyUserData, Function)| {
+            events::subscribe_despawn(lua, &this, f)
+        });
+
+        methods.add_function("unsubscribe_all", |lua, this: AnyUserData| {
+            events::unsubscribe_all(lua, &this)
+        });
+
+        // Subscribers will usually want to modify the world, so it can't stay borrowed while
+        // they're called.
+        methods.add_function("flush_events", |lua, this: AnyUserData| {
+            events::flush_events(lua, &this)
+        });
+
+        // Components whose types have no type object in this Lua state are left out.
+        methods.add_method("components", |lua, this, entity: ecs::Entity| {
+            this.entity(entity)
+                .to_lua_err()?
+                .component_types()
+                .filter_map(|type_id| type_objects::component_type_object(lua, type_id).transpose())
+                .collect::<Result<Vec<_>>>()
+        });
+
+        methods.add_method(
+            "borrow",
+            |lua, this, (entity, ty, f): (ecs::Entity, AnyUserData, Function)| {
+                ty.dyn_borrow::<dyn ComponentType>()?.world_borrow(
+                    lua,
+                    this,
+                    entity,
+                    &mut |component| f.call(component),
+                )
+            },
+        );
+
+        methods.add_method(
+            "borrow_mut",
+            |lua, this, (entity, ty, f): (ecs::Entity, AnyUserData, Function)| {
+                ty.dyn_borrow::<dyn ComponentType>()?.world_borrow_mut(
+                    lua,
+                    this,
+                    entity,
+                    &mut |component| f.call(component),
+                )
+            },
+        );
+
+        methods.add_method("entities", |_lua, this, ()| {
+            Ok(this.iter().map(|e| e.entity()).collect::<Vec<_>>())
         });
 
         methods.add_method(
             "query",
-            |lua, this, (query, for_each): (ecs::DynamicQuery, Function<'lua>)| {
-                let mut dynamic_query = this.dynamic_query(&query);
+            |lua, this, (query, for_each): (AnyUserData, Function<'lua>)| {
+                let query = Query::from_user_data(&query)?;
+                let mut dynamic_query = this.dynamic_query(&query.query);
                 let mut dynamic_query_iter = dynamic_query.iter();
+                let handles = RefCell::new(Vec::new());
                 let mut out: Option<MultiValue<'lua>> = None;
-                lua.scope(|scope| {
-                    let iter =
-                        scope.create_function_mut(|lua, ()| match dynamic_query_iter.next() {
-                            Some(pair) => pair.to_lua_multi(lua),
+                let res = lua.scope(|scope| {
+                    let iter = scope.create_function_mut(|lua, ()| {
+                        let next = dynamic_query_iter
+                            .by_ref()
+                            .find(|(entity, _)| query.matches(this, *entity));
+                        match next {
+                            Some((entity, item)) => {
+                                let item = query.item(item);
+                                handles.borrow_mut().push(item.handle());
+                                (entity, item).to_lua_multi(lua)
+                            }
                             None => Value::Nil.to_lua_multi(lua),
-                        })?;
+                        }
+                    })?;
                     out = Some(for_each.call(iter)?);
                     Ok(())
-                })?;
+                });
+                handles
+                    .into_inner()
+                    .iter()
+                    .for_each(QueryItemHandle::release);
+                res?;
                 Ok(out.unwrap())
             },
         );
 
         methods.add_method(
             "query_one",
-            |_lua, this, (query, entity, for_entity): (ecs::DynamicQuery, ecs::Entity,Function<'lua>)| {
-                let mut dynamic_query_one = this.dynamic_query_one(&query, entity).to_lua_err()?;
-                let out = for_entity.call::<_, MultiValue>(dynamic_query_one.get());
+            |_lua,
+             this,
+             (query, entity, for_entity): (AnyUserData, ecs::Entity, Function<'lua>)| {
+                let query = Query::from_user_data(&query)?;
+                let mut dynamic_query_one =
+                    this.dynamic_query_one(&query.query, entity).to_lua_err()?;
+                let item = dynamic_query_one
+                    .get()
+                    .filter(|_| query.matches(this, entity))
+                    .map(|item| query.item(item));
+                let handle = item.as_ref().map(QueryItem::handle);
+                let out = for_entity.call::<_, MultiValue>(item);
+                if let Some(handle) = handle {
+                    handle.release();
+                }
                 drop(dynamic_query_one);
                 out
             },
         );
+
+        // A generic-for iterator over the entities matching the query and their items, as in
+        // `for entity, item in world:iter(query) do ... end`. See `WorldIter` for when the world is
+        // released.
+        methods.add_function("iter", |lua, (this, query): (AnyUserData, AnyUserData)| {
+            let query = Query::from_user_data(&query)?;
+            let iter = lua.create_userdata(WorldIter::new(lua, &this, query)?)?;
+            Ok((iter.clone(), Value::Nil, Value::Nil, iter))
+        });
+
+        // Calls `f(entity, item)` for every entity matching the query, stopping early if `f`
+        // returns `false`. Like with `query`, the world stays borrowed only until `each` returns,
+        // and each item is released once the call it was passed to is over.
+        methods.add_method("each", |_lua, this, (query, f): (AnyUserData, Function)| {
+            let query = Query::from_user_data(&query)?;
+            let mut dynamic_query = this.dynamic_query(&query.query);
+            for (entity, item) in dynamic_query
+                .iter()
+                .filter(|(entity, _)| query.matches(this, *entity))
+            {
+                let item = query.item(item);
+                let handle = item.handle();
+                let result = f.call::<_, Value>((entity, item));
+                handle.release();
+                if let Value::Boolean(false) = result? {
+                    break;
+                }
+            }
+            Ok(())
+        });
     }
 
     fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M) {
@@ -485,19 +1561,179 @@ impl UserData for ecs::World {
     }
 }
 
+enum Command {
+    SpawnAt(ecs::Entity, ecs::EntityBuilder),
+    Insert(ecs::Entity, ecs::EntityBuilder),
+    Remove(ecs::Entity, Vec<Box<dyn ComponentType>>),
+    Despawn(ecs::Entity),
+}
+
+/// Structural changes to a [`World`](ecs::World) recorded from Lua, so that they can be made while
+/// the world is borrowed (for example, inside of a query) and applied to it afterwards.
+///
+/// Like the `hecs` command buffer, commands which refer to entities that no longer exist by the time
+/// the buffer is run, or to components they don't have, are ignored.
+#[derive(Default)]
+pub struct CommandBuffer {
+    commands: Vec<Command>,
+}
+
+impl CommandBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    /// Apply all recorded commands to the world in the order they were recorded, leaving the
+    /// buffer empty.
+    ///
+    /// This doesn't record any events for the world; running the buffer from Lua with
+    /// `CommandBuffer:run_on` does.
+    pub fn run_on(&mut self, world: &mut ecs::World) {
+        // Without an event log, applying commands can't fail.
+        let _ = self.apply(None, world);
+    }
+
+    fn apply(&mut self, log: Option<&events::EventLog>, world: &mut ecs::World) -> Result<()> {
+        for command in self.commands.drain(..) {
+            match command {
+                Command::SpawnAt(entity, mut builder) => {
+                    world.spawn_at(entity, builder.build());
+                    events::spawned(log, world, entity)?;
+                }
+                Command::Insert(entity, mut builder) => {
+                    events::tracked(log, world, entity, |world| {
+                        let _ = world.insert(entity, builder.build());
+                        Ok(())
+                    })?;
+                }
+                Command::Remove(entity, tys) => {
+                    events::tracked(log, world, entity, |world| {
+                        for ty in tys {
+                            ty.world_discard_one(world, entity);
+                        }
+                        Ok(())
+                    })?;
+                }
+                Command::Despawn(entity) => {
+                    events::tracked(log, world, entity, |world| {
+                        let _ = world.despawn(entity);
+                        Ok(())
+                    })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl UserData for CommandBuffer {
+    fn on_metatable_init(table: Type<Self>) {
+        table.add_send().add_sync();
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("len", |_, this, ()| Ok(this.len()));
+
+        // The entity is reserved in the world right away, so that it can be referred to by later
+        // commands and stored before the buffer is run.
+        methods.add_method_mut(
+            "spawn",
+            |_, this, (world, builder): (AnyUserData, ecs::EntityBuilder)| {
+                let entity = borrow_world(&world)?.reserve_entity();
+                this.commands.push(Command::SpawnAt(entity, builder));
+                Ok(entity)
+            },
+        );
+
+        methods.add_method_mut(
+            "insert",
+            |_, this, (entity, builder): (ecs::Entity, ecs::EntityBuilder)| {
+                this.commands.push(Command::Insert(entity, builder));
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "insert_one",
+            |_, this, (entity, component): (ecs::Entity, AnyUserData)| {
+                let mut builder = ecs::EntityBuilder::new();
+                builder.add_bundle(LuaSingleBundle::from_lua_userdata(&component)?);
+                this.commands.push(Command::Insert(entity, builder));
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "remove",
+            |_, this, (entity, tys): (ecs::Entity, Variadic<AnyUserData>)| {
+                let tys = tys
+                    .iter()
+                    .map(|ty| Ok(ty.dyn_borrow::<dyn ComponentType>()?.boxed()))
+                    .collect::<Result<_>>()?;
+                this.commands.push(Command::Remove(entity, tys));
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut("despawn", |_, this, entity: ecs::Entity| {
+            this.commands.push(Command::Despawn(entity));
+            Ok(())
+        });
+
+        methods.add_method_mut("clear", |_, this, ()| {
+            this.clear();
+            Ok(())
+        });
+
+        methods.add_method_mut("run_on", |lua, this, world: AnyUserData| {
+            let log = events::log_of(lua, &world)?;
+            this.apply(log.as_ref(), &mut borrow_world_mut(&world)?)?;
+            Ok(())
+        });
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M) {
+        methods.add_function("new", |_, ()| Ok(Self::new()));
+    }
+}
+
 pub fn types(lua: &Lua) -> Result<Table> {
     macro_rules! e {
         ($ty:ty as $name:ident) => {
-            (stringify!($name), lua.create_userdata_type::<$ty>()?)
+            (stringify!($name), userdata_type::<$ty> as LazyLoader)
         };
     }
 
-    let es = vec![
+    #[allow(unused_mut)]
+    let mut es = vec![
         e!(ecs::World as World),
-        e!(ecs::DynamicQuery as Query),
-        e!(ecs::DynamicItem as Item),
+        e!(LuaEntity as Entity),
+        e!(Query as Query),
+        e!(QueryItem as Item),
         e!(ecs::ColumnBatchType as ColumnBatchType),
+        e!(ecs::ColumnBatch as ColumnBatch),
+        e!(CommandBuffer as CommandBuffer),
+        e!(LuaComponentType as LuaComponent),
+        e!(Schedule as Schedule),
+        e!(Resources as Resources),
+        e!(Script as Script),
     ];
 
-    lua.create_table_from(es)
+    #[cfg(feature = "serialize")]
+    es.push(e!(SceneRegistry as SceneRegistry));
+
+    lazy_table(lua, "hv.ecs", es)
 }
@@ -0,0 +1,236 @@
+use hv_alchemy::Type;
+use hv_ecs as ecs;
+
+use crate::{hv::ecs::ComponentType, AnyUserData, Error, ExternalResult, Result, Table, Value};
+
+/// Components which can be built from a fixed number of numbers, such as vectors. Registering
+/// [`FlatComponentType`] for the type object of such a component lets it be given as numbers when
+/// building column batches from Lua.
+pub trait FromNumbers: Sized {
+    /// How many numbers make up one component.
+    const LEN: usize;
+
+    /// Build a component from exactly [`FromNumbers::LEN`] numbers.
+    fn from_numbers(numbers: &[f64]) -> Self;
+}
+
+/// The type-erased form of [`FromNumbers`], implemented by the type objects of [`FromNumbers`]
+/// components.
+pub trait FlatComponentType: Send + Sync {
+    /// How many numbers make up one component.
+    fn width(&self) -> usize;
+
+    /// Fill this type's column in a batch from numbers, [`FlatComponentType::width`] per
+    /// component.
+    fn column_batch_builder_fill_numbers(
+        &self,
+        column_batch_builder: &mut ecs::ColumnBatchBuilder,
+        numbers: &[f64],
+    ) -> Result<()>;
+}
+
+impl<T: ecs::Component + FromNumbers> FlatComponentType for Type<T> {
+    fn width(&self) -> usize {
+        T::LEN
+    }
+
+    fn column_batch_builder_fill_numbers(
+        &self,
+        column_batch_builder: &mut ecs::ColumnBatchBuilder,
+        numbers: &[f64],
+    ) -> Result<()> {
+        let mut writer = column_batch_builder
+            .writer::<T>()
+            .ok_or_else(|| Error::external("not in ColumnBatch"))?;
+        for chunk in numbers.chunks_exact(T::LEN) {
+            writer
+                .push(T::from_numbers(chunk))
+                .ok()
+                .ok_or_else(|| Error::external("BatchWriter is full!"))?;
+        }
+        Ok(())
+    }
+}
+
+/// The values of a single component type given for a batch, either as userdata or as numbers.
+enum Column<'lua> {
+    Empty,
+    UserData(Vec<AnyUserData<'lua>>),
+    Numbers(Vec<f64>),
+}
+
+/// A component type and its values, ready to be written into a batch.
+struct TypedColumn<'lua> {
+    ty: AnyUserData<'lua>,
+    column: Column<'lua>,
+}
+
+impl<'lua> TypedColumn<'lua> {
+    fn new(ty: AnyUserData<'lua>) -> Self {
+        Self {
+            ty,
+            column: Column::Empty,
+        }
+    }
+
+    fn width(&self) -> Result<usize> {
+        Ok(self
+            .ty
+            .dyn_borrow::<dyn FlatComponentType>()
+            .map_err(|_| Error::external("component type can't be built from numbers"))?
+            .width())
+    }
+
+    fn push(&mut self, value: Value<'lua>) -> Result<()> {
+        if let Column::Empty = self.column {
+            self.column = match value {
+                Value::UserData(_) => Column::UserData(Vec::new()),
+                _ => Column::Numbers(Vec::new()),
+            };
+        }
+
+        // Every table is one whole component, so a short row can't be made up for by the next one.
+        let value = match value {
+            Value::Table(table) if matches!(self.column, Column::Numbers(_)) => {
+                let width = self.width()?;
+                let row = table.sequence_values::<f64>().collect::<Result<Vec<_>>>()?;
+                if row.len() != width {
+                    return Err(Error::external(format!(
+                        "expected a table of {} numbers, got {}",
+                        width,
+                        row.len()
+                    )));
+                }
+                if let Column::Numbers(numbers) = &mut self.column {
+                    numbers.extend(row);
+                }
+                return Ok(());
+            }
+            value => value,
+        };
+
+        match (&mut self.column, value) {
+            (Column::UserData(uds), Value::UserData(ud)) => uds.push(ud),
+            (Column::Numbers(numbers), Value::Integer(i)) => numbers.push(i as f64),
+            (Column::Numbers(numbers), Value::Number(n)) => numbers.push(n),
+            (_, value) => {
+                return Err(Error::external(format!(
+                    "expected components to be either all userdata or all numbers, got a {}",
+                    value.type_name()
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> Result<usize> {
+        match &self.column {
+            Column::Empty => Ok(0),
+            Column::UserData(uds) => Ok(uds.len()),
+            Column::Numbers(numbers) => {
+                let width = self.width()?;
+                if numbers.len() % width != 0 {
+                    return Err(Error::external(format!(
+                        "expected a multiple of {} numbers, got {}",
+                        width,
+                        numbers.len()
+                    )));
+                }
+                Ok(numbers.len() / width)
+            }
+        }
+    }
+
+    fn fill(self, column_batch_builder: &mut ecs::ColumnBatchBuilder) -> Result<()> {
+        match self.column {
+            Column::Empty => Ok(()),
+            Column::UserData(uds) => self
+                .ty
+                .dyn_borrow::<dyn ComponentType>()?
+                .column_batch_builder_fill(column_batch_builder, &uds),
+            Column::Numbers(numbers) => self
+                .ty
+                .dyn_borrow::<dyn FlatComponentType>()?
+                .column_batch_builder_fill_numbers(column_batch_builder, &numbers),
+        }
+    }
+}
+
+fn build(columns: Vec<TypedColumn>) -> Result<ecs::ColumnBatch> {
+    let mut len = None;
+    let mut column_batch_type = ecs::ColumnBatchType::new();
+    for column in &columns {
+        column
+            .ty
+            .dyn_borrow::<dyn ComponentType>()?
+            .column_batch_type_add(&mut column_batch_type);
+
+        let column_len = column.len()?;
+        match len {
+            Some(len) if len != column_len => {
+                return Err(Error::external(format!(
+                    "all columns must have the same length, found lengths {} and {}",
+                    len, column_len
+                )))
+            }
+            _ => len = Some(column_len),
+        }
+    }
+
+    let mut column_batch_builder = column_batch_type.into_batch(len.unwrap_or(0) as u32);
+    for column in columns {
+        column.fill(&mut column_batch_builder)?;
+    }
+
+    column_batch_builder.build().to_lua_err()
+}
+
+/// Build a column batch from a list of component types and a parallel list of columns, one per
+/// type. Each column is a sequence of the components of its type: as userdata, or, for types with a
+/// [`FlatComponentType`], as tables of numbers or as one flat array of numbers.
+pub fn column_batch_from_columns<'lua>(
+    types: Table<'lua>,
+    columns: Table<'lua>,
+) -> Result<ecs::ColumnBatch> {
+    let mut typed_columns = Vec::new();
+    for (i, ty) in types.sequence_values::<AnyUserData>().enumerate() {
+        let mut typed_column = TypedColumn::new(ty?);
+        let values = columns.raw_get::<_, Table>(i + 1)?;
+        for value in values.sequence_values::<Value>() {
+            typed_column.push(value?)?;
+        }
+        typed_columns.push(typed_column);
+    }
+
+    build(typed_columns)
+}
+
+/// Build a column batch from a list of component types and a list of rows, each holding one
+/// component per type in the same order. Components are given as for
+/// [`column_batch_from_columns`], except that a flat component can't be given as loose numbers.
+pub fn column_batch_from_rows<'lua>(
+    types: Table<'lua>,
+    rows: Table<'lua>,
+) -> Result<ecs::ColumnBatch> {
+    let mut typed_columns = types
+        .sequence_values::<AnyUserData>()
+        .map(|ty| Ok(TypedColumn::new(ty?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    for row in rows.sequence_values::<Table>() {
+        let row = row?;
+        for (i, typed_column) in typed_columns.iter_mut().enumerate() {
+            match row.raw_get::<_, Value>(i + 1)? {
+                value @ (Value::UserData(_) | Value::Table(_)) => typed_column.push(value)?,
+                other => {
+                    return Err(Error::external(format!(
+                        "expected a component or a table of numbers, got a {}",
+                        other.type_name()
+                    )))
+                }
+            }
+        }
+    }
+
+    build(typed_columns)
+}
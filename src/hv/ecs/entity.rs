@@ -0,0 +1,98 @@
+use hv_alchemy::Type;
+use hv_ecs as ecs;
+
+use crate::{
+    AnyUserData, LightUserData, Lua, MetaMethod, Result, UserData, UserDataFields, UserDataMethods,
+    Value,
+};
+
+/// An entity as a full userdata, with `id` and `generation` fields, a readable `__tostring` and
+/// equality.
+///
+/// By default, entities are passed to Lua as light userdata, which is as cheap as it gets but can't
+/// be told apart from any other pointer. Calling [`set_typed_entities`] makes every entity passed
+/// to Lua a `LuaEntity` instead. Typed entities are interned, so the same entity is always the same
+/// userdata and can be used as a table key. Functions taking entities accept either form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LuaEntity(pub ecs::Entity);
+
+impl LuaEntity {
+    pub fn id(&self) -> u32 {
+        self.0.id()
+    }
+
+    pub fn generation(&self) -> u32 {
+        (self.0.to_bits().get() >> 32) as u32
+    }
+}
+
+/// Choose whether entities are passed to Lua as [`LuaEntity`] userdata (`true`) or as light
+/// userdata (`false`, the default) from now on.
+pub fn set_typed_entities(lua: &Lua, enabled: bool) -> Result<()> {
+    if !enabled {
+        lua.set_typed_entities(None)?;
+    } else if lua.typed_entities()?.is_none() {
+        // Its values are weak, so an entity's userdata lives only as long as something references
+        // it.
+        let interned = lua.create_table()?;
+        interned.set_metatable(Some(lua.create_table_from([("__mode", "v")])?));
+        lua.set_typed_entities(Some(interned))?;
+    }
+
+    Ok(())
+}
+
+/// Convert an entity to its Lua representation, according to [`set_typed_entities`].
+pub(super) fn entity_to_lua(lua: &Lua, entity: ecs::Entity) -> Result<Value> {
+    let raw = LightUserData(entity.to_bits().get() as *mut _);
+    let interned = match lua.typed_entities()? {
+        Some(interned) => interned,
+        None => return Ok(Value::LightUserData(raw)),
+    };
+
+    // Interned by their light userdata form, which holds all of their bits.
+    if let Some(ud) = interned.raw_get::<_, Option<AnyUserData>>(raw)? {
+        return Ok(Value::UserData(ud));
+    }
+
+    let ud = lua.create_userdata(LuaEntity(entity))?;
+    interned.raw_set(raw, ud.clone())?;
+    Ok(Value::UserData(ud))
+}
+
+impl UserData for LuaEntity {
+    fn on_metatable_init(table: Type<Self>) {
+        table.add_clone().add_copy().add_send().add_sync();
+    }
+
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("id", |_, this| Ok(this.id()));
+        fields.add_field_method_get("generation", |_, this| Ok(this.generation()));
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // The light userdata form of this entity, for hot loops.
+        methods.add_method("raw", |_, this, ()| {
+            Ok(LightUserData(this.0.to_bits().get() as *mut _))
+        });
+
+        methods.add_meta_method(MetaMethod::Eq, |_, this, other: AnyUserData| {
+            Ok(other
+                .borrow::<LuaEntity>()
+                .map_or(false, |other| *this == *other))
+        });
+
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(format!("Entity({}v{})", this.id(), this.generation()))
+        });
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M)
+    where
+        Self: 'static,
+    {
+        methods.add_function("set_typed", |lua, enabled: bool| {
+            set_typed_entities(lua, enabled)
+        });
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    any::TypeId,
+    sync::{Arc, Weak},
+};
+
+use hv_cell::AtomicRefCell;
+use hv_ecs as ecs;
+use rustc_hash::FxHashMap;
+
+use crate::{hv::ecs::SharedWorld, AnyUserData, Function, Lua, RegistryKey, Result, UserData};
+
+#[derive(Debug, Clone, Copy)]
+enum WorldEvent {
+    Added(ecs::Entity, TypeId),
+    Removed(ecs::Entity, TypeId),
+    Despawned(ecs::Entity),
+}
+
+/// The subscribers and undelivered events of a single world.
+///
+/// The log is kept beside the world it belongs to, so that it can neither outlive the world nor be
+/// picked up by another one: a Lua-owned `World` keeps it as its user value, and a [`SharedWorld`]
+/// keeps it in a per-Lua-state table shared by all of its handles.
+#[derive(Default)]
+struct WorldEventLog {
+    on_add: FxHashMap<TypeId, Vec<RegistryKey>>,
+    on_remove: FxHashMap<TypeId, Vec<RegistryKey>>,
+    on_despawn: Vec<RegistryKey>,
+    pending: Vec<WorldEvent>,
+}
+
+impl UserData for WorldEventLog {}
+
+impl WorldEventLog {
+    fn wants(&self, event: &WorldEvent) -> bool {
+        match event {
+            WorldEvent::Added(_, type_id) => self.on_add.contains_key(type_id),
+            WorldEvent::Removed(_, type_id) => self.on_remove.contains_key(type_id),
+            WorldEvent::Despawned(_) => !self.on_despawn.is_empty(),
+        }
+    }
+
+    fn subscribers(&self, event: &WorldEvent) -> &[RegistryKey] {
+        let subscribers = match event {
+            WorldEvent::Added(_, type_id) => self.on_add.get(type_id),
+            WorldEvent::Removed(_, type_id) => self.on_remove.get(type_id),
+            WorldEvent::Despawned(_) => Some(&self.on_despawn),
+        };
+        subscribers.map_or(&[], Vec::as_slice)
+    }
+}
+
+/// The event logs of the shared worlds with subscribers in a Lua state, by the address of their
+/// cell. The weak reference keeps the address from being reused by another cell for as long as
+/// the entry exists.
+///
+/// This is kept in the Lua state's internal data rather than its app data, where it could be
+/// replaced or left borrowed by users.
+#[derive(Default)]
+pub(crate) struct SharedWorldEventLogs(
+    FxHashMap<usize, (Weak<AtomicRefCell<ecs::World>>, RegistryKey)>,
+);
+
+/// The event log of a world userdata, present if anything ever subscribed to its events.
+pub(super) struct EventLog<'lua>(AnyUserData<'lua>);
+
+/// Get the event log of a `World` or [`SharedWorld`] userdata, if it has one.
+pub(super) fn log_of<'lua>(
+    lua: &'lua Lua,
+    world: &AnyUserData<'lua>,
+) -> Result<Option<EventLog<'lua>>> {
+    if world.is::<ecs::World>() {
+        return Ok(world.get_user_value::<Option<AnyUserData>>()?.map(EventLog));
+    }
+
+    let shared = world.borrow::<SharedWorld>()?;
+    lua.with_shared_world_event_logs(|logs| match logs.0.get(&(Arc::as_ptr(&*shared) as usize)) {
+        Some((_, log)) => Ok(Some(EventLog(lua.registry_value(log)?))),
+        None => Ok(None),
+    })
+}
+
+fn log_or_create<'lua>(lua: &'lua Lua, world: &AnyUserData<'lua>) -> Result<EventLog<'lua>> {
+    if let Some(log) = log_of(lua, world)? {
+        return Ok(log);
+    }
+
+    let log = lua.create_userdata(WorldEventLog::default())?;
+    if world.is::<ecs::World>() {
+        world.set_user_value(log.clone())?;
+    } else {
+        let shared = world.borrow::<SharedWorld>()?;
+        let key = lua.create_registry_value(log.clone())?;
+        lua.with_shared_world_event_logs(|logs| {
+            // Drop the logs of shared worlds which are gone.
+            logs.0.retain(|_, (world, _)| world.strong_count() > 0);
+            logs.0.insert(
+                Arc::as_ptr(&*shared) as usize,
+                (Arc::downgrade(&*shared), key),
+            );
+        });
+    }
+
+    Ok(EventLog(log))
+}
+
+fn record(log: &EventLog, events: impl IntoIterator<Item = WorldEvent>) -> Result<()> {
+    let mut log = log.0.borrow_mut::<WorldEventLog>()?;
+    for event in events {
+        if log.wants(&event) {
+            log.pending.push(event);
+        }
+    }
+    Ok(())
+}
+
+fn component_types(world: &ecs::World, entity: ecs::Entity) -> Vec<TypeId> {
+    world
+        .entity(entity)
+        .map(|entity| entity.component_types().collect())
+        .unwrap_or_default()
+}
+
+/// Run `f`, recording which components it added to or removed from `entity`, and whether it
+/// despawned `entity`, if the world has an event log.
+pub(super) fn tracked<R>(
+    log: Option<&EventLog>,
+    world: &mut ecs::World,
+    entity: ecs::Entity,
+    f: impl FnOnce(&mut ecs::World) -> Result<R>,
+) -> Result<R> {
+    let log = match log {
+        Some(log) => log,
+        None => return f(world),
+    };
+
+    let was_alive = world.contains(entity);
+    let before = component_types(world, entity);
+    let result = f(world)?;
+    let after = component_types(world, entity);
+
+    let added = after
+        .iter()
+        .filter(|t| !before.contains(t))
+        .map(|&t| WorldEvent::Added(entity, t));
+    let removed = before
+        .iter()
+        .filter(|t| !after.contains(t))
+        .map(|&t| WorldEvent::Removed(entity, t));
+    let despawned = if was_alive && !world.contains(entity) {
+        Some(WorldEvent::Despawned(entity))
+    } else {
+        None
+    };
+    record(log, added.chain(removed).chain(despawned))?;
+
+    Ok(result)
+}
+
+/// Record the components of a freshly spawned entity as added.
+pub(super) fn spawned(
+    log: Option<&EventLog>,
+    world: &ecs::World,
+    entity: ecs::Entity,
+) -> Result<()> {
+    match log {
+        Some(log) => {
+            let added = component_types(world, entity)
+                .into_iter()
+                .map(|t| WorldEvent::Added(entity, t));
+            record(log, added)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Record every entity in the world as despawned, before the world is cleared.
+pub(super) fn clearing(log: Option<&EventLog>, world: &ecs::World) -> Result<()> {
+    match log {
+        Some(log) => {
+            let mut events = Vec::new();
+            for entity in world.iter() {
+                let e = entity.entity();
+                events.extend(entity.component_types().map(|t| WorldEvent::Removed(e, t)));
+                events.push(WorldEvent::Despawned(e));
+            }
+            record(log, events)
+        }
+        None => Ok(()),
+    }
+}
+
+pub(super) fn subscribe_add<'lua>(
+    lua: &'lua Lua,
+    world: &AnyUserData<'lua>,
+    ty: TypeId,
+    f: Function,
+) -> Result<()> {
+    let key = lua.create_registry_value(f)?;
+    let log = log_or_create(lua, world)?;
+    let mut log = log.0.borrow_mut::<WorldEventLog>()?;
+    log.on_add.entry(ty).or_default().push(key);
+    Ok(())
+}
+
+pub(super) fn subscribe_remove<'lua>(
+    lua: &'lua Lua,
+    world: &AnyUserData<'lua>,
+    ty: TypeId,
+    f: Function,
+) -> Result<()> {
+    let key = lua.create_registry_value(f)?;
+    let log = log_or_create(lua, world)?;
+    let mut log = log.0.borrow_mut::<WorldEventLog>()?;
+    log.on_remove.entry(ty).or_default().push(key);
+    Ok(())
+}
+
+pub(super) fn subscribe_despawn<'lua>(
+    lua: &'lua Lua,
+    world: &AnyUserData<'lua>,
+    f: Function,
+) -> Result<()> {
+    let key = lua.create_registry_value(f)?;
+    let log = log_or_create(lua, world)?;
+    let mut log = log.0.borrow_mut::<WorldEventLog>()?;
+    log.on_despawn.push(key);
+    Ok(())
+}
+
+/// Remove all subscribers from a `World` or [`SharedWorld`] userdata, and discard its undelivered
+/// events.
+pub fn unsubscribe_all<'lua>(lua: &'lua Lua, world: &AnyUserData<'lua>) -> Result<()> {
+    if let Some(log) = log_of(lua, world)? {
+        *log.0.borrow_mut::<WorldEventLog>()? = WorldEventLog::default();
+    }
+    Ok(())
+}
+
+/// Deliver all events recorded for a `World` or [`SharedWorld`] userdata since the last flush to
+/// their subscribers, in the order they happened. Subscribers are called with the entity the event
+/// concerns, and may freely change the world; any events caused by them are delivered by the next
+/// flush.
+///
+/// The world must not be borrowed while flushing, since subscribers will usually want to modify it.
+/// Every event is delivered to every subscriber even if some of them fail, in which case the first
+/// error is returned once all events have been delivered.
+pub fn flush_events<'lua>(lua: &'lua Lua, world: &AnyUserData<'lua>) -> Result<()> {
+    let log = match log_of(lua, world)? {
+        Some(log) => log,
+        None => return Ok(()),
+    };
+    let pending = std::mem::take(&mut log.0.borrow_mut::<WorldEventLog>()?.pending);
+
+    let mut first_error = None;
+    for event in pending {
+        let entity = match event {
+            WorldEvent::Added(entity, _)
+            | WorldEvent::Removed(entity, _)
+            | WorldEvent::Despawned(entity) => entity,
+        };
+
+        // Collect the subscribers up front, since they may subscribe or unsubscribe others.
+        let subscribers = log
+            .0
+            .borrow::<WorldEventLog>()?
+            .subscribers(&event)
+            .iter()
+            .map(|key| lua.registry_value::<Function>(key))
+            .collect::<Result<Vec<_>>>()?;
+
+        for subscriber in subscribers {
+            if let Err(err) = subscriber.call::<_, ()>(entity) {
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+
+    match first_error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,458 @@
+use std::{
+    any::{Any, TypeId},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use hv_alchemy::Type;
+use hv_ecs as ecs;
+
+use crate::{
+    hv::ecs::{type_objects::register_component_type_object, ComponentType},
+    AnyUserData, Error, Lua, MetaMethod, MultiValue, RegistryKey, Result, Table, UserData,
+    UserDataMethods, Value,
+};
+
+/// The maximum number of Lua component types which can be in use at once, across all Lua states
+/// in the process.
+pub const MAX_LUA_COMPONENT_TYPES: usize = 64;
+
+/// The slots in use by Lua component declarations, as a bit set. Slots are allocated process-wide
+/// because a slot's `TypeId` is: two declarations sharing a slot, even from different Lua states,
+/// would be the same component type to any world they meet in.
+static SLOTS_IN_USE: AtomicU64 = AtomicU64::new(0);
+
+/// What a Lua component declaration shares with its instances. Its slot is freed once the
+/// declaration and every instance of it are gone, so that no world can still hold components of
+/// the slot when it is handed out again.
+struct Declaration {
+    name: String,
+    slot: usize,
+}
+
+impl Declaration {
+    fn new(name: &str) -> Result<Self> {
+        let mut in_use = SLOTS_IN_USE.load(Ordering::Relaxed);
+        loop {
+            let slot = (!in_use).trailing_zeros() as usize;
+            if slot >= MAX_LUA_COMPONENT_TYPES {
+                return Err(Error::external(format!(
+                    "cannot declare Lua component `{}`: all {} Lua component types are in use!",
+                    name, MAX_LUA_COMPONENT_TYPES
+                )));
+            }
+
+            match SLOTS_IN_USE.compare_exchange_weak(
+                in_use,
+                in_use | 1 << slot,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Ok(Self {
+                        name: name.to_owned(),
+                        slot,
+                    })
+                }
+                Err(actual) => in_use = actual,
+            }
+        }
+    }
+}
+
+impl Drop for Declaration {
+    fn drop(&mut self) {
+        SLOTS_IN_USE.fetch_and(!(1 << self.slot), Ordering::AcqRel);
+    }
+}
+
+/// A component defined from Lua, holding a Lua table through a registry reference.
+///
+/// `hecs` tells component types apart by their [`TypeId`], so every live Lua component declaration
+/// is assigned its own "slot" `SLOT`, and `LuaComponent<SLOT>` is the component type its instances
+/// are stored as. Indexing a `LuaComponent` from Lua reads and writes the fields of its table.
+/// Cloning one shares the table rather than copying it.
+///
+/// The table lives in the registry of the Lua state which created the component. Although the
+/// component is `Send + Sync` so that it can be stored in a shared world, its table can only be
+/// reached from that state: accessing it from any other state is an error.
+#[derive(Clone)]
+pub struct LuaComponent<const SLOT: usize> {
+    declaration: Arc<Declaration>,
+    table: Arc<RegistryKey>,
+}
+
+impl<const SLOT: usize> LuaComponent<SLOT> {
+    /// The table holding this component's fields. Fails if `lua` isn't the state which created
+    /// this component.
+    pub fn table<'lua>(&self, lua: &'lua Lua) -> Result<Table<'lua>> {
+        if !lua.owns_registry_value(&self.table) {
+            return Err(Error::external(format!(
+                "Lua component `{}` belongs to another Lua state!",
+                self.declaration.name
+            )));
+        }
+
+        lua.registry_value(&self.table)
+    }
+}
+
+impl<const SLOT: usize> UserData for LuaComponent<SLOT> {
+    fn on_metatable_init(table: Type<Self>) {
+        table.add_clone().add_send().add_sync();
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::Index, |lua, this, key: Value| {
+            this.table(lua)?.get::<_, Value>(key)
+        });
+
+        // Lua components are mutable through shared references, same as any other table.
+        methods.add_meta_method(
+            MetaMethod::NewIndex,
+            |lua, this, (key, value): (Value, Value)| this.table(lua)?.set(key, value),
+        );
+
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(format!(
+                "{}: {:p}",
+                this.declaration.name,
+                Arc::as_ptr(&this.table)
+            ))
+        });
+    }
+}
+
+/// Type-erased operations on a `LuaComponent<SLOT>`, for a slot only known at runtime.
+trait LuaComponentSlot: Send + Sync {
+    fn component_type(&self) -> Box<dyn ComponentType>;
+
+    fn create<'lua>(
+        &self,
+        lua: &'lua Lua,
+        declaration: Arc<Declaration>,
+        table: Table<'lua>,
+    ) -> Result<AnyUserData<'lua>>;
+}
+
+struct Slot<const SLOT: usize>;
+
+impl<const SLOT: usize> LuaComponentSlot for Slot<SLOT> {
+    fn component_type(&self) -> Box<dyn ComponentType> {
+        Box::new(Type::<LuaComponent<SLOT>>::of())
+    }
+
+    fn create<'lua>(
+        &self,
+        lua: &'lua Lua,
+        declaration: Arc<Declaration>,
+        table: Table<'lua>,
+    ) -> Result<AnyUserData<'lua>> {
+        lua.create_userdata(LuaComponent::<SLOT> {
+            declaration,
+            table: Arc::new(lua.create_registry_value(table)?),
+        })
+    }
+}
+
+macro_rules! slots {
+    ($($slot:literal)*) => {
+        [$(&Slot::<$slot> as &dyn LuaComponentSlot),*]
+    };
+}
+
+static SLOTS: [&dyn LuaComponentSlot; MAX_LUA_COMPONENT_TYPES] = slots!(
+    0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
+    32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50 51 52 53 54 55 56 57 58 59 60 61 62 63
+);
+
+/// A component type declared from Lua, with a name and an optional schema.
+///
+/// The schema, if present, is a table mapping every field the component may have to its default
+/// value. Creating an instance fills in the defaults for any missing fields, and rejects fields
+/// not in the schema or whose type differs from a non-nil default.
+///
+/// A declaration can be used anywhere a component type object can, such as in queries or
+/// `World:get`/`World:remove_one`, and calling it creates a new instance of the component.
+pub struct LuaComponentType {
+    declaration: Arc<Declaration>,
+    schema: Option<Arc<RegistryKey>>,
+    slot: &'static dyn LuaComponentSlot,
+    ty: Box<dyn ComponentType>,
+}
+
+impl LuaComponentType {
+    /// Declare a new Lua component type, assigning it the first free slot. Fails if all
+    /// [`MAX_LUA_COMPONENT_TYPES`] slots are in use.
+    pub fn declare(lua: &Lua, name: &str, schema: Option<Table>) -> Result<Self> {
+        let schema = schema
+            .map(|s| lua.create_registry_value(s).map(Arc::new))
+            .transpose()?;
+        let declaration = Declaration::new(name)?;
+        let slot = SLOTS[declaration.slot];
+
+        Ok(Self {
+            declaration: Arc::new(declaration),
+            schema,
+            slot,
+            ty: slot.component_type(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.declaration.name
+    }
+
+    /// Create an instance of this component from a table of fields, checking it against the
+    /// schema if there is one. Without a schema, the table itself is used as the component.
+    pub fn instantiate<'lua>(
+        &self,
+        lua: &'lua Lua,
+        fields: Option<Table<'lua>>,
+    ) -> Result<AnyUserData<'lua>> {
+        let schema = match &self.schema {
+            Some(schema) => lua.registry_value::<Table>(schema)?,
+            None => {
+                let table = fields.map_or_else(|| lua.create_table(), Ok)?;
+                return self.slot.create(lua, self.declaration.clone(), table);
+            }
+        };
+
+        let table = lua.create_table()?;
+        for pair in schema.clone().pairs::<Value, Value>() {
+            let (key, default) = pair?;
+            table.raw_set(key, default)?;
+        }
+
+        for pair in fields.into_iter().flat_map(|f| f.pairs::<Value, Value>()) {
+            let (key, value) = pair?;
+            let default = schema.raw_get::<_, Value>(key.clone())?;
+            match default {
+                Value::Nil => {
+                    return Err(Error::external(format!(
+                        "Lua component `{}` has no field `{}` in its schema",
+                        self.name(),
+                        key_name(&key)
+                    )))
+                }
+                default if type_name(&default) != type_name(&value) => {
+                    return Err(Error::external(format!(
+                        "field `{}` of Lua component `{}` should be a {}, not a {}",
+                        key_name(&key),
+                        self.name(),
+                        type_name(&default),
+                        type_name(&value)
+                    )))
+                }
+                _ => table.raw_set(key, value)?,
+            }
+        }
+
+        self.slot.create(lua, self.declaration.clone(), table)
+    }
+}
+
+impl Clone for LuaComponentType {
+    fn clone(&self) -> Self {
+        Self {
+            declaration: self.declaration.clone(),
+            schema: self.schema.clone(),
+            slot: self.slot,
+            ty: self.ty.boxed(),
+        }
+    }
+}
+
+// Integers and floats are both Lua numbers as far as schemas are concerned.
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Integer(_) => "number",
+        other => other.type_name(),
+    }
+}
+
+fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.to_string_lossy().into_owned(),
+        other => format!("{:?}", other),
+    }
+}
+
+impl ComponentType for LuaComponentType {
+    fn type_id(&self) -> TypeId {
+        ComponentType::type_id(&*self.ty)
+    }
+
+    fn read(&self) -> ecs::DynamicQuery {
+        self.ty.read()
+    }
+
+    fn write(&self) -> ecs::DynamicQuery {
+        self.ty.write()
+    }
+
+    fn read_optional(&self) -> ecs::DynamicQuery {
+        self.ty.read_optional()
+    }
+
+    fn write_optional(&self) -> ecs::DynamicQuery {
+        self.ty.write_optional()
+    }
+
+    fn with(&self) -> ecs::DynamicQuery {
+        self.ty.with()
+    }
+
+    fn without(&self) -> ecs::DynamicQuery {
+        self.ty.without()
+    }
+
+    fn column_batch_type_add(&self, column_batch_type: &mut ecs::ColumnBatchType) {
+        self.ty.column_batch_type_add(column_batch_type)
+    }
+
+    unsafe fn column_batch_builder_writer<'lua, 'a>(
+        &self,
+        lua: &'lua Lua,
+        column_batch: &'a mut ecs::ColumnBatchBuilder,
+    ) -> Result<(Box<dyn Send + 'a>, AnyUserData<'lua>)> {
+        self.ty.column_batch_builder_writer(lua, column_batch)
+    }
+
+    fn column_batch_builder_fill<'lua>(
+        &self,
+        column_batch_builder: &mut ecs::ColumnBatchBuilder,
+        components: &[AnyUserData<'lua>],
+    ) -> Result<()> {
+        self.ty
+            .column_batch_builder_fill(column_batch_builder, components)
+    }
+
+    fn dynamic_item_take<'lua>(
+        &self,
+        lua: &'lua Lua,
+        dynamic_item: &mut ecs::DynamicItem,
+    ) -> Result<Option<AnyUserData<'lua>>> {
+        self.ty.dynamic_item_take(lua, dynamic_item)
+    }
+
+    fn dynamic_item_take_component(
+        &self,
+        dynamic_item: &mut ecs::DynamicItem,
+    ) -> Option<Box<dyn Any + Send>> {
+        self.ty.dynamic_item_take_component(dynamic_item)
+    }
+
+    fn dynamic_component_to_userdata<'lua>(
+        &self,
+        lua: &'lua Lua,
+        component: Box<dyn Any + Send>,
+    ) -> Result<AnyUserData<'lua>> {
+        self.ty.dynamic_component_to_userdata(lua, component)
+    }
+
+    fn dynamic_component_get<'lua>(
+        &self,
+        lua: &'lua Lua,
+        component: &(dyn Any + Send),
+    ) -> Result<(Box<dyn Send>, AnyUserData<'lua>)> {
+        self.ty.dynamic_component_get(lua, component)
+    }
+
+    unsafe fn dynamic_component_get_mut<'lua>(
+        &self,
+        lua: &'lua Lua,
+        component: &mut (dyn Any + Send),
+    ) -> Result<(Box<dyn Send>, AnyUserData<'lua>)> {
+        self.ty.dynamic_component_get_mut(lua, component)
+    }
+
+    fn world_get<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &ecs::World,
+        entity: ecs::Entity,
+    ) -> Result<AnyUserData<'lua>> {
+        self.ty.world_get(lua, world, entity)
+    }
+
+    fn world_has(&self, world: &ecs::World, entity: ecs::Entity) -> Result<bool> {
+        self.ty.world_has(world, entity)
+    }
+
+    fn world_borrow<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &ecs::World,
+        entity: ecs::Entity,
+        f: &mut dyn FnMut(AnyUserData<'lua>) -> Result<MultiValue<'lua>>,
+    ) -> Result<MultiValue<'lua>> {
+        self.ty.world_borrow(lua, world, entity, f)
+    }
+
+    fn world_borrow_mut<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &ecs::World,
+        entity: ecs::Entity,
+        f: &mut dyn FnMut(AnyUserData<'lua>) -> Result<MultiValue<'lua>>,
+    ) -> Result<MultiValue<'lua>> {
+        self.ty.world_borrow_mut(lua, world, entity, f)
+    }
+
+    fn world_remove_one<'lua>(
+        &self,
+        lua: &'lua Lua,
+        world: &mut ecs::World,
+        entity: ecs::Entity,
+    ) -> Result<AnyUserData<'lua>> {
+        self.ty.world_remove_one(lua, world, entity)
+    }
+
+    fn world_discard_one(&self, world: &mut ecs::World, entity: ecs::Entity) -> bool {
+        self.ty.world_discard_one(world, entity)
+    }
+
+    // The copy shares the declaration, so the slot can't be handed out to another declaration
+    // while the copy is still around, for example in a command buffer which hasn't run yet.
+    fn boxed(&self) -> Box<dyn ComponentType> {
+        Box::new(self.clone())
+    }
+}
+
+impl UserData for LuaComponentType {
+    fn on_metatable_init(table: Type<Self>) {
+        table.add_send().add_sync().add::<dyn ComponentType>();
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("name", |_, this, ()| Ok(this.name().to_owned()));
+
+        methods.add_method("new", |lua, this, fields: Option<Table>| {
+            this.instantiate(lua, fields)
+        });
+
+        methods.add_meta_method(MetaMethod::Call, |lua, this, fields: Option<Table>| {
+            this.instantiate(lua, fields)
+        });
+
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(format!("LuaComponentType({})", this.name()))
+        });
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M)
+    where
+        Self: 'static,
+    {
+        methods.add_function("declare", |lua, (name, schema): (String, Option<Table>)| {
+            let declared = LuaComponentType::declare(lua, &name, schema)?;
+            let type_id = ComponentType::type_id(&declared);
+            let ty = lua.create_userdata(declared)?;
+            register_component_type_object(lua, type_id, &ty)?;
+            Ok(ty)
+        });
+    }
+}
@@ -0,0 +1,172 @@
+use std::any::{Any, TypeId};
+
+use hv_alchemy::Type;
+use hv_cell::ArcCell;
+use hv_elastic::{Elastic, StretchedMut, StretchedRef};
+use rustc_hash::FxHashMap;
+
+use crate::{
+    hv::alchemy::MetaType, AnyUserData, Error, Function, Lua, MultiValue, Result, UserData,
+    UserDataMethods,
+};
+
+/// Type-erased access to a resource's cell.
+trait ErasedResource: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn clone_cell(&self) -> Box<dyn ErasedResource>;
+
+    fn cell<'lua>(&self, lua: &'lua Lua) -> Result<AnyUserData<'lua>>;
+    fn with<'lua>(&self, lua: &'lua Lua, f: Function<'lua>) -> Result<MultiValue<'lua>>;
+    fn with_mut<'lua>(&self, lua: &'lua Lua, f: Function<'lua>) -> Result<MultiValue<'lua>>;
+}
+
+impl<T: 'static + UserData + Send + Sync> ErasedResource for ArcCell<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn clone_cell(&self) -> Box<dyn ErasedResource> {
+        Box::new(self.clone())
+    }
+
+    fn cell<'lua>(&self, lua: &'lua Lua) -> Result<AnyUserData<'lua>> {
+        lua.create_userdata(self.clone())
+    }
+
+    fn with<'lua>(&self, lua: &'lua Lua, f: Function<'lua>) -> Result<MultiValue<'lua>> {
+        let borrowed = self.try_borrow().map_err(|_| {
+            Error::external(format!(
+                "resource `{}` is already mutably borrowed!",
+                std::any::type_name::<T>()
+            ))
+        })?;
+        let elastic = <Elastic<StretchedRef<T>>>::new();
+        let guard = elastic.loan(&*borrowed);
+        let out = f.call(lua.create_userdata(elastic)?);
+        drop(guard);
+        out
+    }
+
+    fn with_mut<'lua>(&self, lua: &'lua Lua, f: Function<'lua>) -> Result<MultiValue<'lua>> {
+        let mut borrowed = self.try_borrow_mut().map_err(|_| {
+            Error::external(format!(
+                "resource `{}` is already borrowed!",
+                std::any::type_name::<T>()
+            ))
+        })?;
+        let elastic = <Elastic<StretchedMut<T>>>::new();
+        let guard = elastic.loan(&mut *borrowed);
+        let out = f.call(lua.create_userdata(elastic)?);
+        drop(guard);
+        out
+    }
+}
+
+/// A store of singleton values ("resources") such as time, input state or an RNG, shared between
+/// Rust and Lua and looked up by type.
+///
+/// Every resource lives in its own [`ArcCell`], so it's borrow checked at runtime the same way
+/// components borrowed from queries are: any number of shared borrows, or a single mutable one.
+/// From Lua, resources are looked up by the type object of their type; `with` and `with_mut` borrow
+/// a resource for the duration of a call to a function, which is passed a reference exposing its
+/// methods and fields. The reference is revoked once the function returns.
+#[derive(Default)]
+pub struct Resources {
+    resources: FxHashMap<TypeId, Box<dyn ErasedResource>>,
+}
+
+impl Resources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a resource, returning the cell of the resource of the same type it replaces.
+    pub fn insert_resource<T>(&mut self, resource: T) -> Option<ArcCell<T>>
+    where
+        T: 'static + UserData + Send + Sync,
+    {
+        self.resources
+            .insert(TypeId::of::<T>(), Box::new(ArcCell::new(resource)))
+            .map(|old| Self::downcast::<T>(&*old).clone())
+    }
+
+    /// Remove a resource, returning its cell.
+    pub fn remove_resource<T>(&mut self) -> Option<ArcCell<T>>
+    where
+        T: 'static + UserData + Send + Sync,
+    {
+        self.resources
+            .remove(&TypeId::of::<T>())
+            .map(|old| Self::downcast::<T>(&*old).clone())
+    }
+
+    /// Get a shared handle to a resource's cell.
+    pub fn get_resource<T>(&self) -> Option<ArcCell<T>>
+    where
+        T: 'static + UserData + Send + Sync,
+    {
+        self.resources
+            .get(&TypeId::of::<T>())
+            .map(|resource| Self::downcast::<T>(&**resource).clone())
+    }
+
+    pub fn contains_resource<T: 'static>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<T>())
+    }
+
+    /// A store sharing the cells of just the resources of the given types with this one, or an
+    /// error naming the first of them which is missing.
+    pub(crate) fn subset(&self, types: &[(TypeId, &'static str)]) -> Result<Self> {
+        let mut resources = FxHashMap::default();
+        for &(type_id, type_name) in types {
+            let resource = self
+                .resources
+                .get(&type_id)
+                .ok_or_else(|| Error::external(format!("no resource of type `{}`", type_name)))?;
+            resources.insert(type_id, resource.clone_cell());
+        }
+        Ok(Self { resources })
+    }
+
+    fn downcast<T: 'static>(resource: &dyn ErasedResource) -> &ArcCell<T> {
+        resource.as_any().downcast_ref().unwrap()
+    }
+
+    fn get_by_type_object(&self, ty: &AnyUserData) -> Result<&dyn ErasedResource> {
+        let type_table = ty.dyn_borrow::<dyn MetaType>()?.type_table_of_subject();
+        self.resources
+            .get(&type_table.id)
+            .map(|resource| &**resource)
+            .ok_or_else(|| {
+                Error::external(format!("no resource of type `{}`", type_table.type_name))
+            })
+    }
+}
+
+impl UserData for Resources {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("has", |_, this, ty: AnyUserData| {
+            let type_table = ty.dyn_borrow::<dyn MetaType>()?.type_table_of_subject();
+            Ok(this.resources.contains_key(&type_table.id))
+        });
+
+        methods.add_method("get", |lua, this, ty: AnyUserData| {
+            this.get_by_type_object(&ty)?.cell(lua)
+        });
+
+        methods.add_method("with", |lua, this, (ty, f): (AnyUserData, Function)| {
+            this.get_by_type_object(&ty)?.with(lua, f)
+        });
+
+        methods.add_method("with_mut", |lua, this, (ty, f): (AnyUserData, Function)| {
+            this.get_by_type_object(&ty)?.with_mut(lua, f)
+        });
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M)
+    where
+        Self: 'static,
+    {
+        methods.add_function("new", |_, ()| Ok(Self::new()));
+    }
+}
@@ -0,0 +1,554 @@
+use std::{any::TypeId, fmt, sync::Arc};
+
+use hv_alchemy::{AlchemicalAny, Type};
+use hv_ecs as ecs;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{
+    de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    ser::{self, SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    hv::{
+        alchemy::MetaType,
+        ecs::{borrow_world, borrow_world_mut, ComponentType},
+    },
+    serde::Deserializer as LuaDeserializer,
+    AnyUserData, Error, Lua, LuaSerdeExt, Result, Table, UserData, UserDataMethods, Value,
+};
+
+/// A mapping from the entity IDs stored in a scene to the entities they were loaded as.
+#[derive(Debug, Default, Clone)]
+pub struct EntityMap(FxHashMap<ecs::Entity, ecs::Entity>);
+
+impl EntityMap {
+    /// The entity a serialized entity was loaded as, if it was part of the scene.
+    pub fn get(&self, entity: ecs::Entity) -> Option<ecs::Entity> {
+        self.0.get(&entity).copied()
+    }
+
+    /// Map an entity from the scene to the entity it was loaded as. Entities which weren't part of
+    /// the scene are left as they are.
+    pub fn map(&self, entity: ecs::Entity) -> ecs::Entity {
+        self.get(entity).unwrap_or(entity)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ecs::Entity, ecs::Entity)> + '_ {
+        self.0.iter().map(|(&from, &to)| (from, to))
+    }
+}
+
+/// Components which refer to other entities, and need those references remapped when loaded from
+/// a scene.
+pub trait MapEntities {
+    fn map_entities(&mut self, map: &EntityMap);
+}
+
+impl MapEntities for ecs::Entity {
+    fn map_entities(&mut self, map: &EntityMap) {
+        *self = map.map(*self);
+    }
+}
+
+impl<T: MapEntities> MapEntities for Option<T> {
+    fn map_entities(&mut self, map: &EntityMap) {
+        if let Some(inner) = self {
+            inner.map_entities(map);
+        }
+    }
+}
+
+impl<T: MapEntities> MapEntities for Vec<T> {
+    fn map_entities(&mut self, map: &EntityMap) {
+        for inner in self {
+            inner.map_entities(map);
+        }
+    }
+}
+
+/// A component deserialized from a scene, waiting for its entity references to be remapped before
+/// being added to its entity.
+trait LoadedComponent {
+    fn map_entities(&mut self, map: &EntityMap);
+    fn add_to(self: Box<Self>, builder: &mut ecs::EntityBuilder);
+}
+
+struct Loaded<T> {
+    component: T,
+    map_entities: Option<fn(&mut T, &EntityMap)>,
+}
+
+impl<T: ecs::Component> LoadedComponent for Loaded<T> {
+    fn map_entities(&mut self, map: &EntityMap) {
+        if let Some(map_entities) = self.map_entities {
+            map_entities(&mut self.component, map);
+        }
+    }
+
+    fn add_to(self: Box<Self>, builder: &mut ecs::EntityBuilder) {
+        builder.add(self.component);
+    }
+}
+
+type SerializeFn = fn(&ecs::EntityRef, &mut dyn FnMut(&dyn erased_serde::Serialize)) -> Result<()>;
+type DeserializeFn = fn(
+    &mut dyn erased_serde::Deserializer,
+) -> std::result::Result<Box<dyn LoadedComponent>, erased_serde::Error>;
+
+struct SceneComponent {
+    name: Arc<str>,
+    ty: Box<dyn ComponentType>,
+    serialize: SerializeFn,
+    deserialize: DeserializeFn,
+}
+
+impl Clone for SceneComponent {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            ty: self.ty.boxed(),
+            serialize: self.serialize,
+            deserialize: self.deserialize,
+        }
+    }
+}
+
+fn serialize_component<T: ecs::Component>(
+    entity: &ecs::EntityRef,
+    f: &mut dyn FnMut(&dyn erased_serde::Serialize),
+) -> Result<()> {
+    let component = entity.get::<T>().ok_or(Error::UserDataTypeMismatch)?;
+    let serialize = (&*component as &dyn AlchemicalAny)
+        .dyncast_ref::<dyn erased_serde::Serialize>()
+        .ok_or_else(|| {
+            Error::external(format!(
+                "component type `{}` is not registered as Serialize!",
+                std::any::type_name::<T>()
+            ))
+        })?;
+    f(serialize);
+    Ok(())
+}
+
+fn deserialize_component<T: ecs::Component + DeserializeOwned>(
+    deserializer: &mut dyn erased_serde::Deserializer,
+) -> std::result::Result<Box<dyn LoadedComponent>, erased_serde::Error> {
+    Ok(Box::new(Loaded {
+        component: erased_serde::deserialize::<T>(deserializer)?,
+        map_entities: None,
+    }))
+}
+
+fn deserialize_mapped_component<T: ecs::Component + DeserializeOwned + MapEntities>(
+    deserializer: &mut dyn erased_serde::Deserializer,
+) -> std::result::Result<Box<dyn LoadedComponent>, erased_serde::Error> {
+    Ok(Box::new(Loaded {
+        component: erased_serde::deserialize::<T>(deserializer)?,
+        map_entities: Some(T::map_entities as fn(&mut T, &EntityMap)),
+    }))
+}
+
+/// A registry of the component types which are saved in and loaded from scenes, by name.
+///
+/// A scene is a sequence of entities, each stored as its ID and a map from component names to
+/// components. Components are serialized through the `erased_serde::Serialize` implementation
+/// registered for their type with `hv_alchemy`, and deserialized as the type registered under
+/// their name. Saving an entity with a component of a type which is neither registered nor
+/// explicitly skipped with [`SceneRegistry::ignore`] is an error.
+///
+/// Loading a scene spawns fresh entities for everything in it, and remaps entity references inside
+/// components registered through [`SceneRegistry::register_mapped`] to the new entities.
+#[derive(Default)]
+pub struct SceneRegistry {
+    components: Vec<SceneComponent>,
+    by_name: FxHashMap<Arc<str>, usize>,
+    by_type: FxHashMap<TypeId, usize>,
+    ignored: FxHashSet<TypeId>,
+}
+
+impl SceneRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a component type under a name. Registering another type under the same name, or
+    /// the same type under another name, replaces the old registration.
+    pub fn register<T>(&mut self, name: &str) -> &mut Self
+    where
+        T: ecs::Component + UserData + DeserializeOwned,
+    {
+        self.insert::<T>(name, deserialize_component::<T>)
+    }
+
+    /// Register a component type which refers to other entities under a name. See
+    /// [`SceneRegistry::register`].
+    pub fn register_mapped<T>(&mut self, name: &str) -> &mut Self
+    where
+        T: ecs::Component + UserData + DeserializeOwned + MapEntities,
+    {
+        self.insert::<T>(name, deserialize_mapped_component::<T>)
+    }
+
+    /// Add all of the registrations and ignored types of another registry to this one, replacing
+    /// registrations under the same names or of the same types.
+    pub fn extend(&mut self, other: &SceneRegistry) -> &mut Self {
+        for component in &other.components {
+            self.insert_component(component.clone());
+        }
+        self.ignored.extend(other.ignored.iter().copied());
+        self
+    }
+
+    fn insert<T: ecs::Component + UserData>(
+        &mut self,
+        name: &str,
+        deserialize: DeserializeFn,
+    ) -> &mut Self {
+        self.insert_component(SceneComponent {
+            name: name.into(),
+            ty: Box::new(Type::<T>::of()),
+            serialize: serialize_component::<T>,
+            deserialize,
+        })
+    }
+
+    fn insert_component(&mut self, component: SceneComponent) -> &mut Self {
+        let name = component.name.clone();
+        let type_id = ComponentType::type_id(&*component.ty);
+        let old_index = self
+            .by_name
+            .get(&name)
+            .or_else(|| self.by_type.get(&type_id))
+            .copied();
+        let index = match old_index {
+            Some(index) => {
+                let old = std::mem::replace(&mut self.components[index], component);
+                self.by_name.remove(&old.name);
+                self.by_type.remove(&ComponentType::type_id(&*old.ty));
+                index
+            }
+            None => {
+                self.components.push(component);
+                self.components.len() - 1
+            }
+        };
+
+        self.by_name.insert(name, index);
+        self.by_type.insert(type_id, index);
+        self
+    }
+
+    /// Leave components of a type out of saved scenes, rather than failing to save entities which
+    /// have them. Useful for components which only make sense at runtime, such as scripts.
+    pub fn ignore<T: ecs::Component>(&mut self) -> &mut Self {
+        self.ignored.insert(TypeId::of::<T>());
+        self
+    }
+
+    /// The type object registered under a name.
+    pub fn component_type(&self, name: &str) -> Option<&dyn ComponentType> {
+        self.by_name
+            .get(name)
+            .map(|&index| &*self.components[index].ty)
+    }
+
+    /// The names of all registered component types.
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.components.iter().map(|component| &*component.name)
+    }
+
+    /// Borrow a world as something which serializes to a scene.
+    pub fn serialize_world<'a>(&'a self, world: &'a ecs::World) -> SerializeWorld<'a> {
+        SerializeWorld {
+            registry: self,
+            world,
+        }
+    }
+
+    /// Load a scene into a world, spawning new entities for all of the entities in it. Returns the
+    /// mapping from the entities in the scene to the entities they were spawned as.
+    pub fn deserialize_world<'de, D: Deserializer<'de>>(
+        &self,
+        world: &mut ecs::World,
+        deserializer: D,
+    ) -> std::result::Result<EntityMap, D::Error> {
+        let entities = deserializer.deserialize_seq(SceneVisitor { registry: self })?;
+
+        let mut map = EntityMap::default();
+        for (id, _) in &entities {
+            map.0.insert(*id, world.reserve_entity());
+        }
+
+        let mut builder = ecs::EntityBuilder::new();
+        for (id, components) in entities {
+            builder.clear();
+            for mut component in components {
+                component.map_entities(&map);
+                component.add_to(&mut builder);
+            }
+            world.spawn_at(map.map(id), builder.build());
+        }
+
+        Ok(map)
+    }
+}
+
+/// A world borrowed along with a [`SceneRegistry`], which serializes to a scene.
+pub struct SerializeWorld<'a> {
+    registry: &'a SceneRegistry,
+    world: &'a ecs::World,
+}
+
+impl<'a> Serialize for SerializeWorld<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        // Entities are saved one at a time, in the order the world iterates them.
+        let mut seq = serializer.serialize_seq(None)?;
+        for entity in self.world.iter() {
+            seq.serialize_element(&SerializeEntity {
+                registry: self.registry,
+                entity,
+            })?;
+        }
+        seq.end()
+    }
+}
+
+struct SerializeEntity<'a> {
+    registry: &'a SceneRegistry,
+    entity: ecs::EntityRef<'a>,
+}
+
+impl<'a> Serialize for SerializeEntity<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("id", &self.entity.entity().to_bits().get())?;
+        map.serialize_entry(
+            "components",
+            &SerializeComponents {
+                registry: self.registry,
+                entity: &self.entity,
+            },
+        )?;
+        map.end()
+    }
+}
+
+struct SerializeComponents<'a, 'b> {
+    registry: &'a SceneRegistry,
+    entity: &'b ecs::EntityRef<'a>,
+}
+
+impl<'a, 'b> Serialize for SerializeComponents<'a, 'b> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for type_id in self.entity.component_types() {
+            let component = match self.registry.by_type.get(&type_id) {
+                Some(&index) => &self.registry.components[index],
+                None if self.registry.ignored.contains(&type_id) => continue,
+                None => {
+                    return Err(ser::Error::custom(format!(
+                        "entity {:?} has a component of type {:?} which is neither registered \
+                         nor ignored by the scene registry",
+                        self.entity.entity(),
+                        type_id
+                    )))
+                }
+            };
+
+            let mut result = Ok(());
+            (component.serialize)(self.entity, &mut |serialize| {
+                result = map.serialize_entry(&*component.name, serialize);
+            })
+            .map_err(ser::Error::custom)?;
+            result?;
+        }
+        map.end()
+    }
+}
+
+type LoadedEntity = (ecs::Entity, Vec<Box<dyn LoadedComponent>>);
+
+struct SceneVisitor<'a> {
+    registry: &'a SceneRegistry,
+}
+
+impl<'a, 'de> Visitor<'de> for SceneVisitor<'a> {
+    type Value = Vec<LoadedEntity>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence of entities")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let mut entities = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(entity) = seq.next_element_seed(EntitySeed {
+            registry: self.registry,
+        })? {
+            entities.push(entity);
+        }
+        Ok(entities)
+    }
+}
+
+struct EntitySeed<'a> {
+    registry: &'a SceneRegistry,
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum EntityField {
+    Id,
+    Components,
+}
+
+impl<'a, 'de> DeserializeSeed<'de> for EntitySeed<'a> {
+    type Value = LoadedEntity;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'a, 'de> Visitor<'de> for EntitySeed<'a> {
+    type Value = LoadedEntity;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an entity with an `id` and `components`")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let mut id = None;
+        let mut components = None;
+        while let Some(field) = map.next_key::<EntityField>()? {
+            match field {
+                EntityField::Id => {
+                    let bits = map.next_value::<u64>()?;
+                    id = Some(ecs::Entity::from_bits(bits).ok_or_else(|| {
+                        de::Error::custom(format!("invalid entity ID `{}`", bits))
+                    })?);
+                }
+                EntityField::Components => {
+                    components = Some(map.next_value_seed(ComponentsSeed {
+                        registry: self.registry,
+                    })?);
+                }
+            }
+        }
+
+        Ok((
+            id.ok_or_else(|| de::Error::missing_field("id"))?,
+            components.ok_or_else(|| de::Error::missing_field("components"))?,
+        ))
+    }
+}
+
+struct ComponentsSeed<'a> {
+    registry: &'a SceneRegistry,
+}
+
+impl<'a, 'de> DeserializeSeed<'de> for ComponentsSeed<'a> {
+    type Value = Vec<Box<dyn LoadedComponent>>;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'a, 'de> Visitor<'de> for ComponentsSeed<'a> {
+    type Value = Vec<Box<dyn LoadedComponent>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of component names to components")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let mut components = Vec::new();
+        while let Some(name) = map.next_key::<String>()? {
+            let component = match self.registry.by_name.get(name.as_str()) {
+                Some(&index) => &self.registry.components[index],
+                None => {
+                    return Err(de::Error::custom(format!(
+                        "no component type registered as `{}`",
+                        name
+                    )))
+                }
+            };
+            components.push(map.next_value_seed(ComponentSeed(component.deserialize))?);
+        }
+        Ok(components)
+    }
+}
+
+struct ComponentSeed(DeserializeFn);
+
+impl<'de> DeserializeSeed<'de> for ComponentSeed {
+    type Value = Box<dyn LoadedComponent>;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Self::Value, D::Error> {
+        let mut erased = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.0)(&mut erased).map_err(de::Error::custom)
+    }
+}
+
+impl UserData for SceneRegistry {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("names", |_, this, ()| {
+            Ok(this.names().map(str::to_owned).collect::<Vec<_>>())
+        });
+
+        methods.add_method_mut("ignore", |_, this, ty: AnyUserData| {
+            let type_table = ty.dyn_borrow::<dyn MetaType>()?.type_table_of_subject();
+            this.ignored.insert(type_table.id);
+            Ok(())
+        });
+
+        methods.add_method("save", |lua, this, world: AnyUserData| {
+            let world = borrow_world(&world)?;
+            lua.to_value(&this.serialize_world(&world))
+        });
+
+        // Returns a table mapping the entities in the scene to the entities they were loaded as.
+        methods.add_method("load", |lua, this, (world, scene): (AnyUserData, Value)| {
+            let mut world = borrow_world_mut(&world)?;
+            let map = this.deserialize_world(&mut world, LuaDeserializer::new(scene))?;
+            let table: Table = lua.create_table()?;
+            for (from, to) in map.iter() {
+                table.raw_set(from, to)?;
+            }
+            Ok(table)
+        });
+
+        methods.add_method_mut("extend", |_, this, other: AnyUserData| {
+            this.extend(&other.borrow::<SceneRegistry>()?);
+            Ok(())
+        });
+    }
+
+    // Component types can only be registered from Rust, so a registry created from Lua starts out
+    // empty and gets its registrations from registries created in Rust, through `extend`.
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M)
+    where
+        Self: 'static,
+    {
+        methods.add_function("new", |_, ()| Ok(Self::new()));
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    any::TypeId,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use hv_alchemy::Type;
+use hv_ecs as ecs;
+
+use crate::{
+    hv::{
+        alchemy::MetaType,
+        ecs::{borrow_world, borrow_world_mut, Query, Resources},
+    },
+    types::MaybeSend,
+    AnyUserData, Error, Function, Lua, RegistryKey, Result, Table, ToLua, UserData,
+    UserDataMethods, Value,
+};
+
+#[cfg(feature = "send")]
+type RustSystemFn = Box<dyn FnMut(&Lua, &mut ecs::World, &Resources) -> Result<()> + Send>;
+#[cfg(not(feature = "send"))]
+type RustSystemFn = Box<dyn FnMut(&Lua, &mut ecs::World, &Resources) -> Result<()>>;
+
+/// The name of the stage systems are added to when they don't specify one.
+pub const DEFAULT_STAGE: &str = "update";
+
+enum SystemKind {
+    Rust(RustSystemFn),
+    Lua {
+        /// Called once per run as `run(world, resources)`.
+        run: Option<RegistryKey>,
+        /// Queries, each with a function called once per matching entity as
+        /// `each(entity, item, resources)`.
+        queries: Vec<(Query, RegistryKey)>,
+        /// The types of the resources this system may access.
+        resources: Vec<(TypeId, &'static str)>,
+    },
+}
+
+struct System {
+    name: Arc<str>,
+    kind: SystemKind,
+}
+
+struct Stage {
+    name: Arc<str>,
+    systems: Vec<System>,
+}
+
+/// The outcome of running a single system as part of a [`Schedule`].
+#[derive(Debug, Clone)]
+pub struct SystemReport {
+    pub stage: Arc<str>,
+    pub system: Arc<str>,
+    pub duration: Duration,
+    pub error: Option<Error>,
+}
+
+impl<'lua> ToLua<'lua> for SystemReport {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        let table = lua.create_table()?;
+        table.set("stage", &*self.stage)?;
+        table.set("system", &*self.system)?;
+        table.set("time", self.duration.as_secs_f64())?;
+        table.set("error", self.error.map(|err| err.to_string()))?;
+        Ok(Value::Table(table))
+    }
+}
+
+/// An ordered list of stages, each containing an ordered list of systems, which can be run against
+/// a [`World`](ecs::World). Systems may be written in Rust or in Lua, and can be freely
+/// interleaved.
+///
+/// A Lua system is declared from a table with the fields:
+/// - `name`: the name of the system, used in reports.
+/// - `stage` (optional): the stage to add the system to; defaults to [`DEFAULT_STAGE`].
+/// - `resources` (optional): a list of the type objects of the resources the system accesses.
+/// - `run` (optional): a function called once per run of the schedule, as `run(world, resources)`.
+/// - `query` and `each` (optional): a query, and a function called once per matching entity as
+///   `each(entity, item, resources)`. The world is borrowed while `each` is running, so
+///   structural changes have to go through a [`CommandBuffer`](super::CommandBuffer).
+/// - `queries` (optional): a list of `{ query = ..., each = ... }` tables, for systems iterating
+///   over more than one query. The queries are run in order, after `query` and `each`.
+///
+/// `resources` is a [`Resources`] store sharing only the resources the system declared with the
+/// store passed to [`Schedule::run`]; the system fails without running if any of them is missing.
+/// Rust systems are passed the whole store.
+///
+/// Running the schedule runs every system in order, even if some of them fail, and reports how
+/// long each system took and what error it raised, if any.
+pub struct Schedule {
+    stages: Vec<Stage>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Schedule {
+    /// Create a schedule with a single stage, [`DEFAULT_STAGE`].
+    pub fn new() -> Self {
+        Self::with_stages(&[DEFAULT_STAGE])
+    }
+
+    /// Create a schedule with the given stages, which will be run in order.
+    pub fn with_stages<S: AsRef<str>>(stages: &[S]) -> Self {
+        let mut this = Self { stages: Vec::new() };
+        for stage in stages {
+            this.add_stage(stage.as_ref());
+        }
+        this
+    }
+
+    /// Add a stage to be run after all existing stages. Does nothing if the stage already exists.
+    pub fn add_stage(&mut self, name: &str) {
+        if self.stage_mut(name).is_err() {
+            self.stages.push(Stage {
+                name: name.into(),
+                systems: Vec::new(),
+            });
+        }
+    }
+
+    fn stage_mut(&mut self, name: &str) -> Result<&mut Stage> {
+        self.stages
+            .iter_mut()
+            .find(|stage| &*stage.name == name)
+            .ok_or_else(|| Error::external(format!("no such stage `{}`", name)))
+    }
+
+    /// Add a Rust system to the end of a stage.
+    pub fn add_rust_system<F>(&mut self, stage: &str, name: &str, system: F) -> Result<()>
+    where
+        F: 'static + MaybeSend + FnMut(&Lua, &mut ecs::World, &Resources) -> Result<()>,
+    {
+        self.add_system(stage, name, SystemKind::Rust(Box::new(system)))
+    }
+
+    /// Add a Lua system to the end of its stage, from a table declaring it. See [`Schedule`] for
+    /// the fields of the table.
+    pub fn add_lua_system(&mut self, lua: &Lua, system: Table) -> Result<()> {
+        let name = system.get::<_, String>("name")?;
+        let stage = system
+            .get::<_, Option<String>>("stage")?
+            .unwrap_or_else(|| DEFAULT_STAGE.to_owned());
+        let run = system
+            .get::<_, Option<Function>>("run")?
+            .map(|f| lua.create_registry_value(f))
+            .transpose()?;
+        let mut queries = Vec::new();
+        queries.extend(Self::lua_query(lua, &name, &system)?);
+        for query in system
+            .get::<_, Option<Vec<Table>>>("queries")?
+            .unwrap_or_default()
+        {
+            match Self::lua_query(lua, &name, &query)? {
+                Some(query) => queries.push(query),
+                None => {
+                    return Err(Error::external(format!(
+                        "every entry of the `queries` of system `{}` must have a `query` and an \
+                         `each` function",
+                        name
+                    )))
+                }
+            }
+        }
+
+        if run.is_none() && queries.is_empty() {
+            return Err(Error::external(format!(
+                "system `{}` must have a `run` or an `each` function",
+                name
+            )));
+        }
+
+        let resources = system
+            .get::<_, Option<Vec<AnyUserData>>>("resources")?
+            .unwrap_or_default()
+            .iter()
+            .map(|ty| {
+                let type_table = ty.dyn_borrow::<dyn MetaType>()?.type_table_of_subject();
+                Ok((type_table.id, type_table.type_name))
+            })
+            .collect::<Result<_>>()?;
+
+        self.add_system(
+            &stage,
+            &name,
+            SystemKind::Lua {
+                run,
+                queries,
+                resources,
+            },
+        )
+    }
+
+    // The `query` and `each` fields of a table, if it has them.
+    fn lua_query(lua: &Lua, name: &str, table: &Table) -> Result<Option<(Query, RegistryKey)>> {
+        match (
+            table.get::<_, Option<AnyUserData>>("query")?,
+            table.get::<_, Option<Function>>("each")?,
+        ) {
+            (Some(query), Some(each)) => Ok(Some((
+                Query::from_user_data(&query)?,
+                lua.create_registry_value(each)?,
+            ))),
+            (None, None) => Ok(None),
+            _ => Err(Error::external(format!(
+                "system `{}` must declare both `query` and `each`, or neither",
+                name
+            ))),
+        }
+    }
+
+    fn add_system(&mut self, stage: &str, name: &str, kind: SystemKind) -> Result<()> {
+        self.stage_mut(stage)?.systems.push(System {
+            name: name.into(),
+            kind,
+        });
+        Ok(())
+    }
+
+    /// Run every system in every stage, in order, against a world userdata. Lua systems get the
+    /// resources they declare from `resources`.
+    pub fn run<'lua>(
+        &mut self,
+        lua: &'lua Lua,
+        world: &AnyUserData<'lua>,
+        resources: &Resources,
+    ) -> Vec<SystemReport> {
+        let mut reports = Vec::new();
+        for stage in &mut self.stages {
+            for system in &mut stage.systems {
+                let start = Instant::now();
+                let result = system.run(lua, world, resources);
+                reports.push(SystemReport {
+                    stage: stage.name.clone(),
+                    system: system.name.clone(),
+                    duration: start.elapsed(),
+                    error: result.err(),
+                });
+            }
+        }
+        reports
+    }
+}
+
+impl System {
+    fn run<'lua>(
+        &mut self,
+        lua: &'lua Lua,
+        world: &AnyUserData<'lua>,
+        resources: &Resources,
+    ) -> Result<()> {
+        match &mut self.kind {
+            SystemKind::Rust(system) => system(lua, &mut borrow_world_mut(world)?, resources),
+            SystemKind::Lua {
+                run,
+                queries,
+                resources: declared,
+            } => {
+                let view = resources.subset(declared).map_err(|err| {
+                    Error::external(format!("system `{}` can't run: {}", self.name, err))
+                })?;
+                let view = lua.create_userdata(view)?;
+
+                if let Some(run) = run {
+                    lua.registry_value::<Function>(run)?
+                        .call::<_, ()>((world.clone(), view.clone()))?;
+                }
+
+                for (query, each) in queries.iter() {
+                    let each = lua.registry_value::<Function>(each)?;
+                    let world = borrow_world(world)?;
+                    let mut dynamic_query = world.dynamic_query(&query.query);
+                    for (entity, item) in dynamic_query
+                        .iter()
+                        .filter(|(entity, _)| query.matches(&world, *entity))
+                    {
+                        let item = query.item(item);
+                        let handle = item.handle();
+                        let result = each.call::<_, ()>((entity, item, view.clone()));
+                        handle.release();
+                        result?;
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl UserData for Schedule {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("add_stage", |_, this, name: String| {
+            this.add_stage(&name);
+            Ok(())
+        });
+
+        methods.add_method_mut("add_system", |lua, this, system: Table| {
+            this.add_lua_system(lua, system)
+        });
+
+        methods.add_method_mut(
+            "run",
+            |lua, this, (world, resources): (AnyUserData, Option<AnyUserData>)| match resources {
+                Some(resources) => Ok(this.run(lua, &world, &*resources.borrow::<Resources>()?)),
+                None => Ok(this.run(lua, &world, &Resources::new())),
+            },
+        );
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M) {
+        methods.add_function("new", |_, stages: Option<Vec<String>>| {
+            Ok(match stages {
+                Some(stages) => Self::with_stages(&stages),
+                None => Self::new(),
+            })
+        });
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use hv_alchemy::Type;
+use hv_ecs as ecs;
+
+use crate::{
+    hv::{
+        ecs::{borrow_world, borrow_world_mut, events},
+        LuaUserDataTypeExt, LuaUserDataTypeTypeExt,
+    },
+    AnyUserData, Error, Function, Lua, RegistryKey, Result, Table, TableExt, Thread, ThreadStatus,
+    UserData, UserDataMethods, Value,
+};
+
+enum ScriptKind {
+    /// Resumed every tick as `coroutine.resume(co, entity, dt)`, so `coroutine.yield()` returns
+    /// the entity and the time step of the next tick.
+    Coroutine(RegistryKey),
+    /// A table with an `update` method, called every tick as `self:update(dt, entity)`.
+    Behaviour(RegistryKey),
+}
+
+/// Whether a coroutine script has more to run. A preempted coroutine is resumed where it was
+/// interrupted.
+fn is_running(status: ThreadStatus) -> bool {
+    matches!(status, ThreadStatus::Resumable | ThreadStatus::Preempted)
+}
+
+/// A behaviour script attached to an entity: either a coroutine, or a table with an `update`
+/// method. Scripts are ticked by [`run_scripts`].
+///
+/// A coroutine script which returns is finished, and is removed from its entity by the next run.
+/// Cloning a script shares the underlying coroutine or table rather than copying it.
+///
+/// The coroutine or table lives in the registry of the Lua state which created the script, so a
+/// script can only be run by that state, even if its entity is in a world shared with others.
+#[derive(Clone)]
+pub struct Script(Arc<ScriptKind>);
+
+impl Script {
+    /// Create a script from a coroutine, a function to run as a coroutine, or a table with an
+    /// `update` method.
+    pub fn new<'lua>(lua: &'lua Lua, script: Value<'lua>) -> Result<Self> {
+        let kind = match script {
+            Value::Thread(thread) => ScriptKind::Coroutine(lua.create_registry_value(thread)?),
+            Value::Function(f) => {
+                ScriptKind::Coroutine(lua.create_registry_value(lua.create_thread(f)?)?)
+            }
+            Value::Table(table) => {
+                if table.get::<_, Option<Function>>("update")?.is_none() {
+                    return Err(Error::external(
+                        "a script table must have an `update` method",
+                    ));
+                }
+                ScriptKind::Behaviour(lua.create_registry_value(table)?)
+            }
+            other => {
+                return Err(Error::external(format!(
+                    "expected a coroutine, a function or a table, got a {}",
+                    other.type_name()
+                )))
+            }
+        };
+
+        Ok(Self(Arc::new(kind)))
+    }
+
+    /// Whether this script was created by `lua`, and so can be run by it.
+    pub fn belongs_to(&self, lua: &Lua) -> bool {
+        match &*self.0 {
+            ScriptKind::Coroutine(key) | ScriptKind::Behaviour(key) => lua.owns_registry_value(key),
+        }
+    }
+
+    fn check_owner(&self, lua: &Lua) -> Result<()> {
+        if !self.belongs_to(lua) {
+            return Err(Error::external("this script belongs to another Lua state!"));
+        }
+
+        Ok(())
+    }
+
+    /// Whether this script has nothing left to run. Fails if `lua` didn't create this script.
+    pub fn is_finished(&self, lua: &Lua) -> Result<bool> {
+        self.check_owner(lua)?;
+        match &*self.0 {
+            ScriptKind::Coroutine(key) => {
+                let status = lua.registry_value::<Thread>(key)?.status();
+                Ok(!is_running(status))
+            }
+            ScriptKind::Behaviour(_) => Ok(false),
+        }
+    }
+
+    fn tick(&self, lua: &Lua, entity: ecs::Entity, dt: f64) -> Result<()> {
+        self.check_owner(lua)?;
+        match &*self.0 {
+            ScriptKind::Coroutine(key) => {
+                let thread = lua.registry_value::<Thread>(key)?;
+                if is_running(thread.status()) {
+                    thread.resume::<_, ()>((entity, dt))?;
+                }
+            }
+            ScriptKind::Behaviour(key) => {
+                let table = lua.registry_value::<Table>(key)?;
+                table.call_method::<_, _, ()>("update", (dt, entity))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Tick every script in a `World` or [`SharedWorld`](super::SharedWorld) userdata, returning the
+/// errors raised by scripts along with their entities.
+///
+/// Only the scripts created by `lua` are run: in a world shared by several Lua states, each state
+/// runs its own scripts.
+///
+/// The world isn't borrowed while scripts run, so they're free to change it. Scripts whose
+/// entities are despawned before their turn are skipped, finished scripts are removed from their
+/// entities, and the registry values of dropped scripts are expired at the end of the run.
+pub fn run_scripts<'lua>(
+    lua: &'lua Lua,
+    world: &AnyUserData<'lua>,
+    dt: f64,
+) -> Result<Vec<(ecs::Entity, Error)>> {
+    let scripts = borrow_world(world)?
+        .query::<&Script>()
+        .iter()
+        .filter(|(_, script)| script.belongs_to(lua))
+        .map(|(entity, script)| (entity, script.clone()))
+        .collect::<Vec<_>>();
+
+    let mut errors = Vec::new();
+    let mut finished = Vec::new();
+    for (entity, script) in scripts {
+        if !borrow_world(world)?.contains(entity) {
+            continue;
+        }
+
+        if let Err(err) = script.tick(lua, entity, dt) {
+            errors.push((entity, err));
+        }
+
+        if script.is_finished(lua)? {
+            finished.push((entity, script));
+        }
+    }
+
+    if !finished.is_empty() {
+        let log = events::log_of(lua, world)?;
+        let mut world = borrow_world_mut(world)?;
+        for (entity, script) in finished {
+            // The script may have been replaced by another one since it ran.
+            let is_current = world
+                .get::<Script>(entity)
+                .map_or(false, |current| Arc::ptr_eq(&current.0, &script.0));
+            if is_current {
+                events::tracked(log.as_ref(), &mut world, entity, |world| {
+                    let _ = world.remove_one::<Script>(entity);
+                    Ok(())
+                })?;
+            }
+        }
+    }
+
+    lua.expire_registry_values();
+
+    Ok(errors)
+}
+
+impl UserData for Script {
+    fn on_metatable_init(table: Type<Self>) {
+        table.add_clone().mark_component();
+    }
+
+    fn on_type_metatable_init(table: Type<Type<Self>>) {
+        table.mark_component_type();
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("is_finished", |lua, this, ()| this.is_finished(lua));
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M)
+    where
+        Self: 'static,
+    {
+        methods.add_function("new", |lua, script: Value| Script::new(lua, script));
+
+        // Returns a list of `{ entity = ..., error = ... }` tables, one per failed script.
+        methods.add_function("run", |lua, (world, dt): (AnyUserData, f64)| {
+            run_scripts(lua, &world, dt)?
+                .into_iter()
+                .map(|(entity, err)| {
+                    let report = lua.create_table()?;
+                    report.set("entity", entity)?;
+                    report.set("error", err.to_string())?;
+                    Ok(report)
+                })
+                .collect::<Result<Vec<_>>>()
+        });
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    cell::{Ref, RefMut},
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use hv_cell::{AtomicRef, AtomicRefCell, AtomicRefMut};
+use hv_ecs as ecs;
+
+use crate::{AnyUserData, Error, Result};
+
+/// A world kept on the Rust side and lent to Lua.
+///
+/// Every clone of a `SharedWorld` passed to Lua, in any number of Lua states, exposes the whole
+/// `World` API, borrowing the world for the duration of each call. Rust code borrows it the same
+/// way between script runs, such as once per frame; calls from Lua fail rather than block if the
+/// world is already borrowed incompatibly.
+///
+/// Functions taking a world userdata, such as `Schedule:run` or `CommandBuffer:run_on`, accept
+/// either a `World` or a `SharedWorld`.
+pub type SharedWorld = Arc<AtomicRefCell<ecs::World>>;
+
+/// An owned shared borrow of a [`SharedWorld`].
+// Field order is drop order: the guard borrows from the `Arc`.
+pub(crate) struct SharedWorldRef {
+    guard: AtomicRef<'static, ecs::World>,
+    _world: SharedWorld,
+}
+
+impl SharedWorldRef {
+    fn new(world: SharedWorld) -> Result<Self> {
+        let guard = world
+            .try_borrow()
+            .map_err(|_| Error::UserDataProxyBorrowError)?;
+        // safety: the guard is dropped before the `Arc` keeping the cell alive.
+        let guard = unsafe {
+            std::mem::transmute::<AtomicRef<ecs::World>, AtomicRef<'static, ecs::World>>(guard)
+        };
+        Ok(Self {
+            guard,
+            _world: world,
+        })
+    }
+}
+
+/// An owned mutable borrow of a [`SharedWorld`].
+// Field order is drop order: the guard borrows from the `Arc`.
+pub(crate) struct SharedWorldRefMut {
+    guard: AtomicRefMut<'static, ecs::World>,
+    _world: SharedWorld,
+}
+
+impl SharedWorldRefMut {
+    fn new(world: SharedWorld) -> Result<Self> {
+        let guard = world
+            .try_borrow_mut()
+            .map_err(|_| Error::UserDataProxyBorrowMutError)?;
+        // safety: see `SharedWorldRef::new`.
+        let guard = unsafe {
+            std::mem::transmute::<AtomicRefMut<ecs::World>, AtomicRefMut<'static, ecs::World>>(
+                guard,
+            )
+        };
+        Ok(Self {
+            guard,
+            _world: world,
+        })
+    }
+}
+
+/// A shared borrow of the world held by either a `World` or a [`SharedWorld`] userdata.
+pub(crate) enum WorldRef<'a> {
+    Local(Ref<'a, ecs::World>),
+    Shared(SharedWorldRef),
+}
+
+impl<'a> Deref for WorldRef<'a> {
+    type Target = ecs::World;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Local(world) => world,
+            Self::Shared(world) => &world.guard,
+        }
+    }
+}
+
+/// A mutable borrow of the world held by either a `World` or a [`SharedWorld`] userdata.
+pub(crate) enum WorldRefMut<'a> {
+    Local(RefMut<'a, ecs::World>),
+    Shared(SharedWorldRefMut),
+}
+
+impl<'a> Deref for WorldRefMut<'a> {
+    type Target = ecs::World;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Local(world) => world,
+            Self::Shared(world) => &world.guard,
+        }
+    }
+}
+
+impl<'a> DerefMut for WorldRefMut<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Self::Local(world) => world,
+            Self::Shared(world) => &mut world.guard,
+        }
+    }
+}
+
+/// Borrow the world held by a `World` or [`SharedWorld`] userdata.
+pub(crate) fn borrow_world<'a>(ud: &'a AnyUserData) -> Result<WorldRef<'a>> {
+    if ud.is::<ecs::World>() {
+        Ok(WorldRef::Local(ud.borrow()?))
+    } else {
+        let world = ud.borrow::<SharedWorld>()?.clone();
+        Ok(WorldRef::Shared(SharedWorldRef::new(world)?))
+    }
+}
+
+/// Mutably borrow the world held by a `World` or [`SharedWorld`] userdata.
+pub(crate) fn borrow_world_mut<'a>(ud: &'a AnyUserData) -> Result<WorldRefMut<'a>> {
+    if ud.is::<ecs::World>() {
+        Ok(WorldRefMut::Local(ud.borrow_mut()?))
+    } else {
+        let world = ud.borrow::<SharedWorld>()?.clone();
+        Ok(WorldRefMut::Shared(SharedWorldRefMut::new(world)?))
+    }
+}
@@ -0,0 +1,19 @@
+use std::any::TypeId;
+
+use crate::{hv::registry::TypeRegistry, AnyUserData, Lua, Result};
+
+/// Remember `ty` as the type object of the component type `type_id`, for component types which
+/// have no [`Type`](hv_alchemy::Type) of their own, such as those declared from Lua.
+pub(crate) fn register_component_type_object(
+    lua: &Lua,
+    type_id: TypeId,
+    ty: &AnyUserData,
+) -> Result<()> {
+    TypeRegistry::remember(lua, type_id, ty)
+}
+
+/// The Lua type object of a component type, if one has been created in this Lua state. Type
+/// objects registered with [`Lua::register_type`] take precedence over any others.
+pub fn component_type_object(lua: &Lua, type_id: TypeId) -> Result<Option<AnyUserData>> {
+    TypeRegistry::type_object(lua, type_id)
+}
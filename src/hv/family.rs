@@ -0,0 +1,174 @@
+use std::{any::TypeId, sync::Arc};
+
+use rustc_hash::FxHashMap;
+
+use crate::{
+    hv::{
+        lazy::{lazy_table, userdata_type, LazyLoader},
+        registry::TypeRegistry,
+    },
+    types::MaybeSend,
+    AnyUserData, Error, Lua, Result, Table, ToLua, UserData, Value,
+};
+
+/// A conversion from one member of a type family to another.
+trait Conversion: Send + Sync {
+    fn convert<'lua>(&self, lua: &'lua Lua, value: &AnyUserData<'lua>)
+        -> Result<AnyUserData<'lua>>;
+}
+
+struct FnConversion<A, B>(fn(&A) -> B);
+
+impl<A, B> Conversion for FnConversion<A, B>
+where
+    A: 'static + UserData,
+    B: 'static + MaybeSend + UserData,
+{
+    fn convert<'lua>(
+        &self,
+        lua: &'lua Lua,
+        value: &AnyUserData<'lua>,
+    ) -> Result<AnyUserData<'lua>> {
+        let converted = (self.0)(&*value.borrow::<A>()?);
+        lua.create_userdata(converted)
+    }
+}
+
+struct Member {
+    parameter: &'static str,
+    type_id: TypeId,
+    load: LazyLoader,
+}
+
+/// A family of monomorphized instances of one generic userdata type, such as `Vector3<f32>` and
+/// `Vector3<f64>`, exposed to Lua under one name.
+///
+/// In Lua, the family is a table of the type objects of its members, keyed by type parameter and
+/// created on first access. It can be indexed (`Vector3.f64`) or called (`Vector3("f32")`) to
+/// select a member. Calling it with a value and a type parameter (`Vector3(v, "f64")`) converts the
+/// value to that member, using the conversions declared with [`TypeFamily::conversion`].
+///
+/// Once a family's table has been built in a Lua state, the type objects of its members report
+/// their family and parameter through their `family` and `parameter` fields, and from Rust through
+/// [`type_family_of`]. Within a Lua state, a type can only be a member of one family: building a
+/// family with a member already declared in another family fails, and records nothing.
+pub struct TypeFamily {
+    name: &'static str,
+    members: Vec<Member>,
+    conversions: FxHashMap<(TypeId, TypeId), Arc<dyn Conversion>>,
+}
+
+impl TypeFamily {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            members: Vec::new(),
+            conversions: FxHashMap::default(),
+        }
+    }
+
+    /// Add `T` to the family as its instance for the type parameter `parameter`. Membership is
+    /// only recorded once the family is built with [`TypeFamily::into_table`].
+    pub fn member<T: 'static + MaybeSend + UserData>(mut self, parameter: &'static str) -> Self {
+        self.members.push(Member {
+            parameter,
+            type_id: TypeId::of::<T>(),
+            load: userdata_type::<T>,
+        });
+        self
+    }
+
+    /// Declare a conversion from the member `A` to the member `B`.
+    pub fn conversion<A, B>(mut self, f: fn(&A) -> B) -> Self
+    where
+        A: 'static + UserData,
+        B: 'static + MaybeSend + UserData,
+    {
+        self.conversions.insert(
+            (TypeId::of::<A>(), TypeId::of::<B>()),
+            Arc::new(FnConversion(f)),
+        );
+        self
+    }
+
+    /// Build the Lua table of the family, as the module table entry at `path`. The type objects of
+    /// its members are registered under `path` followed by their type parameter, such as
+    /// `"hv.math.Vector3.f32"`.
+    ///
+    /// Fails if one of its members is already a member of another family in this Lua state, or of
+    /// this one under another parameter.
+    pub fn into_table<'lua>(self, lua: &'lua Lua, path: &str) -> Result<Table<'lua>> {
+        TypeRegistry::join_family(
+            lua,
+            self.name,
+            self.members
+                .iter()
+                .map(|member| (member.parameter, member.type_id)),
+        )?;
+
+        let family = lazy_table(
+            lua,
+            path,
+            self.members
+                .iter()
+                .map(|member| (member.parameter, member.load))
+                .collect(),
+        )?;
+
+        let name = self.name;
+        let parameters = self
+            .members
+            .iter()
+            .map(|member| (member.parameter, member.type_id))
+            .collect::<FxHashMap<_, _>>();
+        let conversions = self.conversions;
+        let call = lua.create_function(
+            move |lua, (family, value, parameter): (Table, Value, Option<std::string::String>)| {
+                let (value, parameter) = match (value, parameter) {
+                    (Value::UserData(value), Some(parameter)) => (value, parameter),
+                    (parameter, None) => return family.get(parameter),
+                    _ => return Err(Error::UserDataTypeMismatch),
+                };
+
+                let to = *parameters.get(parameter.as_str()).ok_or_else(|| {
+                    Error::external(format!(
+                        "`{}` has no member for the parameter `{}`",
+                        name, parameter
+                    ))
+                })?;
+                let from = value.type_table().ok_or(Error::UserDataTypeMismatch)?.id;
+
+                if from == to {
+                    return Ok(Value::UserData(value));
+                }
+
+                match conversions.get(&(from, to)) {
+                    Some(conversion) => conversion.convert(lua, &value).map(Value::UserData),
+                    None => Err(Error::external(format!(
+                        "no conversion to `{}({})` from this value",
+                        name, parameter
+                    ))),
+                }
+            },
+        )?;
+        family
+            .get_metatable()
+            .expect("lazy tables have a metatable")
+            .raw_set("__call", call)?;
+
+        Ok(family)
+    }
+}
+
+impl<'lua> ToLua<'lua> for TypeFamily {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        let name = self.name;
+        self.into_table(lua, name).map(Value::Table)
+    }
+}
+
+/// The family name and type parameter of a type which is a member of a [`TypeFamily`] built in
+/// this Lua state.
+pub fn type_family_of(lua: &Lua, type_id: TypeId) -> Option<(&'static str, &'static str)> {
+    TypeRegistry::family_of(lua, type_id)
+}
@@ -0,0 +1,132 @@
+use std::{any::TypeId, sync::Arc};
+
+use rustc_hash::FxHashMap;
+
+use crate::{types::MaybeSend, Error, Function, Lua, Result, Table, UserData, Value};
+
+/// Builds the value of one entry of a [`lazy_table`], given the path of the entry, such as
+/// `"hv.ecs.World"`.
+pub(crate) type LazyLoader = for<'lua> fn(&'lua Lua, &str) -> Result<Value<'lua>>;
+
+/// A [`LazyLoader`] for the type object of a userdata type, which registers it under the path of
+/// its entry with [`Lua::register_type`]. A type already registered, such as one found in more
+/// than one module table, keeps its type object and path.
+pub(crate) fn userdata_type<'lua, T: 'static + MaybeSend + UserData>(
+    lua: &'lua Lua,
+    path: &str,
+) -> Result<Value<'lua>> {
+    match lua.registered_type_by_id(TypeId::of::<T>())? {
+        Some(ty) => Ok(Value::UserData(ty)),
+        None => lua.register_type::<T>(path).map(Value::UserData),
+    }
+}
+
+/// The path of the entry `key` of the table at `path`.
+pub(crate) fn entry_path(path: &str, key: &str) -> std::string::String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// The metatable field of a [`lazy_table`] holding the function which builds all of its entries.
+const FORCE_ALL: &str = "__force_all";
+
+/// The metatable field of a [`lazy_table`] holding the `next` function its `__pairs` returns.
+const NEXT: &str = "__next";
+
+/// Build every entry of a [`lazy_table`] which has not been built yet, so that iterating it with
+/// `next` or `pairs` sees all of them on every Lua version. Tables which are not lazy are left
+/// alone.
+pub fn force_all(table: &Table) -> Result<()> {
+    let force = match table.get_metatable() {
+        Some(metatable) => metatable.raw_get::<_, Option<Function>>(FORCE_ALL)?,
+        None => None,
+    };
+
+    match force {
+        Some(force) => force.call(table.clone()),
+        None => Ok(()),
+    }
+}
+
+/// Create a table whose entries are built on first access rather than up front.
+///
+/// Indexing a missing key with a loader runs the loader and stores its value in the table, so
+/// every entry is built at most once and later accesses are plain table lookups.
+///
+/// Entries which have not been built yet are not in the table, so a `pairs` loop over it only sees
+/// them if they are built first. The `__pairs` metamethod of the table does this, but LuaJIT and
+/// Lua 5.1 ignore `__pairs`; call [`force_all`] (`hv.force_all` from Lua) before iterating on
+/// those versions.
+///
+/// `path` is the path of the table itself, which the paths passed to the loaders are built from.
+pub(crate) fn lazy_table<'lua>(
+    lua: &'lua Lua,
+    path: &str,
+    entries: Vec<(&'static str, LazyLoader)>,
+) -> Result<Table<'lua>> {
+    let loaders = Arc::new(
+        entries
+            .into_iter()
+            .map(|(name, load)| (name, (entry_path(path, name), load)))
+            .collect::<FxHashMap<_, _>>(),
+    );
+
+    let index = {
+        let loaders = loaders.clone();
+        lua.create_function(move |lua, (table, key): (Table, Value)| {
+            let loader = match &key {
+                Value::String(s) => s.to_str().ok().and_then(|s| loaders.get(s)),
+                _ => None,
+            };
+
+            match loader {
+                Some((path, load)) => {
+                    let value = load(lua, path)?;
+                    table.raw_set(key, value.clone())?;
+                    Ok(value)
+                }
+                None => Ok(Value::Nil),
+            }
+        })?
+    };
+
+    let force = lua.create_function(move |lua, table: Table| {
+        for (&name, (path, load)) in loaders.iter() {
+            if let Value::Nil = table.raw_get::<_, Value>(name)? {
+                table.raw_set(name, load(lua, path)?)?;
+            }
+        }
+
+        Ok(())
+    })?;
+
+    // `pairs` iterates with its own `next`, since the global one may be missing in a sandbox.
+    let next = lua.create_function(|_, (table, key): (Table, Value)| {
+        Ok(match table.raw_next(key)? {
+            Some((key, value)) => (key, value),
+            None => (Value::Nil, Value::Nil),
+        })
+    })?;
+    let pairs = lua.create_function(|_, table: Table| {
+        force_all(&table)?;
+        let next = match table.get_metatable() {
+            Some(metatable) => metatable.raw_get::<_, Function>(NEXT)?,
+            None => return Err(Error::external("lazy table has no metatable")),
+        };
+        Ok((next, table, Value::Nil))
+    })?;
+
+    let metatable = lua.create_table_from(vec![
+        ("__index", index),
+        ("__pairs", pairs),
+        (FORCE_ALL, force),
+        (NEXT, next),
+    ])?;
+    let table = lua.create_table()?;
+    table.set_metatable(Some(metatable));
+
+    Ok(table)
+}
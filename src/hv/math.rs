@@ -2,8 +2,13 @@ use hv_alchemy::Type;
 use hv_math::Velocity2;
 
 use crate::{
-    external::nalgebra::LuaRealField, types::MaybeSend, FromLua, Lua, Result, Table, ToLua,
-    UserData, UserDataMethods, Value,
+    external::nalgebra::{self, LuaRealField},
+    hv::{
+        lazy::{lazy_table, LazyLoader},
+        TypeFamily,
+    },
+    types::MaybeSend,
+    Lua, Result, Table, ToLua, UserData, UserDataMethods, Value,
 };
 
 impl<T: LuaRealField> UserData for Velocity2<T> {
@@ -31,32 +36,29 @@ impl<'lua> ToLua<'lua> for Module {
     }
 }
 
-pub fn table(lua: &Lua) -> Result<Table> {
-    let src = "return family[...]";
-
-    macro_rules! e {
-        ($lua:ident, $name:ident($($ty:ty),*)) => {{
-            let t = $lua.create_table()?;
-            $(t.set(stringify!($ty), lua.create_userdata_type::<$name<$ty>>()?)?;)*
-            let env = lua.create_table_from(vec![("family", t)])?;
-            let f = lua.load(src).set_environment(env)?.into_function()?;
-            (stringify!($name), f)
-        }};
-    }
-
-    macro_rules! types {
-        ($lua:ident, $($name:ident($($field:ty),*)),* $(,)?) => { vec![$(e!($lua, $name($($field),*))),*] };
-    }
-
-    let table = Table::from_lua(crate::external::nalgebra::Module.to_lua(lua)?, lua).unwrap();
-
-    let es = types! {lua,
-        Velocity2(f32, f64),
-    };
+fn velocity2<'lua>(lua: &'lua Lua, path: &str) -> Result<Value<'lua>> {
+    TypeFamily::new("Velocity2")
+        .member::<Velocity2<f32>>("f32")
+        .member::<Velocity2<f64>>("f64")
+        .conversion::<Velocity2<f32>, Velocity2<f64>>(|v| {
+            Velocity2::new(
+                ::nalgebra::convert(v.linear),
+                ::nalgebra::convert(v.angular),
+            )
+        })
+        .conversion::<Velocity2<f64>, Velocity2<f32>>(|v| {
+            Velocity2::new(
+                ::nalgebra::convert(v.linear),
+                ::nalgebra::convert(v.angular),
+            )
+        })
+        .into_table(lua, path)
+        .map(Value::Table)
+}
 
-    for (k, v) in es {
-        table.set(k, v)?;
-    }
+pub fn table(lua: &Lua) -> Result<Table> {
+    let mut es = nalgebra::entries();
+    es.push(("Velocity2", velocity2 as LazyLoader));
 
-    Ok(table)
+    lazy_table(lua, "hv.math", es)
 }
@@ -0,0 +1,190 @@
+use std::any::TypeId;
+
+use hv_alchemy::TypeTable;
+use rustc_hash::FxHashMap;
+
+use crate::{AnyUserData, Error, Lua, RegistryKey, Result};
+
+struct RegisteredType {
+    path: String,
+    type_table: &'static TypeTable,
+    object: RegistryKey,
+}
+
+/// Per-Lua-state registry of type objects, by path and by `TypeId`.
+///
+/// Type objects which aren't registered under a path are remembered too, so that a type object
+/// can be found for any type which has one: the first one created for each type, and those of
+/// component types declared from Lua. Registering a type which already has a remembered type
+/// object registers that object, rather than creating another one.
+///
+/// The registry is kept in the Lua state's internal data rather than its app data, so that users
+/// can neither replace it nor hold borrows of it which would make creating type objects fail.
+#[derive(Default)]
+pub(crate) struct TypeRegistry {
+    by_id: FxHashMap<TypeId, RegisteredType>,
+    by_path: FxHashMap<String, TypeId>,
+    unregistered: FxHashMap<TypeId, RegistryKey>,
+    families: FxHashMap<TypeId, (&'static str, &'static str)>,
+}
+
+impl TypeRegistry {
+    /// Register `object` as the type object of the type described by `type_table` under `path`,
+    /// returning the type object already registered for it if there is one. If a type object was
+    /// remembered for the type, that one is registered instead and `object` isn't called.
+    pub(crate) fn register<'lua>(
+        lua: &'lua Lua,
+        path: &str,
+        type_table: &'static TypeTable,
+        object: impl FnOnce() -> Result<AnyUserData<'lua>>,
+    ) -> Result<AnyUserData<'lua>> {
+        let remembered = lua.with_type_registry(|registry| {
+            if let Some(registered) = registry.by_id.get(&type_table.id) {
+                if registered.path != path {
+                    return Err(Error::external(format!(
+                        "type `{}` is already registered as `{}`",
+                        type_table.type_name, registered.path
+                    )));
+                }
+
+                return lua.registry_value(&registered.object).map(Some);
+            }
+
+            if let Some(other) = registry.by_path.get(path) {
+                return Err(Error::external(format!(
+                    "`{}` is already registered as the path of type `{}`",
+                    path, registry.by_id[other].type_table.type_name
+                )));
+            }
+
+            registry
+                .unregistered
+                .get(&type_table.id)
+                .map(|key| lua.registry_value(key))
+                .transpose()
+        })?;
+
+        // Creating the type object remembers it, so it has to happen outside of the registry.
+        let object = match remembered {
+            Some(object) => object,
+            None => object()?,
+        };
+
+        if lua.with_type_registry(|registry| registry.by_id.contains_key(&type_table.id)) {
+            return Ok(object);
+        }
+
+        let key =
+            match lua.with_type_registry(|registry| registry.unregistered.remove(&type_table.id)) {
+                Some(key) => key,
+                None => lua.create_registry_value(object.clone())?,
+            };
+        lua.with_type_registry(|registry| {
+            registry.by_path.insert(path.to_owned(), type_table.id);
+            registry.by_id.insert(
+                type_table.id,
+                RegisteredType {
+                    path: path.to_owned(),
+                    type_table,
+                    object: key,
+                },
+            );
+        });
+
+        Ok(object)
+    }
+
+    /// Remember `object` as the type object of the type `type_id`, unless one is already known.
+    pub(crate) fn remember(lua: &Lua, type_id: TypeId, object: &AnyUserData) -> Result<()> {
+        let known = lua.with_type_registry(|registry| {
+            registry.by_id.contains_key(&type_id) || registry.unregistered.contains_key(&type_id)
+        });
+
+        if !known {
+            let key = lua.create_registry_value(object.clone())?;
+            lua.with_type_registry(|registry| registry.unregistered.insert(type_id, key));
+        }
+
+        Ok(())
+    }
+
+    /// The type object registered for a type if there is one, or else the one remembered for it.
+    pub(crate) fn type_object(lua: &Lua, type_id: TypeId) -> Result<Option<AnyUserData>> {
+        lua.with_type_registry(|registry| match registry.by_id.get(&type_id) {
+            Some(registered) => lua.registry_value(&registered.object).map(Some),
+            None => registry
+                .unregistered
+                .get(&type_id)
+                .map(|key| lua.registry_value(key))
+                .transpose(),
+        })
+    }
+
+    pub(crate) fn by_path<'lua>(lua: &'lua Lua, path: &str) -> Result<Option<AnyUserData<'lua>>> {
+        lua.with_type_registry(|registry| {
+            registry
+                .by_path
+                .get(path)
+                .map(|id| lua.registry_value(&registry.by_id[id].object))
+                .transpose()
+        })
+    }
+
+    pub(crate) fn by_id(lua: &Lua, type_id: TypeId) -> Result<Option<AnyUserData>> {
+        lua.with_type_registry(|registry| {
+            registry
+                .by_id
+                .get(&type_id)
+                .map(|registered| lua.registry_value(&registered.object))
+                .transpose()
+        })
+    }
+
+    pub(crate) fn path_of(lua: &Lua, type_id: TypeId) -> Option<String> {
+        lua.with_type_registry(|registry| {
+            registry
+                .by_id
+                .get(&type_id)
+                .map(|registered| registered.path.clone())
+        })
+    }
+
+    pub(crate) fn paths(lua: &Lua) -> Vec<String> {
+        let mut paths =
+            lua.with_type_registry(|registry| registry.by_path.keys().cloned().collect::<Vec<_>>());
+        paths.sort();
+        paths
+    }
+
+    /// Record the family and type parameter of every member of a family at once, failing without
+    /// recording any of them if one is already a member of another family or under another
+    /// parameter.
+    pub(crate) fn join_family(
+        lua: &Lua,
+        family: &'static str,
+        members: impl IntoIterator<Item = (&'static str, TypeId)> + Clone,
+    ) -> Result<()> {
+        lua.with_type_registry(|registry| {
+            for (parameter, type_id) in members.clone() {
+                match registry.families.get(&type_id) {
+                    Some(&existing) if existing != (family, parameter) => {
+                        return Err(Error::external(format!(
+                            "the member `{}({})` is already declared as `{}({})`",
+                            family, parameter, existing.0, existing.1
+                        )))
+                    }
+                    _ => {}
+                }
+            }
+
+            for (parameter, type_id) in members {
+                registry.families.insert(type_id, (family, parameter));
+            }
+            Ok(())
+        })
+    }
+
+    pub(crate) fn family_of(lua: &Lua, type_id: TypeId) -> Option<(&'static str, &'static str)> {
+        lua.with_type_registry(|registry| registry.families.get(&type_id).copied())
+    }
+}
@@ -10,6 +10,10 @@ use crate::{
     UserData, UserDataFields, UserDataMethods,
 };
 
+// The proxies below forward the methods and fields of `T`'s `UserData` impl. Keys they don't have
+// are looked up on `T`'s metatable, which finds extensions added with
+// `Lua::extend_userdata_type::<T>`.
+
 impl<T: 'static + UserData + MaybeSend + MaybeSync> UserData for Arc<AtomicRefCell<T>> {
     fn on_metatable_init(table: Type<Self>) {
         table.add_clone();
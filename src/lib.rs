@@ -89,6 +89,8 @@ mod function;
 mod hook;
 mod lua;
 mod multi;
+#[cfg(feature = "send")]
+mod pool;
 mod scope;
 mod stdlib;
 mod string;
@@ -108,8 +110,12 @@ pub use crate::{ffi::lua_CFunction, ffi::lua_State};
 pub use crate::conversion::from_table;
 pub use crate::error::{Error, ExternalError, ExternalResult, Result};
 pub use crate::function::Function;
-pub use crate::hook::{Debug, DebugEvent, DebugNames, DebugSource, DebugStack, HookTriggers};
-pub use crate::lua::{AsChunk, Chunk, ChunkMode, GCMode, Lua, LuaOptions};
+pub use crate::hook::{
+    Debug, DebugEvent, DebugNames, DebugSource, DebugStack, HookTriggers, InterruptHandle,
+};
+pub use crate::lua::{
+    AsChunk, Chunk, ChunkMode, GCMode, Lua, LuaOptions, StaticUserDataFields, StaticUserDataMethods,
+};
 pub use crate::multi::Variadic;
 pub use crate::scope::Scope;
 pub use crate::stdlib::StdLib;
@@ -126,6 +132,10 @@ pub use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti
 #[cfg(feature = "async")]
 pub use crate::thread::AsyncThread;
 
+#[cfg(feature = "send")]
+#[cfg_attr(docsrs, doc(cfg(feature = "send")))]
+pub use crate::pool::{LuaJob, LuaPool, SendUserData, SendValue};
+
 #[cfg(feature = "serialize")]
 #[doc(inline)]
 pub use crate::serde::{
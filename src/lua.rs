@@ -6,6 +6,7 @@ use std::fmt;
 use std::marker::PhantomData;
 use std::os::raw::{c_char, c_int, c_void};
 use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe, Location};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::{mem, ptr, str};
 
@@ -14,7 +15,10 @@ use rustc_hash::FxHashMap;
 use crate::error::{Error, Result};
 use crate::ffi;
 use crate::function::Function;
-use crate::hook::{hook_proc, Debug, HookTriggers};
+use crate::hook::{hook_proc, Debug, HookTriggers, InterruptHandle, INTERRUPT_CHECK_INTERVAL};
+#[cfg(feature = "ecs")]
+use crate::hv::ecs::SharedWorldEventLogs;
+use crate::hv::registry::TypeRegistry;
 use crate::scope::Scope;
 use crate::stdlib::StdLib;
 use crate::string::String;
@@ -22,17 +26,19 @@ use crate::table::Table;
 use crate::thread::Thread;
 use crate::types::{
     Callback, CallbackUpvalue, DestructedUserdataMT, HookCallback, Integer, LightUserData, LuaRef,
-    MaybeSend, Number, RegistryKey,
+    MaybeSend, MaybeSync, Number, RegistryKey, ThreadContext,
 };
 use crate::userdata::TryCloneToUserDataExt;
 use crate::userdata::{
-    AnyUserData, MetaMethod, UserData, UserDataCell, UserDataFields, UserDataMethods,
+    AnyUserData, MetaMethod, ProxyBorrow, UserData, UserDataCell, UserDataFields, UserDataMethods,
+    UserDataProxyTarget,
 };
 use crate::util::{
-    self, assert_stack, callback_error, check_stack, get_destructed_userdata_metatable,
-    get_gc_metatable, get_gc_userdata, get_main_state, get_userdata, init_error_registry,
-    init_gc_metatable, init_userdata_metatable, pop_error, push_gc_userdata, push_string,
-    push_table, rawset_field, safe_pcall, safe_xpcall, StackGuard, WrappedFailure,
+    self, assert_stack, callback_error, check_stack, extend_userdata_metatable,
+    get_destructed_userdata_metatable, get_gc_metatable, get_gc_userdata, get_main_state,
+    get_userdata, init_error_registry, init_gc_metatable, init_userdata_metatable, pop_error,
+    push_gc_userdata, push_string, push_table, rawset_field, safe_pcall, safe_xpcall,
+    set_userdata_metatable_fallback, StackGuard, WrappedFailure,
 };
 use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
 
@@ -71,6 +77,19 @@ struct ExtraData {
     registered_userdata_mt: FxHashMap<*const c_void, Option<&'static TypeTable>>,
     registry_unref_list: Arc<Mutex<Option<Vec<c_int>>>>,
 
+    // States of the threads currently being resumed from Rust, innermost last
+    resumed_threads: Vec<*mut ffi::lua_State>,
+
+    // Extensions to userdata types whose metatables haven't been created yet, each a boxed
+    // `Vec<UserDataExtension<T>>`
+    #[cfg(not(feature = "send"))]
+    pending_userdata_extensions: FxHashMap<TypeId, Box<dyn Any>>,
+    #[cfg(feature = "send")]
+    pending_userdata_extensions: FxHashMap<TypeId, Box<dyn Any + Send>>,
+    // How the methods and fields of a userdata type borrow it from its proxy types, by the type
+    // ids of the proxy type and of the type it proxies
+    userdata_proxies: FxHashMap<(TypeId, TypeId), Box<dyn Any + Send>>,
+
     // A vector of `Vec`s of `Value`s, turned to pointers because they have lifetimes and need to
     // be stored 'static-ally
     multivalue_vec_pool: Vec<(*mut (), usize, usize)>,
@@ -97,6 +116,29 @@ struct ExtraData {
     ref_waker_idx: c_int,
 
     hook_callback: Option<HookCallback>,
+    hook_triggers: HookTriggers,
+    // Instructions counted towards the hook callback's own instruction trigger, when the hook
+    // count is shortened for interrupt checks
+    hook_instructions: u32,
+    interrupt: Option<Arc<AtomicBool>>,
+
+    // Interning table of entities passed to Lua as userdata, if typed entities are enabled
+    // Type objects by path and by `TypeId`
+    type_registry: TypeRegistry,
+    #[cfg(feature = "ecs")]
+    typed_entities: Option<RegistryKey>,
+    // Event logs of the shared worlds with subscribers in this state
+    #[cfg(feature = "ecs")]
+    shared_world_event_logs: SharedWorldEventLogs,
+}
+
+// Pops the innermost thread resumed from Rust when dropped.
+pub(crate) struct ResumedThread<'a>(&'a Lua);
+
+impl<'a> Drop for ResumedThread<'a> {
+    fn drop(&mut self) {
+        unsafe { (*self.0.extra.get()).resumed_threads.pop() };
+    }
 }
 
 #[cfg_attr(any(feature = "lua51", feature = "luajit"), allow(dead_code))]
@@ -166,6 +208,7 @@ impl LuaOptions {
 #[cfg(feature = "async")]
 pub(crate) static ASYNC_POLL_PENDING: u8 = 0;
 pub(crate) static EXTRA_REGISTRY_KEY: u8 = 0;
+static THREAD_CONTEXTS_REGISTRY_KEY: u8 = 0;
 
 const WRAPPED_FAILURES_POOL_SIZE: usize = 16;
 
@@ -437,6 +480,7 @@ impl Lua {
                 init_gc_metatable::<Arc<UnsafeCell<ExtraData>>>(state, None)?;
                 init_gc_metatable::<Callback>(state, None)?;
                 init_gc_metatable::<CallbackUpvalue>(state, None)?;
+                init_gc_metatable::<ThreadContext>(state, None)?;
                 #[cfg(feature = "async")]
                 {
                     init_gc_metatable::<AsyncCallback>(state, None)?;
@@ -482,6 +526,9 @@ impl Lua {
             registered_userdata: FxHashMap::default(),
             registered_userdata_mt: FxHashMap::default(),
             registry_unref_list: Arc::new(Mutex::new(Some(Vec::new()))),
+            resumed_threads: Vec::new(),
+            pending_userdata_extensions: FxHashMap::default(),
+            userdata_proxies: FxHashMap::default(),
             app_data: RefCell::new(HashMap::new()),
             ref_thread,
             libs: StdLib::NONE,
@@ -496,6 +543,14 @@ impl Lua {
             #[cfg(feature = "async")]
             ref_waker_idx,
             hook_callback: None,
+            hook_triggers: HookTriggers::default(),
+            hook_instructions: 0,
+            interrupt: None,
+            type_registry: TypeRegistry::default(),
+            #[cfg(feature = "ecs")]
+            typed_entities: None,
+            #[cfg(feature = "ecs")]
+            shared_world_event_logs: SharedWorldEventLogs::default(),
         }));
 
         mlua_expect!(
@@ -733,14 +788,18 @@ impl Lua {
     {
         let state = self.main_state.ok_or(Error::MainThreadNotAvailable)?;
         unsafe {
-            (*self.extra.get()).hook_callback = Some(Arc::new(RefCell::new(callback)));
-            ffi::lua_sethook(state, Some(hook_proc), triggers.mask(), triggers.count());
+            let extra = &mut *self.extra.get();
+            extra.hook_callback = Some(Arc::new(RefCell::new(callback)));
+            extra.hook_triggers = triggers;
+            self.update_hook(state);
         }
         Ok(())
     }
 
     /// Remove any hook previously set by `set_hook`. This function has no effect if a hook was not
     /// previously set.
+    ///
+    /// Interrupt checks enabled by [`Lua::interrupt_handle`] are not affected.
     pub fn remove_hook(&self) {
         // If main_state is not available, then sethook wasn't called.
         let state = match self.main_state {
@@ -748,7 +807,80 @@ impl Lua {
             None => return,
         };
         unsafe {
-            (*self.extra.get()).hook_callback = None;
+            let extra = &mut *self.extra.get();
+            extra.hook_callback = None;
+            extra.hook_triggers = HookTriggers::default();
+            self.update_hook(state);
+        }
+    }
+
+    /// Returns a handle which can be used to interrupt running Lua code, from any thread.
+    ///
+    /// After [`InterruptHandle::interrupt`] is called, Lua code executing in this state fails at
+    /// its next interrupt check, which happens every 1000 VM instructions, or every
+    /// [`HookTriggers.every_nth_instruction`] instructions if a hook is set with a smaller count.
+    /// The failure is reported as a top-level [`Error::Interrupted`], even when it happens inside
+    /// nested callbacks. The handle stays interrupted, so that the script cannot recover by
+    /// catching the error with `pcall`, until [`InterruptHandle::reset`] is called.
+    ///
+    /// The checks are implemented as a hook on the main thread, so they only apply to the main
+    /// thread and to the coroutines created after the first call to this function. With LuaJIT,
+    /// JIT-compiled code does not trigger hooks and hence cannot be interrupted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use hv_lua::{Error, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// # #[cfg(feature = "luajit")]
+    /// # lua.load("jit.off()").exec()?;
+    /// let handle = lua.interrupt_handle()?;
+    ///
+    /// let stop = std::thread::spawn(move || handle.interrupt());
+    /// let err = lua.load("while true do end").exec().unwrap_err();
+    /// stop.join().unwrap();
+    ///
+    /// assert!(matches!(err, Error::Interrupted));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`InterruptHandle::interrupt`]: crate::InterruptHandle::interrupt
+    /// [`InterruptHandle::reset`]: crate::InterruptHandle::reset
+    /// [`HookTriggers.every_nth_instruction`]: crate::HookTriggers::every_nth_instruction
+    /// [`Error::Interrupted`]: crate::Error::Interrupted
+    pub fn interrupt_handle(&self) -> Result<InterruptHandle> {
+        let state = self.main_state.ok_or(Error::MainThreadNotAvailable)?;
+        unsafe {
+            let extra = &mut *self.extra.get();
+            if let Some(ref interrupt) = extra.interrupt {
+                return Ok(InterruptHandle(Arc::clone(interrupt)));
+            }
+            let interrupt = Arc::new(AtomicBool::new(false));
+            extra.interrupt = Some(Arc::clone(&interrupt));
+            self.update_hook(state);
+            Ok(InterruptHandle(interrupt))
+        }
+    }
+
+    // Installs `hook_proc` with the triggers required by the hook callback and the interrupt
+    // checks, or removes it if there is nothing left to do.
+    unsafe fn update_hook(&self, state: *mut ffi::lua_State) {
+        let extra = &mut *self.extra.get();
+        let mut triggers = extra.hook_triggers;
+        if extra.interrupt.is_some() {
+            let count = match triggers.every_nth_instruction {
+                Some(count) => count.min(INTERRUPT_CHECK_INTERVAL),
+                None => INTERRUPT_CHECK_INTERVAL,
+            };
+            triggers.every_nth_instruction = Some(count);
+        }
+        extra.hook_instructions = 0;
+
+        if triggers.mask() != 0 {
+            ffi::lua_sethook(state, Some(hook_proc), triggers.mask(), triggers.count());
+        } else {
             ffi::lua_sethook(state, None, 0, 0);
         }
     }
@@ -1270,7 +1402,11 @@ impl Lua {
             self.push_ref(&func.0);
             ffi::lua_xmove(self.state, thread_state, 1);
 
-            Ok(Thread(self.pop_ref()))
+            let thread = Thread(self.pop_ref());
+            if let Some(context) = self.effective_context() {
+                self.set_context_of(&thread.0, Some(context))?;
+            }
+            Ok(thread)
         }
     }
 
@@ -1287,7 +1423,115 @@ impl Lua {
     where
         T: 'static + MaybeSend + UserData,
     {
-        self.create_userdata(hv_alchemy::of::<T>())
+        let ty = self.create_userdata(hv_alchemy::of::<T>())?;
+        TypeRegistry::remember(self, TypeId::of::<T>(), &ty)?;
+        Ok(ty)
+    }
+
+    /// Create the type object of a userdata type, as [`Lua::create_userdata_type`] does, and
+    /// register it under a stable path such as `"math.Vector3f"`.
+    ///
+    /// Registered type objects can be looked up again by path, by [`TypeId`] or by [`TypeTable`].
+    /// Registering a type again under the same path returns the type object already registered;
+    /// registering it under a different path, or a different type under a path already taken, is
+    /// an error. If a type object was already created for the type, that one is registered rather
+    /// than a new one.
+    pub fn register_type<T>(&self, path: &str) -> Result<AnyUserData>
+    where
+        T: 'static + MaybeSend + UserData,
+    {
+        TypeRegistry::register(self, path, TypeTable::of::<T>(), || {
+            self.create_userdata_type::<T>()
+        })
+    }
+
+    /// Look up a type object registered with [`Lua::register_type`] by its path.
+    pub fn registered_type(&self, path: &str) -> Result<Option<AnyUserData>> {
+        TypeRegistry::by_path(self, path)
+    }
+
+    /// Look up the type object registered with [`Lua::register_type`] for a type, by its
+    /// [`TypeId`].
+    pub fn registered_type_by_id(&self, type_id: TypeId) -> Result<Option<AnyUserData>> {
+        TypeRegistry::by_id(self, type_id)
+    }
+
+    /// Look up the type object registered with [`Lua::register_type`] for a type, by its
+    /// [`TypeTable`].
+    pub fn registered_type_by_table(
+        &self,
+        type_table: &'static TypeTable,
+    ) -> Result<Option<AnyUserData>> {
+        TypeRegistry::by_id(self, type_table.id)
+    }
+
+    /// The path a type was registered under with [`Lua::register_type`], if it was.
+    pub fn registered_type_path(&self, type_id: TypeId) -> Option<std::string::String> {
+        TypeRegistry::path_of(self, type_id)
+    }
+
+    /// The paths of every type registered with [`Lua::register_type`], in sorted order.
+    pub fn registered_type_paths(&self) -> Vec<std::string::String> {
+        TypeRegistry::paths(self)
+    }
+
+    /// Add methods and fields to a userdata type, on top of those from its [`UserData`] impl.
+    ///
+    /// This allows adding to types from other crates, which can't be given a second `UserData`
+    /// impl. Methods and fields added this way take precedence over those of the same name from
+    /// the `UserData` impl or from earlier extensions; to add methods implemented in Lua, capture
+    /// [`RegistryKey`]s of the functions to call.
+    ///
+    /// If the metatable of `T` has already been created, it's extended in place, and values of
+    /// `T` created before the call see the new methods and fields too. Otherwise, the extension is
+    /// applied when the metatable is first created. Either way, `Index` and `NewIndex` meta methods
+    /// added by an extension are only used for keys which aren't fields or methods, as with those
+    /// of a `UserData` impl.
+    ///
+    /// Types which forward the methods and fields of `T`, such as `Arc<AtomicRefCell<T>>` and the
+    /// other proxies in [`hv`](crate::hv), look up the keys they don't have themselves on `T`, so
+    /// they see the methods and fields an extension adds. They don't see extensions which replace
+    /// methods or fields they already forward, or meta methods other than `Index` and `NewIndex`.
+    pub fn extend_userdata_type<T, F>(&self, extend: F) -> Result<()>
+    where
+        T: 'static + UserData,
+        F: 'static
+            + MaybeSend
+            + for<'lua> FnOnce(
+                &mut StaticUserDataMethods<'lua, T>,
+                &mut StaticUserDataFields<'lua, T>,
+            ),
+    {
+        let type_id = TypeId::of::<T>();
+        let registered = unsafe {
+            (*self.extra.get())
+                .registered_userdata
+                .contains_key(&type_id)
+        };
+
+        if registered {
+            unsafe {
+                let _sg = StackGuard::new(self.state);
+                check_stack(self.state, 14)?;
+
+                let mut methods = StaticUserDataMethods::default();
+                let mut fields = StaticUserDataFields::default();
+                extend(&mut methods, &mut fields);
+
+                self.push_userdata_metatable::<T>()?;
+                self.init_userdata_metatable_with(methods, fields, true)
+            }
+        } else {
+            let extra = unsafe { &mut *self.extra.get() };
+            extra
+                .pending_userdata_extensions
+                .entry(type_id)
+                .or_insert_with(|| Box::new(Vec::<UserDataExtension<T>>::new()))
+                .downcast_mut::<Vec<UserDataExtension<T>>>()
+                .unwrap()
+                .push(Box::new(extend));
+            Ok(())
+        }
     }
 
     /// Returns a handle to the global environment.
@@ -1694,6 +1938,205 @@ impl Lua {
             .and_then(|data| data.downcast().ok().map(|data| *data))
     }
 
+    // How the methods and fields of `T` borrow it from a userdata of the proxy type `proxy`, if
+    // `proxy` is a proxy type of `T` whose metatable has been created.
+    pub(crate) fn userdata_proxy<T: 'static>(&self, proxy: TypeId) -> Option<ProxyBorrow<T>> {
+        let extra = unsafe { &*self.extra.get() };
+        extra
+            .userdata_proxies
+            .get(&(proxy, TypeId::of::<T>()))
+            .and_then(|borrow| borrow.downcast_ref::<ProxyBorrow<T>>())
+            .copied()
+    }
+
+    /// Run `f` with this state's registry of type objects. `f` must not call back into this
+    /// function, and so must not create type objects.
+    pub(crate) fn with_type_registry<R>(&self, f: impl FnOnce(&mut TypeRegistry) -> R) -> R {
+        f(unsafe { &mut (*self.extra.get()).type_registry })
+    }
+
+    /// The interning table of typed entities, kept here rather than in the app data since it's
+    /// looked up every time an entity is passed to Lua.
+    #[cfg(feature = "ecs")]
+    pub(crate) fn typed_entities(&self) -> Result<Option<Table>> {
+        match unsafe { &(*self.extra.get()).typed_entities } {
+            Some(key) => self.registry_value(key).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    #[cfg(feature = "ecs")]
+    pub(crate) fn set_typed_entities(&self, interned: Option<Table>) -> Result<()> {
+        let key = interned
+            .map(|table| self.create_registry_value(table))
+            .transpose()?;
+        unsafe { (*self.extra.get()).typed_entities = key };
+        Ok(())
+    }
+
+    /// Run `f` with the event logs of the shared worlds with subscribers in this state. `f` must
+    /// not call back into this function.
+    #[cfg(feature = "ecs")]
+    pub(crate) fn with_shared_world_event_logs<R>(
+        &self,
+        f: impl FnOnce(&mut SharedWorldEventLogs) -> R,
+    ) -> R {
+        f(unsafe { &mut (*self.extra.get()).shared_world_event_logs })
+    }
+
+    /// Gets the context value of type `T` of the currently running code.
+    ///
+    /// This is mostly useful inside callbacks, which see the thread (or coroutine) calling them as
+    /// the current thread. The value is looked up on the current thread first, then on the threads
+    /// being resumed from Rust with [`Thread::resume()`], innermost first, and finally on the main
+    /// thread. See [`Thread::set_context()`] for how context values are attached and inherited.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use hv::lua::{Lua, Result, Thread};
+    ///
+    /// struct Owner(&'static str);
+    ///
+    /// fn main() -> Result<()> {
+    ///     let lua = Lua::new();
+    ///     let whoami = lua.create_function(|lua, ()| {
+    ///         Ok(lua.current_context::<Owner>().map(|owner| owner.0))
+    ///     })?;
+    ///     lua.globals().set("whoami", whoami)?;
+    ///
+    ///     let thread: Thread = lua.load(r#"
+    ///         coroutine.create(function()
+    ///             local inner = coroutine.wrap(function() return whoami() end)
+    ///             return whoami(), inner()
+    ///         end)
+    ///     "#).eval()?;
+    ///     thread.set_context(Owner("player"))?;
+    ///
+    ///     let owners: (String, String) = thread.resume(())?;
+    ///     assert_eq!(owners, ("player".to_string(), "player".to_string()));
+    ///     assert!(lua.load("whoami()").eval::<Option<String>>()?.is_none());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn current_context<T: 'static + MaybeSend + MaybeSync>(&self) -> Option<Arc<T>> {
+        let context = self.effective_context()?;
+        context.get()
+    }
+
+    // Returns the context of the running code: the contexts of the main thread, of the threads
+    // being resumed from Rust and of the current thread merged together, innermost first.
+    pub(crate) fn effective_context(&self) -> Option<ThreadContext> {
+        let resumed = unsafe { (*self.extra.get()).resumed_threads.clone() };
+        let states = self
+            .main_state
+            .into_iter()
+            .chain(resumed)
+            .chain(std::iter::once(self.state));
+
+        let mut effective: Option<ThreadContext> = None;
+        for state in states {
+            if let Some(context) = unsafe { self.get_context_of_thread_state(state) } {
+                match &mut effective {
+                    Some(effective) => effective.0.extend(context.0),
+                    None => effective = Some(context),
+                }
+            }
+        }
+        effective
+    }
+
+    // Returns a copy of the context attached to the given value (usually a thread), if any.
+    pub(crate) fn get_context_of(&self, value: &LuaRef) -> Option<ThreadContext> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 4);
+            self.push_ref(value);
+            self.get_context_of_top()
+        }
+    }
+
+    // Returns a copy of the context attached to the thread with the given state, if any.
+    unsafe fn get_context_of_thread_state(
+        &self,
+        thread_state: *mut ffi::lua_State,
+    ) -> Option<ThreadContext> {
+        let _sg = StackGuard::new(self.state);
+        assert_stack(self.state, 4);
+        if thread_state == self.state {
+            ffi::lua_pushthread(self.state);
+        } else {
+            if ffi::lua_checkstack(thread_state, 1) == 0 {
+                return None;
+            }
+            ffi::lua_pushthread(thread_state);
+            ffi::lua_xmove(thread_state, self.state, 1);
+        }
+        self.get_context_of_top()
+    }
+
+    // Returns a copy of the context attached to the value on top of the stack, if any.
+    // Uses 3 stack spaces, does not call checkstack.
+    unsafe fn get_context_of_top(&self) -> Option<ThreadContext> {
+        let contexts_key = &THREAD_CONTEXTS_REGISTRY_KEY as *const u8 as *const c_void;
+        if ffi::lua_rawgetp(self.state, ffi::LUA_REGISTRYINDEX, contexts_key) != ffi::LUA_TTABLE {
+            return None;
+        }
+        ffi::lua_pushvalue(self.state, -2);
+        ffi::lua_rawget(self.state, -2);
+        get_gc_userdata::<ThreadContext>(self.state, -1)
+            .as_ref()
+            .cloned()
+    }
+
+    // Records that `thread_state` is being resumed from Rust until the returned guard is dropped,
+    // so that coroutines running inside it see its context.
+    pub(crate) fn enter_resumed_thread(&self, thread_state: *mut ffi::lua_State) -> ResumedThread {
+        unsafe { (*self.extra.get()).resumed_threads.push(thread_state) };
+        ResumedThread(self)
+    }
+
+    // Attaches a context to the given value (usually a thread), or detaches it if `None`.
+    pub(crate) fn set_context_of(
+        &self,
+        value: &LuaRef,
+        context: Option<ThreadContext>,
+    ) -> Result<()> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            check_stack(self.state, 5)?;
+
+            let contexts_key = &THREAD_CONTEXTS_REGISTRY_KEY as *const u8 as *const c_void;
+            if ffi::lua_rawgetp(self.state, ffi::LUA_REGISTRYINDEX, contexts_key) != ffi::LUA_TTABLE
+            {
+                if context.is_none() {
+                    return Ok(());
+                }
+
+                ffi::lua_pop(self.state, 1);
+                // Contexts must not keep their threads alive, so the keys are weak
+                protect_lua!(self.state, 0, 1, fn(state) {
+                    ffi::lua_newtable(state);
+                    ffi::lua_createtable(state, 0, 1);
+                    ffi::lua_pushstring(state, cstr!("k"));
+                    ffi::lua_setfield(state, -2, cstr!("__mode"));
+                    ffi::lua_setmetatable(state, -2);
+                    ffi::lua_pushvalue(state, -1);
+                    let contexts_key = &THREAD_CONTEXTS_REGISTRY_KEY as *const u8 as *const c_void;
+                    ffi::lua_rawsetp(state, ffi::LUA_REGISTRYINDEX, contexts_key);
+                })?;
+            }
+
+            self.push_ref(value);
+            match context {
+                Some(context) => push_gc_userdata(self.state, context)?,
+                None => ffi::lua_pushnil(self.state),
+            }
+            protect_lua!(self.state, 3, 1, fn(state) ffi::lua_rawset(state, -3))?;
+        }
+        Ok(())
+    }
+
     // Uses 2 stack spaces, does not call checkstack
     pub(crate) unsafe fn push_value(&self, value: Value) -> Result<()> {
         match value {
@@ -1905,23 +2348,86 @@ impl Lua {
         T::add_fields(&mut fields);
         T::add_methods(&mut methods);
 
-        // Prepare metatable, add meta methods first and then meta fields
+        if let Some(pending) = extra.pending_userdata_extensions.remove(&type_id) {
+            let pending = pending.downcast::<Vec<UserDataExtension<T>>>().unwrap();
+            for extend in *pending {
+                extend(&mut methods, &mut fields);
+            }
+        }
+
+        // Proxy types end their lookups with those of the type they proxy, which find its
+        // extensions and its own `Index` and `NewIndex` meta methods.
+        if let Some(target) = methods.proxy_target.take() {
+            extra
+                .userdata_proxies
+                .insert((type_id, target.type_id), target.borrow);
+            methods
+                .meta_methods
+                .retain(|(meta, _)| *meta != MetaMethod::Index && *meta != MetaMethod::NewIndex);
+            methods.meta_methods.push((
+                MetaMethod::Index,
+                proxy_fallback(target.push_metatable, "__index"),
+            ));
+            methods.meta_methods.push((
+                MetaMethod::NewIndex,
+                proxy_fallback(target.push_metatable, "__newindex"),
+            ));
+        }
+
         let metatable_nrec = methods.meta_methods.len() + fields.meta_fields.len();
         #[cfg(feature = "async")]
         let metatable_nrec = metatable_nrec + methods.async_meta_methods.len();
         push_table(self.state, 0, metatable_nrec as c_int)?;
+        self.init_userdata_metatable_with(methods, fields, false)?;
+
+        let mt_ptr = ffi::lua_topointer(self.state, -1);
+        ffi::lua_pushvalue(self.state, -1);
+        let id = protect_lua!(self.state, 1, 0, |state| {
+            ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+        })?;
+
+        extra.registered_userdata.insert(type_id, id);
+        extra
+            .registered_userdata_mt
+            .insert(mt_ptr, Some(alchemy_table.as_untyped()));
+
+        Ok(())
+    }
+
+    // Fills in the userdata metatable at the top of the stack from `methods` and `fields`, adding
+    // their meta methods and meta fields and wrapping its `__index` and `__newindex` to look up their
+    // fields and methods first.
+    //
+    // If `extend` is set, the metatable has been filled in before. Its fields and methods are then
+    // merged into those its `__index` and `__newindex` already look up, and new `__index` and
+    // `__newindex` meta methods become the fallbacks they end with rather than replacing them.
+    // Uses 13 stack spaces, does not call checkstack.
+    unsafe fn init_userdata_metatable_with<'lua, T: 'static + UserData>(
+        &'lua self,
+        methods: StaticUserDataMethods<'lua, T>,
+        fields: StaticUserDataFields<'lua, T>,
+        extend: bool,
+    ) -> Result<()> {
+        let set_meta = |name: &str| match name {
+            "__index" | "__newindex" if extend => {
+                set_userdata_metatable_fallback(self.state, -2, name)
+            }
+            _ => rawset_field(self.state, -2, name),
+        };
+
+        // Add meta methods first and then meta fields
         for (k, m) in methods.meta_methods {
             self.push_value(Value::Function(self.create_callback(m)?))?;
-            rawset_field(self.state, -2, k.validate()?.name())?;
+            set_meta(k.validate()?.name())?;
         }
         #[cfg(feature = "async")]
         for (k, m) in methods.async_meta_methods {
             self.push_value(Value::Function(self.create_async_callback(m)?))?;
-            rawset_field(self.state, -2, k.validate()?.name())?;
+            set_meta(k.validate()?.name())?;
         }
         for (k, f) in fields.meta_fields {
             self.push_value(f(self)?)?;
-            rawset_field(self.state, -2, k.validate()?.name())?;
+            set_meta(k.validate()?.name())?;
         }
         let metatable_index = ffi::lua_absindex(self.state, -1);
 
@@ -1970,28 +2476,27 @@ impl Lua {
             extra_tables_count += 1;
         }
 
-        init_userdata_metatable(
-            self.state,
-            metatable_index,
-            field_getters_index,
-            field_setters_index,
-            methods_index,
-        )?;
+        if extend {
+            extend_userdata_metatable(
+                self.state,
+                metatable_index,
+                field_getters_index,
+                field_setters_index,
+                methods_index,
+            )?;
+        } else {
+            init_userdata_metatable(
+                self.state,
+                metatable_index,
+                field_getters_index,
+                field_setters_index,
+                methods_index,
+            )?;
+        }
 
         // Pop extra tables to get metatable on top of the stack
         ffi::lua_pop(self.state, extra_tables_count);
 
-        let mt_ptr = ffi::lua_topointer(self.state, -1);
-        ffi::lua_pushvalue(self.state, -1);
-        let id = protect_lua!(self.state, 1, 0, |state| {
-            ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
-        })?;
-
-        extra.registered_userdata.insert(type_id, id);
-        extra
-            .registered_userdata_mt
-            .insert(mt_ptr, Some(alchemy_table.as_untyped()));
-
         Ok(())
     }
 
@@ -2365,6 +2870,38 @@ impl Lua {
         (*self.extra.get()).hook_callback.clone()
     }
 
+    pub(crate) unsafe fn hook_triggers(&self) -> HookTriggers {
+        (*self.extra.get()).hook_triggers
+    }
+
+    // Counts the instructions executed since the last count event towards the hook callback's own
+    // instruction trigger, returning whether the callback is due.
+    pub(crate) unsafe fn hook_count_due(&self) -> bool {
+        let extra = &mut *self.extra.get();
+        let count = match extra.hook_triggers.every_nth_instruction {
+            Some(count) => count,
+            None => return false,
+        };
+        if extra.interrupt.is_none() || count <= INTERRUPT_CHECK_INTERVAL {
+            return true;
+        }
+
+        extra.hook_instructions += INTERRUPT_CHECK_INTERVAL;
+        if extra.hook_instructions >= count {
+            extra.hook_instructions -= count;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) unsafe fn is_interrupted(&self) -> bool {
+        match (*self.extra.get()).interrupt {
+            Some(ref interrupt) => interrupt.load(Ordering::Relaxed),
+            None => false,
+        }
+    }
+
     pub(crate) fn pull_multivalue_vec(&self) -> Vec<Value> {
         unsafe {
             let extra = &mut *self.extra.get();
@@ -2612,6 +3149,7 @@ pub(crate) fn init_metatable_cache(cache: &mut FxHashMap<TypeId, u8>) {
     cache.insert(TypeId::of::<Arc<UnsafeCell<ExtraData>>>(), 0);
     cache.insert(TypeId::of::<Callback>(), 0);
     cache.insert(TypeId::of::<CallbackUpvalue>(), 0);
+    cache.insert(TypeId::of::<ThreadContext>(), 0);
 
     #[cfg(feature = "async")]
     {
@@ -2721,9 +3259,12 @@ where
             } else {
                 "<not enough stack space for traceback>".to_string()
             };
+            // Interrupts are reported as is, however deep in nested callbacks they happen
             if let WrappedFailure::Error(ref mut err) = *wrapped_error {
-                let cause = Arc::new(err.clone());
-                *err = Error::CallbackError { traceback, cause };
+                if !matches!(err, Error::Interrupted) {
+                    let cause = Arc::new(err.clone());
+                    *err = Error::CallbackError { traceback, cause };
+                }
             }
 
             ffi::lua_error(state)
@@ -2890,13 +3431,58 @@ unsafe fn ref_stack_pop(extra: &mut ExtraData) -> c_int {
     extra.ref_stack_top
 }
 
-struct StaticUserDataMethods<'lua, T: 'static + UserData> {
+// The `__index` or `__newindex` of a proxy type, which looks the key up in the metatable of the
+// type it proxies. The lookup happens on every call, so that it sees the metatable as extended by
+// `Lua::extend_userdata_type` since.
+fn proxy_fallback<'lua>(
+    push_metatable: unsafe fn(&Lua) -> Result<()>,
+    name: &'static str,
+) -> Callback<'lua, 'static> {
+    Box::new(move |lua, args| {
+        let metatable = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 13)?;
+            push_metatable(lua)?;
+            Table(lua.pop_ref())
+        };
+
+        let (_, key, value) = <(Value, Value, Value)>::from_lua_multi(args.clone(), lua)?;
+        let field = match (metatable.raw_get::<_, Value>(name)?, name) {
+            (Value::Function(f), _) => return f.call(args),
+            (Value::Table(t), "__index") => return t.get::<_, Value>(key)?.to_lua_multi(lua),
+            (Value::Table(t), _) => return t.set(key, value)?.to_lua_multi(lua),
+            _ => lua
+                .coerce_string(key)?
+                .map_or_else(|| "?".to_owned(), |s| s.to_string_lossy().into_owned()),
+        };
+        let action = if name == "__index" { "get" } else { "set" };
+        Err(Error::RuntimeError(format!(
+            "attempt to {} an unknown field '{}'",
+            action, field
+        )))
+    })
+}
+
+#[cfg(feature = "send")]
+type UserDataExtension<T> = Box<
+    dyn for<'lua> FnOnce(&mut StaticUserDataMethods<'lua, T>, &mut StaticUserDataFields<'lua, T>)
+        + Send,
+>;
+#[cfg(not(feature = "send"))]
+type UserDataExtension<T> = Box<
+    dyn for<'lua> FnOnce(&mut StaticUserDataMethods<'lua, T>, &mut StaticUserDataFields<'lua, T>),
+>;
+
+/// Collects the methods of a `'static` userdata type, as passed to
+/// [`Lua::extend_userdata_type`].
+pub struct StaticUserDataMethods<'lua, T: 'static + UserData> {
     methods: Vec<(Vec<u8>, Callback<'lua, 'static>)>,
     #[cfg(feature = "async")]
     async_methods: Vec<(Vec<u8>, AsyncCallback<'lua, 'static>)>,
     meta_methods: Vec<(MetaMethod, Callback<'lua, 'static>)>,
     #[cfg(feature = "async")]
     async_meta_methods: Vec<(MetaMethod, AsyncCallback<'lua, 'static>)>,
+    proxy_target: Option<UserDataProxyTarget>,
     _type: PhantomData<T>,
 }
 
@@ -2909,6 +3495,7 @@ impl<'lua, T: 'static + UserData> Default for StaticUserDataMethods<'lua, T> {
             meta_methods: Vec::new(),
             #[cfg(feature = "async")]
             async_meta_methods: Vec::new(),
+            proxy_target: None,
             _type: PhantomData,
         }
     }
@@ -3080,6 +3667,10 @@ impl<'lua, T: 'static + UserData> UserDataMethods<'lua, T> for StaticUserDataMet
     ) {
         self.async_meta_methods.push((meta, callback))
     }
+
+    fn add_proxy_target(&mut self, target: UserDataProxyTarget) {
+        self.proxy_target = Some(target);
+    }
 }
 
 impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
@@ -3102,7 +3693,17 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
                             let ud = lua.get_userdata_ref::<T>()?;
                             method(lua, &ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
                         }
-                        _ => Err(Error::UserDataTypeMismatch),
+                        Some(id) => match lua.userdata_proxy::<T>(id) {
+                            Some(proxy) => (proxy.borrow)(
+                                &userdata,
+                                Box::new(|ud| {
+                                    method(lua, ud, A::from_lua_multi(args, lua)?)?
+                                        .to_lua_multi(lua)
+                                }),
+                            ),
+                            None => Err(Error::UserDataTypeMismatch),
+                        },
+                        None => Err(Error::UserDataTypeMismatch),
                     }
                 }
             } else {
@@ -3138,7 +3739,17 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
                             let mut ud = lua.get_userdata_mut::<T>()?;
                             method(lua, &mut ud, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)
                         }
-                        _ => Err(Error::UserDataTypeMismatch),
+                        Some(id) => match lua.userdata_proxy::<T>(id) {
+                            Some(proxy) => (proxy.borrow_mut)(
+                                &userdata,
+                                Box::new(|ud| {
+                                    method(lua, ud, A::from_lua_multi(args, lua)?)?
+                                        .to_lua_multi(lua)
+                                }),
+                            ),
+                            None => Err(Error::UserDataTypeMismatch),
+                        },
+                        None => Err(Error::UserDataTypeMismatch),
                     }
                 }
             } else {
@@ -3234,7 +3845,8 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
     }
 }
 
-struct StaticUserDataFields<'lua, T: 'static + UserData> {
+/// Collects the fields of a `'static` userdata type, as passed to [`Lua::extend_userdata_type`].
+pub struct StaticUserDataFields<'lua, T: 'static + UserData> {
     field_getters: Vec<(Vec<u8>, Callback<'lua, 'static>)>,
     field_setters: Vec<(Vec<u8>, Callback<'lua, 'static>)>,
     #[allow(clippy::type_complexity)]
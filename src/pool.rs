@@ -0,0 +1,420 @@
+//! A pool of Lua states running on their own OS threads.
+//!
+//! Requires `feature = "send"`
+
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::os::raw::c_void;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+use hv_alchemy::AlchemicalAny;
+
+use crate::error::{Error, Result};
+use crate::function::Function;
+use crate::lua::Lua;
+use crate::table::Table;
+use crate::types::{Integer, LightUserData, Number};
+use crate::userdata::{AnyUserData, TryCloneToUserDataExt};
+use crate::value::{FromLua, FromLuaMulti, ToLua, ToLuaMulti, Value};
+
+/// An owned Lua value which can be sent between OS threads and moved into another Lua state.
+///
+/// Only plain data can be represented: `nil`, booleans, numbers, strings, light userdata, tables
+/// consisting of such values and userdata whose [`TypeTable`] records them as both `Clone` and
+/// `Send`. Userdata are cloned when converted from Lua, so the original is left untouched. Tables
+/// are copied recursively; metatables are not preserved and recursive tables cannot be
+/// represented.
+///
+/// Requires `feature = "send"`
+///
+/// [`TypeTable`]: hv_alchemy::TypeTable
+#[derive(Debug)]
+pub enum SendValue {
+    /// The Lua value `nil`.
+    Nil,
+    /// The Lua value `true` or `false`.
+    Boolean(bool),
+    /// A "light userdata" object, stored as its address.
+    LightUserData(usize),
+    /// An integer number.
+    Integer(Integer),
+    /// A floating point number.
+    Number(Number),
+    /// A Lua string, as raw bytes.
+    String(Vec<u8>),
+    /// The key-value pairs of a Lua table.
+    Table(Vec<(SendValue, SendValue)>),
+    /// A clone of a userdata value.
+    UserData(SendUserData),
+}
+
+/// A clone of a userdata value whose type is `Clone + Send`, detached from any Lua state.
+///
+/// Requires `feature = "send"`
+pub struct SendUserData(Box<dyn AlchemicalAny>);
+
+// SAFETY: `SendUserData` can only be constructed from values whose type table records `Send`.
+unsafe impl Send for SendUserData {}
+
+impl SendUserData {
+    /// Clones the value out of a userdata, if its type is registered as `Clone + Send`.
+    pub fn from_user_data(ud: &AnyUserData) -> Result<Self> {
+        let type_table = ud.type_table().ok_or(Error::UserDataTypeMismatch)?;
+        if !type_table.is::<dyn Send>() || !type_table.is::<dyn TryCloneToUserDataExt>() {
+            return Err(Error::UserDataDynMismatch);
+        }
+
+        let cloned = ud
+            .dyn_borrow::<dyn AlchemicalAny>()?
+            .try_clone()
+            .ok_or(Error::UserDataDynMismatch)?;
+        Ok(SendUserData(cloned))
+    }
+
+    /// Moves the value into a new userdata in the given Lua state.
+    pub fn into_user_data(self, lua: &Lua) -> Result<AnyUserData> {
+        self.0
+            .dyncast::<dyn TryCloneToUserDataExt>()
+            .ok_or(Error::UserDataDynMismatch)?
+            .into_user_data(lua)
+    }
+}
+
+impl fmt::Debug for SendUserData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SendUserData({})", self.0.type_table().type_name)
+    }
+}
+
+impl SendValue {
+    fn from_lua_value(value: Value, visited: &mut HashSet<*const c_void>) -> Result<Self> {
+        Ok(match value {
+            Value::Nil => SendValue::Nil,
+            Value::Boolean(b) => SendValue::Boolean(b),
+            Value::LightUserData(LightUserData(ptr)) => SendValue::LightUserData(ptr as usize),
+            Value::Integer(i) => SendValue::Integer(i),
+            Value::Number(n) => SendValue::Number(n),
+            Value::String(s) => SendValue::String(s.as_bytes().to_vec()),
+            Value::Table(t) => SendValue::from_table(t, visited)?,
+            Value::UserData(ud) => SendValue::UserData(SendUserData::from_user_data(&ud)?),
+            value => {
+                return Err(Error::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: "SendValue",
+                    message: Some("only plain data can be sent to another Lua state".to_string()),
+                })
+            }
+        })
+    }
+
+    fn from_table(table: Table, visited: &mut HashSet<*const c_void>) -> Result<Self> {
+        let ptr = table.0.to_pointer();
+        if !visited.insert(ptr) {
+            return Err(Error::FromLuaConversionError {
+                from: "table",
+                to: "SendValue",
+                message: Some("recursive table detected".to_string()),
+            });
+        }
+
+        let mut pairs = Vec::new();
+        for pair in table.pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            pairs.push((
+                SendValue::from_lua_value(key, visited)?,
+                SendValue::from_lua_value(value, visited)?,
+            ));
+        }
+
+        visited.remove(&ptr);
+        Ok(SendValue::Table(pairs))
+    }
+}
+
+impl<'lua> FromLua<'lua> for SendValue {
+    fn from_lua(lua_value: Value<'lua>, _: &'lua Lua) -> Result<Self> {
+        SendValue::from_lua_value(lua_value, &mut HashSet::new())
+    }
+}
+
+impl<'lua> ToLua<'lua> for SendValue {
+    fn to_lua(self, lua: &'lua Lua) -> Result<Value<'lua>> {
+        Ok(match self {
+            SendValue::Nil => Value::Nil,
+            SendValue::Boolean(b) => Value::Boolean(b),
+            SendValue::LightUserData(ptr) => Value::LightUserData(LightUserData(ptr as *mut _)),
+            SendValue::Integer(i) => Value::Integer(i),
+            SendValue::Number(n) => Value::Number(n),
+            SendValue::String(s) => Value::String(lua.create_string(&s)?),
+            SendValue::Table(pairs) => {
+                let table = lua.create_table_with_capacity(0, pairs.len() as i32)?;
+                for (key, value) in pairs {
+                    table.raw_set(key, value)?;
+                }
+                Value::Table(table)
+            }
+            SendValue::UserData(ud) => Value::UserData(ud.into_user_data(lua)?),
+        })
+    }
+}
+
+type Job = Box<dyn FnOnce(&Lua) + Send>;
+
+/// A fixed-size pool of Lua states, each running on its own OS thread.
+///
+/// Every state is initialized by the same function when the pool is created, which is expected to
+/// define the global functions jobs can then be dispatched to with [`LuaPool::call`]. Jobs are
+/// picked up by whichever state is idle first, so the functions should not rely on any state
+/// persisting between calls.
+///
+/// Arguments and results are converted to and from Lua inside the worker thread, so they must be
+/// `Send` Rust values. [`SendValue`] can be used to pass arbitrary plain Lua data around.
+///
+/// Dropping the pool waits for all queued jobs to finish.
+///
+/// Requires `feature = "send"`
+pub struct LuaPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    pending: Arc<AtomicUsize>,
+}
+
+impl LuaPool {
+    /// Creates a pool of `size` Lua states, calling `init` on each of them.
+    ///
+    /// Returns the first error returned by `init`, if any.
+    pub fn new<F>(size: usize, init: F) -> Result<LuaPool>
+    where
+        F: Fn(&Lua) -> Result<()> + Send + Sync + 'static,
+    {
+        assert!(size > 0, "cannot create an empty Lua pool");
+
+        let init = Arc::new(init);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (init_sender, init_receiver) = mpsc::channel();
+
+        let workers = (0..size)
+            .map(|i| {
+                let init = Arc::clone(&init);
+                let receiver = Arc::clone(&receiver);
+                let init_sender = init_sender.clone();
+                thread::Builder::new()
+                    .name(format!("lua-pool-{}", i))
+                    .spawn(move || {
+                        let lua = Lua::new();
+                        let res = init(&lua);
+                        let ok = res.is_ok();
+                        let _ = init_sender.send(res);
+                        if ok {
+                            worker_loop(&lua, &receiver);
+                        }
+                    })
+                    .map_err(Error::external)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        drop(init_sender);
+
+        let pool = LuaPool {
+            sender: Some(sender),
+            workers,
+            pending: Arc::new(AtomicUsize::new(0)),
+        };
+
+        for _ in 0..size {
+            match init_receiver.recv() {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => return Err(err),
+                Err(_) => {
+                    return Err(Error::RuntimeError(
+                        "Lua pool worker panicked during initialization".to_string(),
+                    ))
+                }
+            }
+        }
+
+        Ok(pool)
+    }
+
+    /// Returns the number of Lua states in the pool.
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Returns the number of jobs which have been dispatched but not finished yet.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    /// Calls the global function `name` with `args` on the first available Lua state.
+    ///
+    /// The returned [`LuaJob`] can be awaited or waited on to get the result of the call.
+    pub fn call<A, R>(&self, name: &str, args: A) -> LuaJob<R>
+    where
+        A: for<'lua> ToLuaMulti<'lua> + Send + 'static,
+        R: for<'lua> FromLuaMulti<'lua> + Send + 'static,
+    {
+        let name = name.to_string();
+        self.execute(move |lua| {
+            let func: Function = lua.globals().get(name.as_str())?;
+            func.call(args)
+        })
+    }
+
+    /// Runs an arbitrary closure on the first available Lua state.
+    pub fn execute<F, R>(&self, f: F) -> LuaJob<R>
+    where
+        F: FnOnce(&Lua) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let shared = Arc::new(JobShared {
+            state: Mutex::new(JobState {
+                result: None,
+                waker: None,
+            }),
+            done: Condvar::new(),
+        });
+
+        let job_shared = Arc::clone(&shared);
+        let pending = Arc::clone(&self.pending);
+        pending.fetch_add(1, Ordering::AcqRel);
+        let job: Job = Box::new(move |lua| {
+            let result = catch_unwind(AssertUnwindSafe(|| f(lua)))
+                .unwrap_or_else(|_| Err(Error::RuntimeError("Lua pool job panicked".to_string())));
+            // The job stops counting as pending before its result is visible, so that a caller
+            // woken by the result never sees it still pending.
+            pending.fetch_sub(1, Ordering::AcqRel);
+            job_shared.complete(result);
+        });
+
+        let sender = self.sender.as_ref().expect("Lua pool is shut down");
+        if let Err(mpsc::SendError(_)) = sender.send(job) {
+            self.pending.fetch_sub(1, Ordering::AcqRel);
+            shared.complete(Err(Error::RuntimeError(
+                "all Lua pool workers have stopped".to_string(),
+            )));
+        }
+
+        LuaJob { shared }
+    }
+
+    fn shutdown(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for LuaPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl fmt::Debug for LuaPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LuaPool")
+            .field("size", &self.size())
+            .field("pending", &self.pending())
+            .finish()
+    }
+}
+
+fn worker_loop(lua: &Lua, receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        match job {
+            Ok(job) => job(lua),
+            Err(_) => return,
+        }
+        lua.expire_registry_values();
+    }
+}
+
+struct JobState<R> {
+    result: Option<Result<R>>,
+    waker: Option<Waker>,
+}
+
+struct JobShared<R> {
+    state: Mutex<JobState<R>>,
+    done: Condvar,
+}
+
+impl<R> JobShared<R> {
+    fn complete(&self, result: Result<R>) {
+        let mut state = self.state.lock().expect("Lua job state poisoned");
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.done.notify_all();
+    }
+}
+
+/// The pending result of a job dispatched to a [`LuaPool`].
+///
+/// A `LuaJob` is a [`Future`], and can also be waited on synchronously with [`LuaJob::wait`].
+///
+/// Requires `feature = "send"`
+pub struct LuaJob<R> {
+    shared: Arc<JobShared<R>>,
+}
+
+impl<R> LuaJob<R> {
+    /// Returns `true` if the job has finished.
+    pub fn is_done(&self) -> bool {
+        let state = self.shared.state.lock().expect("Lua job state poisoned");
+        state.result.is_some()
+    }
+
+    /// Blocks the current thread until the job has finished, and returns its result.
+    pub fn wait(self) -> Result<R> {
+        let mut state = self.shared.state.lock().expect("Lua job state poisoned");
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self
+                .shared
+                .done
+                .wait(state)
+                .expect("Lua job state poisoned");
+        }
+    }
+}
+
+impl<R> Future for LuaJob<R> {
+    type Output = Result<R>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().expect("Lua job state poisoned");
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<R> fmt::Debug for LuaJob<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LuaJob")
+            .field("done", &self.is_done())
+            .finish()
+    }
+}
@@ -310,6 +310,29 @@ impl<'lua> Table<'lua> {
         }
     }
 
+    // Returns the key and value following `key` in the table, like the Lua `next` function.
+    pub(crate) fn raw_next(&self, key: Value<'lua>) -> Result<Option<(Value<'lua>, Value<'lua>)>> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 5)?;
+
+            lua.push_ref(&self.0);
+            lua.push_value(key)?;
+
+            let next = protect_lua!(lua.state, 2, ffi::LUA_MULTRET, |state| {
+                ffi::lua_next(state, -2)
+            })?;
+            if next != 0 {
+                let value = lua.pop_value();
+                let key = lua.pop_value();
+                Ok(Some((key, value)))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+
     /// Returns a reference to the metatable of this table, or `None` if no metatable is set.
     ///
     /// Unlike the `getmetatable` Lua function, this method ignores the `__metatable` field.
@@ -383,7 +406,7 @@ impl<'lua> Table<'lua> {
     /// [Lua manual]: http://www.lua.org/manual/5.3/manual.html#pdf-next
     pub fn pairs<K: FromLua<'lua>, V: FromLua<'lua>>(self) -> TablePairsIter<'lua, K, V> {
         TablePairsIter {
-            table: self.0,
+            table: self,
             key: Some(Nil),
             _phantom: PhantomData,
         }
@@ -668,7 +691,7 @@ impl<'lua> Serialize for Table<'lua> {
 ///
 /// [`Table::pairs`]: crate::Table::pairs
 pub struct TablePairsIter<'lua, K, V> {
-    table: LuaRef<'lua>,
+    table: Table<'lua>,
     key: Option<Value<'lua>>,
     _phantom: PhantomData<(K, V)>,
 }
@@ -682,29 +705,15 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(prev_key) = self.key.take() {
-            let lua = self.table.lua;
-
-            let res = (|| unsafe {
-                let _sg = StackGuard::new(lua.state);
-                check_stack(lua.state, 5)?;
-
-                lua.push_ref(&self.table);
-                lua.push_value(prev_key)?;
-
-                let next = protect_lua!(lua.state, 2, ffi::LUA_MULTRET, |state| {
-                    ffi::lua_next(state, -2)
-                })?;
-                if next != 0 {
-                    let value = lua.pop_value();
-                    let key = lua.pop_value();
-                    Ok(Some((
-                        key.clone(),
-                        K::from_lua(key, lua)?,
-                        V::from_lua(value, lua)?,
-                    )))
-                } else {
-                    Ok(None)
-                }
+            let lua = self.table.0.lua;
+
+            let res = (|| match self.table.raw_next(prev_key)? {
+                Some((key, value)) => Ok(Some((
+                    key.clone(),
+                    K::from_lua(key, lua)?,
+                    V::from_lua(value, lua)?,
+                ))),
+                None => Ok(None),
             })();
 
             match res {
@@ -1,15 +1,19 @@
 use std::cmp;
 use std::os::raw::c_int;
+use std::sync::Arc;
 
 use crate::error::{Error, Result};
 use crate::ffi;
-use crate::types::LuaRef;
+use crate::types::{LuaRef, MaybeSend, MaybeSync};
 use crate::util::{check_stack, error_traceback, pop_error, StackGuard};
 use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti};
 
 #[cfg(any(feature = "lua54", all(feature = "luajit", feature = "vendored"), doc))]
 use crate::function::Function;
 
+#[cfg(any(feature = "lua54", feature = "lua53", doc))]
+use crate::hook::{preemption_hook_proc, PREEMPTIBLE_REGISTRY_KEY};
+
 #[cfg(feature = "async")]
 use {
     crate::{
@@ -35,6 +39,17 @@ pub enum ThreadStatus {
     ///
     /// [`Thread::resume`]: crate::Thread::resume
     Resumable,
+    /// The thread was suspended by its preemption hook after running out of its instruction budget.
+    ///
+    /// A preempted thread can be resumed by calling [`Thread::resume`]; any arguments passed to the
+    /// resumption are discarded and the thread continues exactly where it was interrupted.
+    ///
+    /// Only threads with preemption enabled through [`Thread::set_preemption`] can end up in this
+    /// state.
+    ///
+    /// [`Thread::resume`]: crate::Thread::resume
+    /// [`Thread::set_preemption`]: crate::Thread::set_preemption
+    Preempted,
     /// Either the thread has finished executing, or the thread is currently running.
     Unresumable,
     /// The thread has raised a Lua error during execution.
@@ -130,6 +145,7 @@ impl<'lua> Thread<'lua> {
 
             let mut nresults = 0;
 
+            let _resumed = lua.enter_resumed_thread(thread_state);
             let ret = ffi::lua_resume(thread_state, lua.state, nargs, &mut nresults as *mut c_int);
             if ret != ffi::LUA_OK && ret != ffi::LUA_YIELD {
                 protect_lua!(lua.state, 0, 0, |_| error_traceback(thread_state))?;
@@ -158,6 +174,8 @@ impl<'lua> Thread<'lua> {
             let status = ffi::lua_status(thread_state);
             if status != ffi::LUA_OK && status != ffi::LUA_YIELD {
                 ThreadStatus::Error
+            } else if status == ffi::LUA_YIELD && is_preempted(thread_state) {
+                ThreadStatus::Preempted
             } else if status == ffi::LUA_YIELD || ffi::lua_gettop(thread_state) > 0 {
                 ThreadStatus::Resumable
             } else {
@@ -166,6 +184,122 @@ impl<'lua> Thread<'lua> {
         }
     }
 
+    /// Enables or disables preemptive time-slicing of this thread.
+    ///
+    /// When enabled, the thread is automatically suspended after every `every_nth_instruction` VM
+    /// instructions. [`Thread::resume`] then returns no values and [`Thread::status`] reports
+    /// [`ThreadStatus::Preempted`]; resuming the thread again continues execution where it was
+    /// interrupted. This allows the host to spread a long-running script over several frames
+    /// instead of letting it stall the current one.
+    ///
+    /// The preemption hook replaces any hook previously set on this thread (including one inherited
+    /// from [`Lua::set_hook`]). Coroutines created from inside a preemptible thread are never
+    /// preempted themselves, so `coroutine.resume`/`coroutine.wrap` keep their usual semantics.
+    ///
+    /// Passing `None` disables preemption and removes the hook.
+    ///
+    /// Requires `feature = "lua54/lua53"`
+    ///
+    /// [`Thread::resume`]: crate::Thread::resume
+    /// [`Thread::status`]: crate::Thread::status
+    /// [`ThreadStatus::Preempted`]: crate::ThreadStatus::Preempted
+    /// [`Lua::set_hook`]: crate::Lua::set_hook
+    #[cfg(any(feature = "lua54", feature = "lua53", doc))]
+    pub fn set_preemption(&self, every_nth_instruction: Option<u32>) -> Result<()> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            check_stack(lua.state, 5)?;
+
+            let preemptible_key = &PREEMPTIBLE_REGISTRY_KEY as *const u8 as *const _;
+            if ffi::lua_rawgetp(lua.state, ffi::LUA_REGISTRYINDEX, preemptible_key)
+                != ffi::LUA_TTABLE
+            {
+                ffi::lua_pop(lua.state, 1);
+                // Threads are weak keys, so marking a thread preemptible does not keep it alive
+                protect_lua!(lua.state, 0, 1, fn(state) {
+                    ffi::lua_newtable(state);
+                    ffi::lua_createtable(state, 0, 1);
+                    ffi::lua_pushstring(state, cstr!("k"));
+                    ffi::lua_setfield(state, -2, cstr!("__mode"));
+                    ffi::lua_setmetatable(state, -2);
+                    ffi::lua_pushvalue(state, -1);
+                    ffi::lua_rawsetp(state, ffi::LUA_REGISTRYINDEX, &PREEMPTIBLE_REGISTRY_KEY as *const u8 as *const _);
+                })?;
+            }
+
+            lua.push_ref(&self.0);
+            let thread_state = ffi::lua_tothread(lua.state, -1);
+            match every_nth_instruction {
+                Some(count) => {
+                    ffi::lua_pushboolean(lua.state, 1);
+                    protect_lua!(lua.state, 3, 1, fn(state) ffi::lua_rawset(state, -3))?;
+                    ffi::lua_sethook(
+                        thread_state,
+                        Some(preemption_hook_proc),
+                        ffi::LUA_MASKCOUNT,
+                        count as c_int,
+                    );
+                }
+                None => {
+                    ffi::lua_pushnil(lua.state);
+                    protect_lua!(lua.state, 3, 1, fn(state) ffi::lua_rawset(state, -3))?;
+                    ffi::lua_sethook(thread_state, None, 0, 0);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets or replaces the context value of type `T` of this thread.
+    ///
+    /// Context values are scoped to the thread (or coroutine) they are attached to, which lets Rust
+    /// callbacks find out on whose behalf they are running by calling [`Lua::current_context()`]
+    /// instead of having that information passed around through every argument.
+    ///
+    /// Threads created from Rust with [`Lua::create_thread()`] inherit a copy of the context of the
+    /// running code, taken at creation time. Coroutines created from Lua with `coroutine.create` or
+    /// `coroutine.wrap` have no context of their own unless one is set on them; while they run
+    /// inside a thread resumed from Rust with [`Thread::resume()`], [`Lua::current_context()`] sees
+    /// that thread's context, whether the coroutine was created before or after it was set.
+    ///
+    /// [`Lua::current_context()`]: crate::Lua::current_context
+    /// [`Lua::create_thread()`]: crate::Lua::create_thread
+    /// [`Thread::resume()`]: crate::Thread::resume
+    pub fn set_context<T: 'static + MaybeSend + MaybeSync>(&self, data: T) -> Result<()> {
+        let lua = self.0.lua;
+        let mut context = lua.get_context_of(&self.0).unwrap_or_default();
+        context.insert(data);
+        lua.set_context_of(&self.0, Some(context))
+    }
+
+    /// Gets the context value of type `T` attached to this thread, set by [`Thread::set_context()`]
+    /// either on this thread or on the code running when it was created from Rust.
+    ///
+    /// [`Thread::set_context()`]: crate::Thread::set_context
+    pub fn context<T: 'static + MaybeSend + MaybeSync>(&self) -> Option<Arc<T>> {
+        let context = self.0.lua.get_context_of(&self.0)?;
+        context.get()
+    }
+
+    /// Removes the context value of type `T` from this thread, returning it if it was present.
+    ///
+    /// Threads that already inherited the value keep their copy.
+    pub fn remove_context<T: 'static + MaybeSend + MaybeSync>(&self) -> Result<Option<Arc<T>>> {
+        let lua = self.0.lua;
+        let mut context = match lua.get_context_of(&self.0) {
+            Some(context) => context,
+            None => return Ok(None),
+        };
+        let data = context.remove::<T>();
+        if context.0.is_empty() {
+            lua.set_context_of(&self.0, None)?;
+        } else {
+            lua.set_context_of(&self.0, Some(context))?;
+        }
+        Ok(data)
+    }
+
     /// Resets a thread
     ///
     /// In [Lua 5.4]: cleans its call stack and closes all pending to-be-closed variables.
@@ -264,6 +398,27 @@ impl<'lua> Thread<'lua> {
     }
 }
 
+// A thread suspended by a hook is left with its Lua function on top of the call stack, whereas a
+// regular `coroutine.yield` leaves the (C) yield function there.
+#[cfg(any(feature = "lua54", feature = "lua53"))]
+unsafe fn is_preempted(thread_state: *mut ffi::lua_State) -> bool {
+    let mut ar: ffi::lua_Debug = std::mem::zeroed();
+    if ffi::lua_getstack(thread_state, 0, &mut ar) == 0 {
+        return false;
+    }
+    mlua_assert!(
+        ffi::lua_getinfo(thread_state, cstr!("S"), &mut ar) != 0,
+        "lua_getinfo failed with `S`"
+    );
+    !ar.what.is_null() && std::ffi::CStr::from_ptr(ar.what).to_bytes() != b"C"
+}
+
+#[cfg(not(any(feature = "lua54", feature = "lua53")))]
+#[inline(always)]
+unsafe fn is_preempted(_thread_state: *mut ffi::lua_State) -> bool {
+    false
+}
+
 impl<'lua> PartialEq for Thread<'lua> {
     fn eq(&self, other: &Self) -> bool {
         self.0 == other.0
@@ -281,7 +436,7 @@ where
         let lua = self.thread.0.lua;
 
         match self.thread.status() {
-            ThreadStatus::Resumable => {}
+            ThreadStatus::Resumable | ThreadStatus::Preempted => {}
             _ => return Poll::Ready(None),
         };
 
@@ -297,6 +452,11 @@ where
         }
 
         cx.waker().wake_by_ref();
+        if let ThreadStatus::Preempted = self.thread.status() {
+            // Preemption is not a yield, so there is no value to produce yet
+            return Poll::Pending;
+        }
+
         Poll::Ready(Some(R::from_lua_multi(ret, lua)))
     }
 }
@@ -312,7 +472,7 @@ where
         let lua = self.thread.0.lua;
 
         match self.thread.status() {
-            ThreadStatus::Resumable => {}
+            ThreadStatus::Resumable | ThreadStatus::Preempted => {}
             _ => return Poll::Ready(Err(Error::CoroutineInactive)),
         };
 
@@ -327,7 +487,7 @@ where
             return Poll::Pending;
         }
 
-        if let ThreadStatus::Resumable = self.thread.status() {
+        if let ThreadStatus::Resumable | ThreadStatus::Preempted = self.thread.status() {
             // Ignore value returned via yield()
             cx.waker().wake_by_ref();
             return Poll::Pending;
@@ -1,3 +1,4 @@
+use std::any::{Any, TypeId};
 use std::cell::RefCell;
 use std::hash::{Hash, Hasher};
 use std::os::raw::{c_int, c_void};
@@ -7,6 +8,8 @@ use std::{fmt, mem, ptr};
 #[cfg(feature = "async")]
 use futures_core::future::LocalBoxFuture;
 
+use rustc_hash::FxHashMap;
+
 use crate::error::Result;
 use crate::ffi;
 use crate::hook::Debug;
@@ -53,6 +56,45 @@ pub(crate) type HookCallback = Arc<RefCell<dyn FnMut(&Lua, Debug) -> Result<()>
 #[cfg(not(feature = "send"))]
 pub(crate) type HookCallback = Arc<RefCell<dyn FnMut(&Lua, Debug) -> Result<()>>>;
 
+// Context values attached to a thread, see `Thread::set_context`.
+#[cfg(feature = "send")]
+#[derive(Clone, Default)]
+pub(crate) struct ThreadContext(pub(crate) FxHashMap<TypeId, Arc<dyn Any + Send + Sync>>);
+
+#[cfg(not(feature = "send"))]
+#[derive(Clone, Default)]
+pub(crate) struct ThreadContext(pub(crate) FxHashMap<TypeId, Arc<dyn Any>>);
+
+impl ThreadContext {
+    pub(crate) fn insert<T: 'static + MaybeSend + MaybeSync>(&mut self, data: T) {
+        self.0.insert(TypeId::of::<T>(), Arc::new(data));
+    }
+
+    pub(crate) fn get<T: 'static + MaybeSend + MaybeSync>(&self) -> Option<Arc<T>> {
+        downcast_context(self.0.get(&TypeId::of::<T>())?.clone())
+    }
+
+    pub(crate) fn remove<T: 'static + MaybeSend + MaybeSync>(&mut self) -> Option<Arc<T>> {
+        downcast_context(self.0.remove(&TypeId::of::<T>())?)
+    }
+}
+
+#[cfg(feature = "send")]
+fn downcast_context<T: 'static + Send + Sync>(data: Arc<dyn Any + Send + Sync>) -> Option<Arc<T>> {
+    data.downcast().ok()
+}
+
+// `Arc<dyn Any>` has no `downcast`, so check the type and cast the pointer as it would.
+#[cfg(not(feature = "send"))]
+fn downcast_context<T: 'static>(data: Arc<dyn Any>) -> Option<Arc<T>> {
+    if data.is::<T>() {
+        // Safety: the value is a `T`, so the data pointer of the `Arc` points to a `T`.
+        Some(unsafe { Arc::from_raw(Arc::into_raw(data) as *const T) })
+    } else {
+        None
+    }
+}
+
 #[cfg(feature = "send")]
 pub trait MaybeSend: Send {}
 #[cfg(feature = "send")]
@@ -143,6 +185,16 @@ pub(crate) struct LuaRef<'lua> {
     pub(crate) index: c_int,
 }
 
+impl<'lua> LuaRef<'lua> {
+    // Returns the address of the referenced object, which identifies it within its Lua state.
+    pub(crate) fn to_pointer(&self) -> *const c_void {
+        unsafe {
+            self.lua
+                .ref_thread_exec(|ref_thread| ffi::lua_topointer(ref_thread, self.index))
+        }
+    }
+}
+
 impl<'lua> fmt::Debug for LuaRef<'lua> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Ref({})", self.index)
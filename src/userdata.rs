@@ -1,4 +1,7 @@
-use std::{any::TypeId, mem::MaybeUninit};
+use std::{
+    any::{Any, TypeId},
+    mem::MaybeUninit,
+};
 use std::{
     cell::{Ref, RefCell, RefMut},
     marker::PhantomData,
@@ -27,7 +30,7 @@ use hv_guarded_borrow::{NonBlockingGuardedBorrow, NonBlockingGuardedMutBorrowMut
 
 use crate::types::{Callback, LuaRef, MaybeSend};
 use crate::util::{check_stack, get_userdata, take_userdata, StackGuard};
-use crate::value::{FromLua, FromLuaMulti, ToLua, ToLuaMulti};
+use crate::value::{FromLua, FromLuaMulti, MultiValue, ToLua, ToLuaMulti};
 use crate::{
     error::{Error, Result},
     types::DestructedUserdataMT,
@@ -515,6 +518,9 @@ pub trait UserDataMethods<'lua, T: UserData> {
         _callback: AsyncCallback<'lua, 'static>,
     ) {
     }
+
+    #[doc(hidden)]
+    fn add_proxy_target(&mut self, _target: UserDataProxyTarget) {}
 }
 
 /// Field registry for [`UserData`] implementors.
@@ -1806,11 +1812,12 @@ where
 
 impl<'a, 'lua, T, U, M> UserDataMethodsProxy<'a, 'lua, T, U, M, Mutable>
 where
-    T: UserData + NonBlockingGuardedBorrow<U> + NonBlockingGuardedMutBorrowMut<U>,
-    U: UserData,
+    T: 'static + UserData + NonBlockingGuardedBorrow<U> + NonBlockingGuardedMutBorrowMut<U>,
+    U: 'static + UserData,
     M: UserDataMethods<'lua, T>,
 {
     pub fn new(methods: &'a mut M) -> Self {
+        methods.add_proxy_target(UserDataProxyTarget::new::<T, U>());
         Self {
             inner: methods,
             _phantom: PhantomData,
@@ -1820,11 +1827,12 @@ where
 
 impl<'a, 'lua, T, U, M> UserDataMethodsProxy<'a, 'lua, T, U, M, Immutable>
 where
-    T: UserData + NonBlockingGuardedBorrow<U>,
-    U: UserData,
+    T: 'static + UserData + NonBlockingGuardedBorrow<U>,
+    U: 'static + UserData,
     M: UserDataMethods<'lua, T>,
 {
     pub fn new_immutable(methods: &'a mut M) -> Self {
+        methods.add_proxy_target(UserDataProxyTarget::new_immutable::<T, U>());
         Self {
             inner: methods,
             _phantom: PhantomData,
@@ -1832,6 +1840,113 @@ where
     }
 }
 
+/// Borrows the `U` inside of a userdata of a proxy type, and calls a function with it.
+pub(crate) type ProxyBorrowFn<U> = for<'lua, 'a> fn(
+    &AnyUserData<'lua>,
+    Box<dyn FnOnce(&U) -> Result<MultiValue<'lua>> + 'a>,
+) -> Result<MultiValue<'lua>>;
+
+/// Mutably borrows the `U` inside of a userdata of a proxy type, and calls a function with it.
+pub(crate) type ProxyBorrowMutFn<U> = for<'lua, 'a> fn(
+    &AnyUserData<'lua>,
+    Box<dyn FnOnce(&mut U) -> Result<MultiValue<'lua>> + 'a>,
+) -> Result<MultiValue<'lua>>;
+
+/// How the methods and fields of `U` reach the `U` inside of a userdata of a proxy type.
+pub(crate) struct ProxyBorrow<U> {
+    pub(crate) borrow: ProxyBorrowFn<U>,
+    pub(crate) borrow_mut: ProxyBorrowMutFn<U>,
+}
+
+impl<U> Clone for ProxyBorrow<U> {
+    fn clone(&self) -> Self {
+        Self {
+            borrow: self.borrow,
+            borrow_mut: self.borrow_mut,
+        }
+    }
+}
+
+impl<U> Copy for ProxyBorrow<U> {}
+
+fn proxy_borrow<'lua, 'a, T, U>(
+    userdata: &AnyUserData<'lua>,
+    f: Box<dyn FnOnce(&U) -> Result<MultiValue<'lua>> + 'a>,
+) -> Result<MultiValue<'lua>>
+where
+    T: 'static + UserData + NonBlockingGuardedBorrow<U>,
+{
+    let this = userdata.borrow::<T>()?;
+    let guard = this
+        .try_nonblocking_guarded_borrow()
+        .map_err(|_| Error::UserDataProxyBorrowError)?;
+    f(&*guard)
+}
+
+fn proxy_borrow_mut<'lua, 'a, T, U>(
+    userdata: &AnyUserData<'lua>,
+    f: Box<dyn FnOnce(&mut U) -> Result<MultiValue<'lua>> + 'a>,
+) -> Result<MultiValue<'lua>>
+where
+    T: 'static + UserData + NonBlockingGuardedMutBorrowMut<U>,
+{
+    let mut this = userdata.borrow_mut::<T>()?;
+    let mut guard = this
+        .try_nonblocking_guarded_mut_borrow_mut()
+        .map_err(|_| Error::UserDataProxyBorrowMutError)?;
+    f(&mut *guard)
+}
+
+fn proxy_borrow_mut_denied<'lua, 'a, U>(
+    _userdata: &AnyUserData<'lua>,
+    _f: Box<dyn FnOnce(&mut U) -> Result<MultiValue<'lua>> + 'a>,
+) -> Result<MultiValue<'lua>> {
+    Err(Error::UserDataProxyBorrowMutError)
+}
+
+/// The type whose methods and fields a proxy type such as [`Arc<RwLock<U>>`] forwards, as
+/// collected by [`UserDataMethodsProxy`]. The metatable of the proxy type ends its `__index` and
+/// `__newindex` lookups with those of `U`, so that methods and fields added to `U` with
+/// [`Lua::extend_userdata_type`] are found on the proxy type too.
+#[doc(hidden)]
+pub struct UserDataProxyTarget {
+    pub(crate) type_id: TypeId,
+    pub(crate) borrow: Box<dyn Any + Send>,
+    pub(crate) push_metatable: unsafe fn(&Lua) -> Result<()>,
+}
+
+impl UserDataProxyTarget {
+    fn with_borrow<U: 'static + UserData>(borrow: ProxyBorrow<U>) -> Self {
+        Self {
+            type_id: TypeId::of::<U>(),
+            borrow: Box::new(borrow),
+            push_metatable: |lua| unsafe { lua.push_userdata_metatable::<U>() },
+        }
+    }
+
+    fn new<T, U>() -> Self
+    where
+        T: 'static + UserData + NonBlockingGuardedBorrow<U> + NonBlockingGuardedMutBorrowMut<U>,
+        U: 'static + UserData,
+    {
+        Self::with_borrow(ProxyBorrow::<U> {
+            borrow: proxy_borrow::<T, U>,
+            borrow_mut: proxy_borrow_mut::<T, U>,
+        })
+    }
+
+    fn new_immutable<T, U>() -> Self
+    where
+        T: 'static + UserData + NonBlockingGuardedBorrow<U>,
+        U: 'static + UserData,
+    {
+        Self::with_borrow(ProxyBorrow::<U> {
+            borrow: proxy_borrow::<T, U>,
+            borrow_mut: proxy_borrow_mut_denied::<U>,
+        })
+    }
+}
+
 /// A proxy for [`UserDataFields`] which automatically forwards field accessors for some type `T`
 /// which allows guarded borrowing access to a wrapped type `U`. For example, this is used to
 /// implement [`UserData`] for types like [`Arc<RwLock<U>>`]. For the [`UserDataMethods`]
@@ -2063,6 +2178,16 @@ impl<T: 'static + UserData + MaybeSend + MaybeSync> UserData for Arc<RwLock<T>>
 
 pub trait TryCloneToUserDataExt {
     fn try_clone_to_user_data<'lua>(&self, lua: &'lua Lua) -> Result<AnyUserData<'lua>>;
+
+    /// Moves a boxed value into a new userdata, provided its type is `Send`.
+    ///
+    /// The default implementation falls back to [`try_clone_to_user_data`] and drops the original,
+    /// so existing implementors keep working.
+    ///
+    /// [`try_clone_to_user_data`]: TryCloneToUserDataExt::try_clone_to_user_data
+    fn into_user_data<'lua>(self: Box<Self>, lua: &'lua Lua) -> Result<AnyUserData<'lua>> {
+        self.try_clone_to_user_data(lua)
+    }
 }
 
 impl<T: 'static + UserData> TryCloneToUserDataExt for T {
@@ -2076,4 +2201,12 @@ impl<T: 'static + UserData> TryCloneToUserDataExt for T {
             Err(Error::UserDataDynMismatch)
         }
     }
+
+    fn into_user_data<'lua>(self: Box<Self>, lua: &'lua Lua) -> Result<AnyUserData<'lua>> {
+        if hv_alchemy::of::<T>().is::<dyn Send>() {
+            unsafe { lua.make_userdata::<T>(UserDataCell::new(*self)) }
+        } else {
+            Err(Error::UserDataDynMismatch)
+        }
+    }
 }
@@ -348,6 +348,94 @@ pub unsafe fn get_gc_userdata<T: Any>(state: *mut ffi::lua_State, index: c_int)
     ud
 }
 
+// Wrapper to lookup in `field_getters` first, then `methods`, ending original `__index`.
+// Used only if `field_getters` or `methods` set.
+unsafe extern "C" fn meta_index_impl(state: *mut ffi::lua_State) -> c_int {
+    // stack: self, key
+    ffi::luaL_checkstack(state, 2, ptr::null());
+
+    // lookup in `field_getters` table
+    if ffi::lua_isnil(state, ffi::lua_upvalueindex(2)) == 0 {
+        ffi::lua_pushvalue(state, -1); // `key` arg
+        if ffi::lua_rawget(state, ffi::lua_upvalueindex(2)) != ffi::LUA_TNIL {
+            ffi::lua_insert(state, -3); // move function
+            ffi::lua_pop(state, 1); // remove `key`
+            ffi::lua_call(state, 1, 1);
+            return 1;
+        }
+        ffi::lua_pop(state, 1); // pop the nil value
+    }
+    // lookup in `methods` table
+    if ffi::lua_isnil(state, ffi::lua_upvalueindex(3)) == 0 {
+        ffi::lua_pushvalue(state, -1); // `key` arg
+        if ffi::lua_rawget(state, ffi::lua_upvalueindex(3)) != ffi::LUA_TNIL {
+            ffi::lua_insert(state, -3);
+            ffi::lua_pop(state, 2);
+            return 1;
+        }
+        ffi::lua_pop(state, 1); // pop the nil value
+    }
+
+    // lookup in `__index`
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+    match ffi::lua_type(state, -1) {
+        ffi::LUA_TNIL => {
+            ffi::lua_pop(state, 1); // pop the nil value
+            let field = ffi::lua_tostring(state, -1);
+            ffi::luaL_error(state, cstr!("attempt to get an unknown field '%s'"), field);
+        }
+        ffi::LUA_TTABLE => {
+            ffi::lua_insert(state, -2);
+            ffi::lua_gettable(state, -2);
+        }
+        ffi::LUA_TFUNCTION => {
+            ffi::lua_insert(state, -3);
+            ffi::lua_call(state, 2, 1);
+        }
+        _ => unreachable!(),
+    }
+
+    1
+}
+
+// Similar to `meta_index_impl`, checks `field_setters` table first, then `__newindex` metamethod.
+// Used only if `field_setters` set.
+unsafe extern "C" fn meta_newindex_impl(state: *mut ffi::lua_State) -> c_int {
+    // stack: self, key, value
+    ffi::luaL_checkstack(state, 2, ptr::null());
+
+    // lookup in `field_setters` table
+    ffi::lua_pushvalue(state, -2); // `key` arg
+    if ffi::lua_rawget(state, ffi::lua_upvalueindex(2)) != ffi::LUA_TNIL {
+        ffi::lua_remove(state, -3); // remove `key`
+        ffi::lua_insert(state, -3); // move function
+        ffi::lua_call(state, 2, 0);
+        return 0;
+    }
+    ffi::lua_pop(state, 1); // pop the nil value
+
+    // lookup in `__newindex`
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+    match ffi::lua_type(state, -1) {
+        ffi::LUA_TNIL => {
+            ffi::lua_pop(state, 1); // pop the nil value
+            let field = ffi::lua_tostring(state, -2);
+            ffi::luaL_error(state, cstr!("attempt to set an unknown field '%s'"), field);
+        }
+        ffi::LUA_TTABLE => {
+            ffi::lua_insert(state, -3);
+            ffi::lua_settable(state, -3);
+        }
+        ffi::LUA_TFUNCTION => {
+            ffi::lua_insert(state, -4);
+            ffi::lua_call(state, 3, 0);
+        }
+        _ => unreachable!(),
+    }
+
+    0
+}
+
 // Populates the given table with the appropriate members to be a userdata metatable for the given type.
 // This function takes the given table at the `metatable` index, and adds an appropriate `__gc` member
 // to it for the given type and a `__metatable` entry to protect the table from script access.
@@ -363,94 +451,6 @@ pub unsafe fn init_userdata_metatable(
     field_setters: Option<c_int>,
     methods: Option<c_int>,
 ) -> Result<()> {
-    // Wrapper to lookup in `field_getters` first, then `methods`, ending original `__index`.
-    // Used only if `field_getters` or `methods` set.
-    unsafe extern "C" fn meta_index_impl(state: *mut ffi::lua_State) -> c_int {
-        // stack: self, key
-        ffi::luaL_checkstack(state, 2, ptr::null());
-
-        // lookup in `field_getters` table
-        if ffi::lua_isnil(state, ffi::lua_upvalueindex(2)) == 0 {
-            ffi::lua_pushvalue(state, -1); // `key` arg
-            if ffi::lua_rawget(state, ffi::lua_upvalueindex(2)) != ffi::LUA_TNIL {
-                ffi::lua_insert(state, -3); // move function
-                ffi::lua_pop(state, 1); // remove `key`
-                ffi::lua_call(state, 1, 1);
-                return 1;
-            }
-            ffi::lua_pop(state, 1); // pop the nil value
-        }
-        // lookup in `methods` table
-        if ffi::lua_isnil(state, ffi::lua_upvalueindex(3)) == 0 {
-            ffi::lua_pushvalue(state, -1); // `key` arg
-            if ffi::lua_rawget(state, ffi::lua_upvalueindex(3)) != ffi::LUA_TNIL {
-                ffi::lua_insert(state, -3);
-                ffi::lua_pop(state, 2);
-                return 1;
-            }
-            ffi::lua_pop(state, 1); // pop the nil value
-        }
-
-        // lookup in `__index`
-        ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
-        match ffi::lua_type(state, -1) {
-            ffi::LUA_TNIL => {
-                ffi::lua_pop(state, 1); // pop the nil value
-                let field = ffi::lua_tostring(state, -1);
-                ffi::luaL_error(state, cstr!("attempt to get an unknown field '%s'"), field);
-            }
-            ffi::LUA_TTABLE => {
-                ffi::lua_insert(state, -2);
-                ffi::lua_gettable(state, -2);
-            }
-            ffi::LUA_TFUNCTION => {
-                ffi::lua_insert(state, -3);
-                ffi::lua_call(state, 2, 1);
-            }
-            _ => unreachable!(),
-        }
-
-        1
-    }
-
-    // Similar to `meta_index_impl`, checks `field_setters` table first, then `__newindex` metamethod.
-    // Used only if `field_setters` set.
-    unsafe extern "C" fn meta_newindex_impl(state: *mut ffi::lua_State) -> c_int {
-        // stack: self, key, value
-        ffi::luaL_checkstack(state, 2, ptr::null());
-
-        // lookup in `field_setters` table
-        ffi::lua_pushvalue(state, -2); // `key` arg
-        if ffi::lua_rawget(state, ffi::lua_upvalueindex(2)) != ffi::LUA_TNIL {
-            ffi::lua_remove(state, -3); // remove `key`
-            ffi::lua_insert(state, -3); // move function
-            ffi::lua_call(state, 2, 0);
-            return 0;
-        }
-        ffi::lua_pop(state, 1); // pop the nil value
-
-        // lookup in `__newindex`
-        ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
-        match ffi::lua_type(state, -1) {
-            ffi::LUA_TNIL => {
-                ffi::lua_pop(state, 1); // pop the nil value
-                let field = ffi::lua_tostring(state, -2);
-                ffi::luaL_error(state, cstr!("attempt to set an unknown field '%s'"), field);
-            }
-            ffi::LUA_TTABLE => {
-                ffi::lua_insert(state, -3);
-                ffi::lua_settable(state, -3);
-            }
-            ffi::LUA_TFUNCTION => {
-                ffi::lua_insert(state, -4);
-                ffi::lua_call(state, 3, 0);
-            }
-            _ => unreachable!(),
-        }
-
-        0
-    }
-
     ffi::lua_pushvalue(state, metatable);
 
     if field_getters.is_some() || methods.is_some() {
@@ -502,6 +502,120 @@ pub unsafe fn init_userdata_metatable(
     Ok(())
 }
 
+// Like `init_userdata_metatable`, but for a metatable which has already been initialized. The
+// `field_getters`, `field_setters` and `methods` tables are merged into those of its existing
+// `__index` and `__newindex` wrappers, replacing entries of the same name, rather than wrapping
+// them again. Wrappers which don't exist yet are created as in `init_userdata_metatable`.
+// Internally uses 9 stack spaces and does not call checkstack.
+pub unsafe fn extend_userdata_metatable(
+    state: *mut ffi::lua_State,
+    metatable: c_int,
+    field_getters: Option<c_int>,
+    field_setters: Option<c_int>,
+    methods: Option<c_int>,
+) -> Result<()> {
+    let metatable = ffi::lua_absindex(state, metatable);
+    let (mut new_getters, mut new_setters, mut new_methods) = (None, None, None);
+
+    if field_getters.is_some() || methods.is_some() {
+        push_string(state, "__index")?;
+        ffi::lua_rawget(state, metatable);
+        if is_cfunction(state, -1, meta_index_impl) {
+            let index = ffi::lua_absindex(state, -1);
+            merge_into_upvalue(state, index, 2, field_getters)?;
+            merge_into_upvalue(state, index, 3, methods)?;
+        } else {
+            new_getters = field_getters;
+            new_methods = methods;
+        }
+        ffi::lua_pop(state, 1);
+    }
+
+    if field_setters.is_some() {
+        push_string(state, "__newindex")?;
+        ffi::lua_rawget(state, metatable);
+        if is_cfunction(state, -1, meta_newindex_impl) {
+            let newindex = ffi::lua_absindex(state, -1);
+            merge_into_upvalue(state, newindex, 2, field_setters)?;
+        } else {
+            new_setters = field_setters;
+        }
+        ffi::lua_pop(state, 1);
+    }
+
+    if new_getters.is_some() || new_setters.is_some() || new_methods.is_some() {
+        init_userdata_metatable(state, metatable, new_getters, new_setters, new_methods)?;
+    }
+
+    Ok(())
+}
+
+// Sets the value on top of the stack as the `__index` or `__newindex` of a userdata metatable. If
+// the metatable has a wrapper for it made by `init_userdata_metatable`, the value replaces the
+// fallback the wrapper ends with, so that the fields and methods it looks up first are kept.
+// Pops the value. Uses 3 stack spaces, does not call checkstack.
+pub unsafe fn set_userdata_metatable_fallback(
+    state: *mut ffi::lua_State,
+    metatable: c_int,
+    name: &str,
+) -> Result<()> {
+    let metatable = ffi::lua_absindex(state, metatable);
+    let wrapper: ffi::lua_CFunction = match name {
+        "__index" => meta_index_impl,
+        "__newindex" => meta_newindex_impl,
+        _ => mlua_panic!("no userdata metatable wrapper for {}", name),
+    };
+
+    push_string(state, name)?;
+    ffi::lua_rawget(state, metatable);
+    if is_cfunction(state, -1, wrapper) {
+        ffi::lua_insert(state, -2);
+        ffi::lua_setupvalue(state, -2, 1);
+        ffi::lua_pop(state, 1);
+        Ok(())
+    } else {
+        ffi::lua_pop(state, 1);
+        rawset_field(state, metatable, name)
+    }
+}
+
+unsafe fn is_cfunction(state: *mut ffi::lua_State, index: c_int, f: ffi::lua_CFunction) -> bool {
+    ffi::lua_iscfunction(state, index) != 0
+        && ffi::lua_tocfunction(state, index) as usize == f as usize
+}
+
+// Copies the entries of the table at `table` into the table in upvalue `n` of the function at
+// `func`, or makes it the upvalue if there is none.
+unsafe fn merge_into_upvalue(
+    state: *mut ffi::lua_State,
+    func: c_int,
+    n: c_int,
+    table: Option<c_int>,
+) -> Result<()> {
+    let table = match table {
+        Some(table) => table,
+        None => return Ok(()),
+    };
+
+    ffi::lua_getupvalue(state, func, n);
+    if ffi::lua_isnil(state, -1) != 0 {
+        ffi::lua_pop(state, 1);
+        ffi::lua_pushvalue(state, table);
+        ffi::lua_setupvalue(state, func, n);
+        return Ok(());
+    }
+
+    ffi::lua_pushvalue(state, table);
+    protect_lua!(state, 2, 0, |state| {
+        ffi::lua_pushnil(state);
+        while ffi::lua_next(state, -2) != 0 {
+            ffi::lua_pushvalue(state, -2);
+            ffi::lua_insert(state, -2);
+            ffi::lua_rawset(state, -5);
+        }
+    })
+}
+
 pub unsafe extern "C" fn userdata_cell_destructor(state: *mut ffi::lua_State) -> c_int {
     // It's probably NOT a good idea to catch Rust panics in finalizer
     // Lua 5.4 ignores it, other versions generates `LUA_ERRGCMM` without calling message handler
@@ -569,9 +683,12 @@ where
             } else {
                 "<not enough stack space for traceback>".to_string()
             };
+            // Interrupts are reported as is, however deep in nested callbacks they happen
             if let WrappedFailure::Error(ref mut err) = *wrapped_error {
-                let cause = Arc::new(err.clone());
-                *err = Error::CallbackError { traceback, cause };
+                if !matches!(err, Error::Interrupted) {
+                    let cause = Arc::new(err.clone());
+                    *err = Error::CallbackError { traceback, cause };
+                }
             }
 
             ffi::lua_error(state)
@@ -1,5 +1,10 @@
+use std::os::raw::c_void;
+use std::string::String as StdString;
+use std::sync::Arc;
 use std::{iter, mem, slice, str, vec};
 
+use rustc_hash::FxHashMap;
+
 #[cfg(feature = "serialize")]
 use {
     serde::ser::{self, Serialize, Serializer},
@@ -13,7 +18,7 @@ use crate::string::String;
 use crate::table::Table;
 use crate::thread::Thread;
 use crate::types::{Integer, LightUserData, Number};
-use crate::userdata::AnyUserData;
+use crate::userdata::{AnyUserData, TryCloneToUserDataExt};
 
 /// A dynamically typed Lua value. The `String`, `Table`, `Function`, `Thread`, and `UserData`
 /// variants contain handle types into the internal Lua state. It is a logic error to mix handle
@@ -85,6 +90,159 @@ impl<'lua> Value<'lua> {
             _ => Ok(self == other.as_ref()),
         }
     }
+
+    /// Deep-copies this value into another Lua state.
+    ///
+    /// Nil, booleans, numbers, light userdata and errors are copied as is, strings are recreated
+    /// and tables are copied recursively, preserving cycles and shared references: a table
+    /// reachable through several paths is copied once. Userdata are cloned into the other state,
+    /// which requires their [`TypeTable`] to record them as both `Clone` and `Send`; a userdata
+    /// reachable through several paths is cloned once as well.
+    ///
+    /// Metatables and user values are not transferred, and functions and threads cannot be
+    /// transferred at all.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error::TransferError`] holding the path to the first value which could not
+    /// be transferred, along with the reason why.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use hv_lua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// let ui = Lua::new();
+    /// let gameplay = Lua::new();
+    ///
+    /// let state: Table = gameplay.load("{ score = 10, players = { 'ann', 'bob' } }").eval()?;
+    /// ui.globals().set("state", gameplay.pack(state)?.transfer_to(&ui)?)?;
+    ///
+    /// ui.load("assert(state.score == 10 and state.players[2] == 'bob')").exec()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`TypeTable`]: hv_alchemy::TypeTable
+    /// [`Error::TransferError`]: crate::Error::TransferError
+    pub fn transfer_to<'other>(&self, lua: &'other Lua) -> Result<Value<'other>> {
+        Transfer {
+            lua,
+            copies: FxHashMap::default(),
+            path: StdString::from("value"),
+        }
+        .value(self)
+    }
+}
+
+// State of a `Value::transfer_to` call.
+struct Transfer<'other> {
+    lua: &'other Lua,
+    // Copies of the tables and userdata transferred so far, by address in the source state
+    copies: FxHashMap<*const c_void, Value<'other>>,
+    // Path to the value being transferred
+    path: StdString,
+}
+
+impl<'other> Transfer<'other> {
+    fn value(&mut self, value: &Value) -> Result<Value<'other>> {
+        Ok(match value {
+            Value::Nil => Value::Nil,
+            Value::Boolean(b) => Value::Boolean(*b),
+            Value::LightUserData(ud) => Value::LightUserData(*ud),
+            Value::Integer(i) => Value::Integer(*i),
+            Value::Number(n) => Value::Number(*n),
+            Value::String(s) => Value::String(
+                self.lua
+                    .create_string(s.as_bytes())
+                    .map_err(|err| self.fail(err))?,
+            ),
+            Value::Table(t) => self.table(t)?,
+            Value::UserData(ud) => self.user_data(ud)?,
+            Value::Error(err) => Value::Error(err.clone()),
+            Value::Function(_) | Value::Thread(_) => {
+                return Err(self.fail(Error::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: "Value",
+                    message: Some("value is bound to its Lua state".to_string()),
+                }))
+            }
+        })
+    }
+
+    fn table(&mut self, table: &Table) -> Result<Value<'other>> {
+        let ptr = table.0.to_pointer();
+        if let Some(copy) = self.copies.get(&ptr) {
+            return Ok(copy.clone());
+        }
+
+        let copy = self.lua.create_table().map_err(|err| self.fail(err))?;
+        self.copies.insert(ptr, Value::Table(copy.clone()));
+
+        for pair in table.clone().pairs::<Value, Value>() {
+            let (key, value) = pair.map_err(|err| self.fail(err))?;
+            let len = self.path.len();
+            push_key_path(&mut self.path, &key);
+            let key_len = self.path.len();
+
+            self.path.push_str(" (key)");
+            let key = self.value(&key)?;
+            self.path.truncate(key_len);
+
+            let value = self.value(&value)?;
+            copy.raw_set(key, value).map_err(|err| self.fail(err))?;
+            self.path.truncate(len);
+        }
+
+        Ok(Value::Table(copy))
+    }
+
+    fn user_data(&mut self, ud: &AnyUserData) -> Result<Value<'other>> {
+        let ptr = ud.0.to_pointer();
+        if let Some(copy) = self.copies.get(&ptr) {
+            return Ok(copy.clone());
+        }
+
+        let copy = ud
+            .dyn_borrow::<dyn TryCloneToUserDataExt>()
+            .and_then(|ud| ud.try_clone_to_user_data(self.lua))
+            .map_err(|err| self.fail(err))?;
+        self.copies.insert(ptr, Value::UserData(copy.clone()));
+
+        Ok(Value::UserData(copy))
+    }
+
+    fn fail(&self, cause: Error) -> Error {
+        match cause {
+            Error::TransferError { .. } => cause,
+            cause => Error::TransferError {
+                path: self.path.clone(),
+                cause: Arc::new(cause),
+            },
+        }
+    }
+}
+
+// Appends the Lua syntax for indexing a table with `key` to `path`.
+fn push_key_path(path: &mut StdString, key: &Value) {
+    let is_identifier = |s: &str| {
+        let mut chars = s.chars();
+        matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+
+    let segment = match key {
+        Value::String(s) => match s.to_str() {
+            Ok(s) if is_identifier(s) => format!(".{}", s),
+            Ok(s) => format!("[{:?}]", s),
+            Err(_) => "[<string>]".to_string(),
+        },
+        Value::Boolean(b) => format!("[{}]", b),
+        Value::Integer(i) => format!("[{}]", i),
+        Value::Number(n) => format!("[{}]", n),
+        key => format!("[<{}>]", key.type_name()),
+    };
+    path.push_str(&segment);
 }
 
 impl<'lua> PartialEq for Value<'lua> {
@@ -0,0 +1,1067 @@
+#![cfg(feature = "ecs")]
+
+use hv::alchemy::Type;
+use hv::lua::{
+    hv::{
+        ecs::{Resources, Schedule, DEFAULT_STAGE},
+        LuaUserDataTypeExt, LuaUserDataTypeTypeExt,
+    },
+    Lua, Result, UserData, UserDataFields, UserDataMethods,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Position(i64);
+
+impl UserData for Position {
+    fn on_metatable_init(table: Type<Self>) {
+        table.add_clone().add_copy().mark_component();
+    }
+
+    fn on_type_metatable_init(table: Type<Type<Self>>) {
+        table.mark_component_type();
+    }
+
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("value", |_, this| Ok(this.0));
+        fields.add_field_method_set("value", |_, this, value| {
+            this.0 = value;
+            Ok(())
+        });
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M) {
+        methods.add_function("new", |_, value: i64| Ok(Self(value)));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Velocity(i64);
+
+impl UserData for Velocity {
+    fn on_metatable_init(table: Type<Self>) {
+        table.add_clone().add_copy().mark_component();
+    }
+
+    fn on_type_metatable_init(table: Type<Type<Self>>) {
+        table.mark_component_type();
+    }
+
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("value", |_, this| Ok(this.0));
+        fields.add_field_method_set("value", |_, this, value| {
+            this.0 = value;
+            Ok(())
+        });
+    }
+
+    fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M) {
+        methods.add_function("new", |_, value: i64| Ok(Self(value)));
+    }
+}
+
+fn new_lua() -> Result<Lua> {
+    let lua = Lua::new();
+    let globals = lua.globals();
+    globals.set("hv", hv::lua::hv::types(&lua)?)?;
+    globals.set("Position", lua.create_userdata_type::<Position>()?)?;
+    globals.set("Velocity", lua.create_userdata_type::<Velocity>()?)?;
+    drop(globals);
+    Ok(lua)
+}
+
+#[test]
+fn test_world_remove() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local world = hv.ecs.World.new()
+        local e = world:spawn({ Position.new(1), Velocity.new(2) })
+
+        -- Duplicate types are rejected before anything is removed
+        assert(not pcall(world.remove, world, e, Position, Position))
+        assert(world:has(e, Position) and world:has(e, Velocity))
+
+        -- So are types the entity doesn't have
+        local only_position = world:spawn({ Position.new(3) })
+        assert(not pcall(world.remove, world, only_position, Position, Velocity))
+        assert(world:has(only_position, Position))
+
+        local p, v = world:remove(e, Position, Velocity)
+        assert(p.value == 1 and v.value == 2)
+        assert(not world:has(e, Position) and not world:has(e, Velocity))
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_typed_entities() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local world = hv.ecs.World.new()
+        hv.ecs.Entity.set_typed(true)
+
+        local e = world:spawn({ Position.new(1) })
+        local seen = {}
+        world:each(hv.ecs.Query.read(Position), function(entity)
+            -- The same entity is always the same userdata
+            assert(rawequal(entity, e))
+            seen[entity] = true
+        end)
+        assert(seen[e])
+
+        -- Entities which only differ by generation are told apart
+        world:despawn(e)
+        local f = world:spawn({ Position.new(2) })
+        assert(f.id == e.id and f.generation ~= e.generation)
+        assert(not rawequal(e, f) and e ~= f)
+        assert(not seen[f])
+
+        hv.ecs.Entity.set_typed(false)
+        local raw = world:spawn({ Position.new(3) })
+        assert(not pcall(function() return raw.id end))
+        assert(world:get(raw, Position).value == 3)
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_world_components() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local world = hv.ecs.World.new()
+        local e = world:spawn({ Position.new(1), Velocity.new(2) })
+        local only_position = world:spawn({ Position.new(3) })
+
+        local found = {}
+        for _, ty in ipairs(world:components(e)) do
+            found[ty] = (found[ty] or 0) + 1
+        end
+        assert(found[Position] == 1 and found[Velocity] == 1)
+        assert(#world:components(only_position) == 1)
+        assert(world:components(only_position)[1] == Position)
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_world_borrow() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local world = hv.ecs.World.new()
+        local e = world:spawn({ Position.new(1), Velocity.new(2) })
+        local only_position = world:spawn({ Position.new(3) })
+
+        local kept
+        assert(world:borrow(e, Position, function(position)
+            kept = position
+            return position.value
+        end) == 1)
+        -- The component is only lent for the duration of the call
+        assert(not pcall(function() return kept.value end))
+
+        world:borrow_mut(e, Velocity, function(velocity)
+            velocity.value = 5
+            -- Shared and mutable borrows of the same component conflict
+            assert(not pcall(world.borrow, world, e, Velocity, function() end))
+        end)
+        assert(world:get(e, Velocity).value == 5)
+
+        -- Shared borrows don't
+        world:borrow(e, Position, function(outer)
+            world:borrow(e, Position, function(inner)
+                assert(inner.value == outer.value)
+            end)
+        end)
+
+        assert(not pcall(world.borrow, world, only_position, Velocity, function() end))
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_query_item_borrows() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local Query = hv.ecs.Query
+        local world = hv.ecs.World.new()
+        local e = world:spawn({ Position.new(1), Velocity.new(2) })
+
+        local query = Query.new { Query.read(Position), Query.write(Velocity) }
+        local kept_item, kept_velocity
+        world:query(query, function(next)
+            for entity, item in next do
+                assert(entity == e)
+                assert(item:get(Position).value == 1)
+
+                local velocity = item:get_mut(Velocity)
+                velocity.value = velocity.value + 1
+                -- Borrowing again hands out the same loan
+                assert(item:get_mut(Velocity).value == 3)
+
+                -- Only components queried with write access can be borrowed mutably
+                assert(not pcall(item.get_mut, item, Position))
+
+                kept_item, kept_velocity = item, velocity
+            end
+        end)
+
+        -- Items and their borrowed components are revoked once the query is over
+        assert(not pcall(function() return kept_item:get(Position) end))
+        assert(not pcall(function() return kept_velocity.value end))
+
+        world:query_one(Query.write(Velocity), e, function(item)
+            assert(item:get_mut(Velocity).value == 3)
+        end)
+
+        -- Borrowing doesn't consume the component, and taking it afterwards revokes the borrow
+        world:query_one(Query.new { Query.read(Position), Query.write(Velocity) }, e, function(item)
+            local position = item:get(Position)
+            assert(item:get(Position).value == 1)
+            assert(position.value == 1)
+
+            local velocity = item:get_mut(Velocity)
+            local taken = item:take(Velocity)
+            assert(taken ~= nil and taken.value == 3)
+            assert(not pcall(function() return velocity.value end))
+            assert(item:get(Position).value == 1)
+        end)
+
+        assert(world:get(e, Velocity).value == 3)
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_world_each() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local Query = hv.ecs.Query
+        local world = hv.ecs.World.new()
+        for i = 1, 5 do
+            world:spawn({ Position.new(i) })
+        end
+
+        local seen, kept = 0, nil
+        world:each(Query.write(Position), function(entity, item)
+            seen = seen + 1
+            kept = item
+            item:get_mut(Position).value = 0
+            -- Returning `false` breaks out of the loop
+            return seen < 2
+        end)
+        assert(seen == 2)
+        assert(not pcall(function() return kept:get(Position) end))
+
+        -- The world isn't borrowed anymore after breaking out of the loop
+        world:spawn({ Position.new(6) })
+        assert(world:len() == 6)
+
+        local zeroes = 0
+        world:each(Query.read(Position), function(entity, item)
+            if item:get(Position).value == 0 then
+                zeroes = zeroes + 1
+            end
+        end)
+        assert(zeroes == 2)
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_world_iter() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local Query = hv.ecs.Query
+        local world = hv.ecs.World.new()
+        for i = 1, 3 do
+            world:spawn({ Position.new(i) })
+        end
+
+        local seen, kept = 0, nil
+        for entity, item in world:iter(Query.write(Position)) do
+            seen = seen + 1
+            kept = item
+            item:get_mut(Position).value = 0
+        end
+        assert(seen == 3)
+        -- Items only live for one iteration, and the world is released once the loop is over
+        assert(not pcall(function() return kept:get(Position) end))
+        world:spawn({ Position.new(4) })
+
+        -- Breaking out of a loop or raising an error in it releases the world on Lua 5.4, where
+        -- the iterator is a to-be-closed value. Elsewhere, that takes the iterator being closed or
+        -- garbage collected.
+        for entity in world:iter(Query.read(Position)) do
+            break
+        end
+        assert(not pcall(function()
+            for entity in world:iter(Query.read(Position)) do
+                error("boom")
+            end
+        end))
+        if _VERSION ~= "Lua 5.4" then
+            collectgarbage()
+            collectgarbage()
+        end
+        world:spawn({ Position.new(5) })
+
+        local iter = world:iter(Query.read(Position))
+        for entity in iter do
+            break
+        end
+        assert(not pcall(world.spawn, world, { Position.new(6) }))
+        iter:close()
+        world:spawn({ Position.new(6) })
+        assert(world:len() == 6)
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_query_filters() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local Query = hv.ecs.Query
+        local world = hv.ecs.World.new()
+        local both = world:spawn({ Position.new(1), Velocity.new(1) })
+        local position = world:spawn({ Position.new(2) })
+        local velocity = world:spawn({ Velocity.new(3) })
+
+        local function matching(query)
+            local found = {}
+            world:each(query, function(entity)
+                found[#found + 1] = entity
+            end)
+            return found
+        end
+
+        local found = matching(Query.new { Query.read(Position), Query.with(Velocity) })
+        assert(#found == 1 and found[1] == both)
+
+        found = matching(Query.new { Query.read(Position), Query.without(Velocity) })
+        assert(#found == 1 and found[1] == position)
+
+        local optional = 0
+        world:each(Query.new { Query.read(Velocity), Query.optional(Position) }, function(entity, item)
+            if item:get(Position) == nil then
+                assert(entity == velocity)
+                optional = optional + 1
+            end
+        end)
+        assert(optional == 1)
+
+        found = matching(Query.any { Query.without(Position), Query.without(Velocity) })
+        assert(#found == 2)
+
+        -- `any` only accepts filters
+        assert(not pcall(Query.any, { Query.read(Position) }))
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_command_buffer() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local Query = hv.ecs.Query
+        local world = hv.ecs.World.new()
+        local e = world:spawn({ Position.new(1), Velocity.new(1) })
+
+        local buffer = hv.ecs.CommandBuffer.new()
+        local spawned
+        world:each(Query.read(Position), function(entity)
+            -- The world is borrowed here, so changes have to be deferred
+            spawned = buffer:spawn(world, { Position.new(2) })
+            buffer:insert_one(spawned, Velocity.new(3))
+            buffer:remove(entity, Velocity)
+        end)
+        assert(buffer:len() == 3)
+        assert(world:len() == 1)
+
+        buffer:run_on(world)
+        assert(buffer:len() == 0)
+        assert(world:get(spawned, Position).value == 2)
+        assert(world:get(spawned, Velocity).value == 3)
+        assert(not world:has(e, Velocity))
+
+        buffer:despawn(spawned)
+        buffer:run_on(world)
+        assert(not world:contains(spawned))
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_column_batch_from_numbers() -> Result<()> {
+    use hv::lua::hv::ecs::FromNumbers;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Size(f64, f64);
+
+    impl FromNumbers for Size {
+        const LEN: usize = 2;
+
+        fn from_numbers(numbers: &[f64]) -> Self {
+            Self(numbers[0], numbers[1])
+        }
+    }
+
+    impl UserData for Size {
+        fn on_metatable_init(table: Type<Self>) {
+            table.mark_component();
+        }
+
+        fn on_type_metatable_init(table: Type<Type<Self>>) {
+            table.mark_component_type().mark_flat_component_type();
+        }
+
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("w", |_, this| Ok(this.0));
+            fields.add_field_method_get("h", |_, this| Ok(this.1));
+        }
+    }
+
+    let lua = new_lua()?;
+    lua.globals()
+        .set("Size", lua.create_userdata_type::<Size>()?)?;
+
+    lua.load(
+        r#"
+        local ColumnBatch = hv.ecs.ColumnBatch
+        local world = hv.ecs.World.new()
+
+        local batch = ColumnBatch.from_rows({ Size, Position }, {
+            { { 1, 2 }, Position.new(1) },
+            { { 3, 4 }, Position.new(2) },
+        })
+        world:spawn_column_batch(batch)
+        assert(world:len() == 2)
+
+        local batch = ColumnBatch.from_columns({ Size }, { { 5, 6, 7, 8 } })
+        world:spawn_column_batch(batch)
+        assert(world:len() == 4)
+
+        -- Each table of numbers must be exactly one component, even if the total adds up
+        assert(not pcall(ColumnBatch.from_rows, { Size }, { { { 1, 2, 3 } }, { { 4 } } }))
+        assert(not pcall(ColumnBatch.from_columns, { Size }, { { { 1 }, { 2, 3, 4 } } }))
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_lua_components() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local LuaComponent = hv.ecs.LuaComponent
+        local Health = LuaComponent.declare("Health", { current = 10, max = 10 })
+        local Tag = LuaComponent.declare("Tag")
+        assert(Health:name() == "Health")
+
+        local world = hv.ecs.World.new()
+        local e = world:spawn({ Health { current = 5 }, Tag(), Position.new(1) })
+
+        -- Every declaration is its own component type
+        assert(world:has(e, Health) and world:has(e, Tag))
+        local health = world:get(e, Health)
+        assert(health.current == 5 and health.max == 10)
+
+        -- Schemas reject unknown fields and fields of the wrong type
+        assert(not pcall(Health, { shield = 1 }))
+        assert(not pcall(Health, { current = "full" }))
+
+        world:each(hv.ecs.Query.write(Health), function(entity, item)
+            item:get_mut(Health).current = 7
+        end)
+        assert(world:get(e, Health).current == 7)
+
+        world:remove_one(e, Tag)
+        assert(not world:has(e, Tag) and world:has(e, Health))
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_lua_component_slots_are_reused() -> Result<()> {
+    // Slots are shared by the whole process, and freed once a declaration and all of its
+    // instances are gone.
+    for i in 0..hv::lua::hv::ecs::MAX_LUA_COMPONENT_TYPES * 2 {
+        let lua = new_lua()?;
+        lua.load(
+            r#"
+            local Marker = hv.ecs.LuaComponent.declare("Marker" .. ...)
+            local world = hv.ecs.World.new()
+            local e = world:spawn({ Marker() })
+            assert(world:has(e, Marker))
+            "#,
+        )
+        .call::<_, ()>(i)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Gravity(i64);
+
+impl UserData for Gravity {
+    fn on_metatable_init(table: Type<Self>) {
+        table.add_send().add_sync();
+    }
+
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("value", |_, this| Ok(this.0));
+        fields.add_field_method_set("value", |_, this, value| {
+            this.0 = value;
+            Ok(())
+        });
+    }
+}
+
+#[test]
+fn test_schedule() -> Result<()> {
+    let lua = new_lua()?;
+    lua.globals()
+        .set("Gravity", lua.create_userdata_type::<Gravity>()?)?;
+
+    let mut resources = Resources::new();
+    resources.insert_resource(Gravity(-1));
+    lua.globals().set("resources", resources)?;
+
+    let mut schedule = Schedule::new();
+    schedule.add_rust_system(DEFAULT_STAGE, "rust", |_, world, resources| {
+        assert!(resources.contains_resource::<Gravity>());
+        world.spawn((Position(100),));
+        Ok(())
+    })?;
+    lua.globals().set("schedule", schedule)?;
+
+    lua.load(
+        r#"
+        local Query = hv.ecs.Query
+        local world = hv.ecs.World.new()
+        local e = world:spawn({ Position.new(10), Velocity.new(0) })
+        local kept_gravity
+
+        schedule:add_system {
+            name = "physics",
+            resources = { Gravity },
+            queries = {
+                {
+                    query = Query.write(Velocity),
+                    each = function(entity, item, resources)
+                        local gravity = resources:with(Gravity, function(gravity)
+                            -- Resources can be shared, but not mutably borrowed while shared
+                            assert(resources:with(Gravity, function(again) return again.value end) == -1)
+                            assert(not pcall(resources.with_mut, resources, Gravity, function() end))
+                            kept_gravity = gravity
+                            return gravity.value
+                        end)
+                        item:get_mut(Velocity).value = item:get(Velocity).value + gravity
+                    end,
+                },
+                {
+                    query = Query.new { Query.write(Position), Query.read(Velocity) },
+                    each = function(entity, item)
+                        item:get_mut(Position).value = item:get(Position).value
+                            + item:get(Velocity).value
+                    end,
+                },
+            },
+        }
+
+        schedule:add_system {
+            name = "needs missing resource",
+            resources = { Position },
+            run = function() error("should not run") end,
+        }
+
+        local reports = schedule:run(world, resources)
+        -- Borrowed resources are revoked once the function borrowing them returns
+        assert(not pcall(function() return kept_gravity.value end))
+        assert(#reports == 3)
+        assert(reports[1].error == nil and reports[2].error == nil)
+        assert(reports[3].error:find("no resource of type", 1, true))
+
+        assert(world:get(e, Velocity).value == -1)
+        assert(world:get(e, Position).value == 9)
+        assert(world:len() == 2)
+
+        assert(resources:with_mut(Gravity, function(gravity)
+            gravity.value = -2
+            return gravity.value
+        end) == -2)
+        assert(resources:with(Gravity, function(gravity) return gravity.value end) == -2)
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_world_events() -> Result<()> {
+    let lua = new_lua()?;
+
+    lua.load(
+        r#"
+        local world = hv.ecs.World.new()
+        local other = hv.ecs.World.new()
+
+        local added, despawned = {}, {}
+        world:on_add(Velocity, function(entity) added[#added + 1] = entity end)
+        world:on_despawn(function(entity) error("subscriber failed") end)
+        world:on_despawn(function(entity) despawned[#despawned + 1] = entity end)
+
+        -- Every world has its own subscribers
+        other:spawn({ Velocity.new(1) })
+        other:flush_events()
+        assert(#added == 0)
+
+        local e = world:spawn({ Position.new(1), Velocity.new(1) })
+        local f = world:spawn({ Position.new(2) })
+        world:insert_one(f, Velocity.new(2))
+        world:flush_events()
+        assert(#added == 2 and added[1] == e and added[2] == f)
+
+        -- Changes made by command buffers are recorded too
+        local buffer = hv.ecs.CommandBuffer.new()
+        local g = buffer:spawn(world, { Velocity.new(3) })
+        buffer:despawn(e)
+        buffer:despawn(f)
+        buffer:run_on(world)
+
+        -- A failing subscriber doesn't keep the others from being called
+        local ok, err = pcall(world.flush_events, world)
+        assert(not ok and tostring(err):find("subscriber failed", 1, true))
+        assert(#added == 3 and added[3] == g)
+        assert(#despawned == 2 and despawned[1] == e and despawned[2] == f)
+
+        -- Nothing is delivered twice
+        world:flush_events()
+        assert(#added == 3 and #despawned == 2)
+
+        world:unsubscribe_all()
+        world:spawn({ Velocity.new(4) })
+        world:flush_events()
+        assert(#added == 3)
+        "#,
+    )
+    .exec()
+}
+
+#[cfg(feature = "serialize")]
+#[test]
+fn test_scene_round_trip() -> Result<()> {
+    use hv::lua::hv::ecs::{EntityMap, MapEntities, SceneRegistry};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    struct Health(i64);
+
+    impl UserData for Health {
+        fn on_metatable_init(table: Type<Self>) {
+            table.add::<dyn erased_serde::Serialize>().mark_component();
+        }
+
+        fn on_type_metatable_init(table: Type<Type<Self>>) {
+            table.mark_component_type();
+        }
+
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("value", |_, this| Ok(this.0));
+        }
+
+        fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M) {
+            methods.add_function("new", |_, value: i64| Ok(Self(value)));
+        }
+    }
+
+    // Refers to another entity by its ID.
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    struct Parent(u64);
+
+    impl MapEntities for Parent {
+        fn map_entities(&mut self, map: &EntityMap) {
+            let entity = hv_ecs::Entity::from_bits(self.0).unwrap();
+            self.0 = map.map(entity).to_bits().get();
+        }
+    }
+
+    impl UserData for Parent {
+        fn on_metatable_init(table: Type<Self>) {
+            table.add::<dyn erased_serde::Serialize>().mark_component();
+        }
+
+        fn on_type_metatable_init(table: Type<Type<Self>>) {
+            table.mark_component_type();
+        }
+
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("entity", |_, this| Ok(hv_ecs::Entity::from_bits(this.0)));
+        }
+
+        fn add_type_methods<'lua, M: UserDataMethods<'lua, Type<Self>>>(methods: &mut M) {
+            methods.add_function("new", |_, entity: hv_ecs::Entity| {
+                Ok(Self(entity.to_bits().get()))
+            });
+        }
+    }
+
+    let lua = new_lua()?;
+    let mut registry = SceneRegistry::new();
+    registry
+        .register::<Health>("Health")
+        .register_mapped::<Parent>("Parent")
+        .ignore::<Velocity>();
+
+    let globals = lua.globals();
+    globals.set("registry", registry)?;
+    globals.set("Health", lua.create_userdata_type::<Health>()?)?;
+    globals.set("Parent", lua.create_userdata_type::<Parent>()?)?;
+    drop(globals);
+
+    lua.load(
+        r#"
+        local world = hv.ecs.World.new()
+        local a = world:spawn({ Health.new(10) })
+        local b = world:spawn({ Health.new(5), Parent.new(a), Velocity.new(1) })
+        local scene = registry:save(world)
+
+        -- Registries created from Lua get their registrations from other registries
+        local empty = hv.ecs.SceneRegistry.new()
+        assert(#empty:names() == 0 and not pcall(empty.save, empty, world))
+        local copy = hv.ecs.SceneRegistry.new()
+        copy:extend(registry)
+        assert(#copy:names() == 2)
+        assert(#copy:save(world) == #scene)
+
+        -- Components which are neither registered nor ignored can't be saved
+        world:spawn({ Position.new(1) })
+        assert(not pcall(registry.save, registry, world))
+
+        local loaded = hv.ecs.World.new()
+        loaded:spawn({ Health.new(0) })
+        local map = registry:load(loaded, scene)
+        local loaded_a, loaded_b = map[a], map[b]
+        assert(loaded:len() == 3)
+        assert(loaded:get(loaded_a, Health).value == 10)
+        assert(loaded:get(loaded_b, Health).value == 5)
+        assert(not loaded:has(loaded_b, Velocity))
+
+        -- Entity references are remapped to the loaded entities
+        assert(loaded:get(loaded_b, Parent).entity == loaded_a)
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_shared_world() -> Result<()> {
+    use std::sync::Arc;
+
+    use hv::lua::hv::ecs::SharedWorld;
+    use hv_cell::AtomicRefCell;
+
+    let lua = new_lua()?;
+    let world: SharedWorld = Arc::new(AtomicRefCell::new(hv_ecs::World::new()));
+    let rust_entity = world.borrow_mut().spawn((Position(1),));
+
+    let globals = lua.globals();
+    globals.set("shared", world.clone())?;
+    globals.set("alias", world.clone())?;
+    globals.set("rust_entity", rust_entity)?;
+    let borrowed_from_rust = {
+        let world = world.clone();
+        lua.create_function(move |_, ()| Ok(world.try_borrow_mut().is_err()))?
+    };
+    globals.set("borrowed_from_rust", borrowed_from_rust)?;
+    drop(globals);
+
+    lua.load(
+        r#"
+        local Query = hv.ecs.Query
+
+        -- Every handle sees the same world, and changes made from Rust
+        assert(shared:len() == 1 and alias:get(rust_entity, Position).value == 1)
+        local e = shared:spawn({ Position.new(2), Velocity.new(1) })
+        assert(alias:len() == 2 and alias:has(e, Velocity))
+
+        -- The world stays borrowed for as long as a call from Lua runs, and no longer
+        assert(not borrowed_from_rust())
+        shared:each(Query.read(Position), function(entity, item)
+            assert(borrowed_from_rust())
+            -- Conflicting borrows through another handle fail rather than block
+            assert(not pcall(alias.spawn, alias, { Position.new(3) }))
+        end)
+        assert(not borrowed_from_rust())
+
+        -- Subscribers are shared by all handles of a world
+        local added = 0
+        shared:on_add(Velocity, function() added = added + 1 end)
+        alias:insert_one(rust_entity, Velocity.new(2))
+        alias:flush_events()
+        assert(added == 1)
+
+        local buffer = hv.ecs.CommandBuffer.new()
+        buffer:despawn(e)
+        buffer:run_on(alias)
+        assert(not shared:contains(e))
+        "#,
+    )
+    .exec()?;
+
+    // Failed borrows from Lua leave the world usable from Rust, and the handles still in Lua keep
+    // it alive.
+    assert_eq!(world.borrow().len(), 1);
+    let world_ref = world.borrow();
+    assert!(lua
+        .load("shared:spawn({ Position.new(4) })")
+        .exec()
+        .is_err());
+    drop(world_ref);
+    drop(world);
+    lua.load("assert(shared:len() == 1 and alias:get(rust_entity, Velocity).value == 2)")
+        .exec()
+}
+
+#[test]
+fn test_extensions_in_a_shared_world() -> Result<()> {
+    use std::sync::Arc;
+
+    use hv::lua::hv::ecs::SharedWorld;
+    use hv_cell::AtomicRefCell;
+
+    let lua = new_lua()?;
+    lua.extend_userdata_type::<Position, _>(|methods, fields| {
+        methods.add_method("doubled", |_, this, ()| Ok(this.0 * 2));
+        fields.add_field_method_get("negated", |_, this| Ok(-this.0));
+    })?;
+
+    let world: SharedWorld = Arc::new(AtomicRefCell::new(hv_ecs::World::new()));
+    let entity = world.borrow_mut().spawn((Position(3),));
+    lua.globals().set("shared", world)?;
+    lua.globals().set("entity", entity)?;
+
+    // Components reached through a shared world are proxies, which see the extensions of their
+    // component types
+    lua.load(
+        r#"
+        shared:borrow(entity, Position, function(position)
+            assert(position:doubled() == 6 and position.negated == -3)
+        end)
+        shared:borrow_mut(entity, Position, function(position)
+            position.value = 4
+            assert(position:doubled() == 8)
+        end)
+        shared:each(hv.ecs.Query.read(Position), function(_, item)
+            assert(item:get(Position).negated == -4)
+        end)
+        "#,
+    )
+    .exec()
+}
+
+#[test]
+fn test_scripts_in_a_shared_world() -> Result<()> {
+    use std::sync::Arc;
+
+    use hv::lua::hv::ecs::{run_scripts, Script, SharedWorld};
+    use hv_cell::AtomicRefCell;
+
+    let world: SharedWorld = Arc::new(AtomicRefCell::new(hv_ecs::World::new()));
+    let first = new_lua()?;
+    let second = new_lua()?;
+    for lua in [&first, &second] {
+        lua.globals().set("shared", world.clone())?;
+        lua.load(
+            r#"
+            ticks = 0
+            shared:spawn({ hv.ecs.Script.new(function()
+                while true do
+                    ticks = ticks + 1
+                    coroutine.yield()
+                end
+            end) })
+            "#,
+        )
+        .exec()?;
+    }
+
+    // Each state only runs the scripts it created
+    let first_world = first.globals().get("shared")?;
+    assert!(run_scripts(&first, &first_world, 1.0)?.is_empty());
+    assert_eq!(first.globals().get::<_, i64>("ticks")?, 1);
+    assert_eq!(second.globals().get::<_, i64>("ticks")?, 0);
+
+    let scripts = world
+        .borrow()
+        .query::<&Script>()
+        .iter()
+        .map(|(_, script)| script.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(scripts.len(), 2);
+    for script in scripts {
+        let (owner, other) = if script.belongs_to(&first) {
+            (&first, &second)
+        } else {
+            (&second, &first)
+        };
+        assert!(!script.is_finished(owner)?);
+        assert!(script.is_finished(other).is_err());
+    }
+
+    Ok(())
+}
+
+#[cfg(any(feature = "lua54", feature = "lua53"))]
+#[test]
+fn test_preempted_scripts_keep_running() -> Result<()> {
+    use hv::lua::{
+        hv::ecs::{run_scripts, Script},
+        Thread,
+    };
+
+    let lua = new_lua()?;
+    let thread: Thread = lua
+        .load(
+            r#"
+            coroutine.create(function()
+                local n = 0
+                for i = 1, 100000 do
+                    n = n + i
+                end
+                done = n
+            end)
+            "#,
+        )
+        .eval()?;
+    thread.set_preemption(Some(1000))?;
+
+    let world = lua.create_userdata(hv_ecs::World::new())?;
+    let script = Script::new(&lua, hv::lua::Value::Thread(thread))?;
+    let entity = world
+        .borrow_mut::<hv_ecs::World>()?
+        .spawn((script.clone(),));
+
+    let mut ticks = 0;
+    while !script.is_finished(&lua)? {
+        assert!(run_scripts(&lua, &world, 1.0)?.is_empty());
+        // Preempted scripts stay on their entity
+        if !script.is_finished(&lua)? {
+            assert!(world
+                .borrow::<hv_ecs::World>()?
+                .get::<Script>(entity)
+                .is_ok());
+        }
+        ticks += 1;
+    }
+
+    // Spread over several ticks, and finished only once the coroutine returned
+    assert!(ticks > 1);
+    assert_eq!(lua.globals().get::<_, i64>("done")?, 5000050000);
+    assert!(world
+        .borrow::<hv_ecs::World>()?
+        .get::<Script>(entity)
+        .is_err());
+    Ok(())
+}
+
+#[cfg(feature = "parry3d")]
+#[test]
+fn test_collision_system() -> Result<()> {
+    use hv::lua::external::parry3d::{CollisionFilter, CollisionSystem};
+    use hv_math::{Isometry3, Vector3};
+    use parry3d::shape::{Ball, Cuboid};
+
+    let at = |x: f32| Isometry3::translation(x, 0., 0.);
+    let mut world = hv_ecs::World::new();
+    let ball = world.spawn((at(0.), Ball::new(1.)));
+    let cuboid = world.spawn((at(1.4), Cuboid::new(Vector3::new(0.5, 0.5, 0.5))));
+    let sensor = world.spawn((
+        at(2.2),
+        Ball::new(0.5),
+        CollisionFilter {
+            sensor: true,
+            ..CollisionFilter::default()
+        },
+    ));
+    // Overlaps `ball`, but collides with nothing.
+    world.spawn((
+        at(-1.),
+        Ball::new(1.),
+        CollisionFilter {
+            memberships: 1,
+            filter: 0,
+            sensor: false,
+        },
+    ));
+    // Too far from everything else.
+    world.spawn((at(10.), Ball::new(1.)));
+    // Has no shape.
+    world.spawn((at(0.),));
+
+    let key = |a: hv_ecs::Entity, b: hv_ecs::Entity| {
+        let (a, b) = (a.to_bits().get(), b.to_bits().get());
+        (a.min(b), a.max(b))
+    };
+    let mut system = CollisionSystem::new(0.);
+    let mut pairs = system
+        .detect(&world)
+        .iter()
+        .map(|pair| (key(pair.entity1, pair.entity2), pair.sensor))
+        .collect::<Vec<_>>();
+    pairs.sort_unstable();
+    let mut expected = vec![(key(ball, cuboid), false), (key(cuboid, sensor), true)];
+    expected.sort_unstable();
+    assert_eq!(pairs, expected);
+
+    let lua = new_lua()?;
+    let globals = lua.globals();
+    globals.set("world", lua.create_userdata(world)?)?;
+    globals.set("system", system)?;
+    globals.set("sensor", sensor)?;
+    drop(globals);
+
+    lua.load(
+        r#"
+        local contacts, sensors = 0, 0
+        system:detect(world, function(entity1, entity2, contact, is_sensor)
+            contacts = contacts + 1
+            if is_sensor then
+                sensors = sensors + 1
+                assert(entity1 == sensor or entity2 == sensor)
+            end
+        end)
+        assert(contacts == 2 and sensors == 1)
+        "#,
+    )
+    .exec()
+}
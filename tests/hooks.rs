@@ -231,3 +231,89 @@ fn test_hook_swap_within_hook() -> Result<()> {
         Ok(())
     })
 }
+
+#[test]
+fn test_interrupt_handle() -> Result<()> {
+    let lua = Lua::new();
+
+    #[cfg(feature = "luajit")]
+    // For LuaJIT disable JIT, as compiled code does not trigger hooks
+    lua.load("jit.off()").exec()?;
+
+    let handle = lua.interrupt_handle()?;
+    assert!(!handle.is_interrupted());
+
+    let lines = Arc::new(Mutex::new(0));
+    let hook_lines = lines.clone();
+    lua.set_hook(HookTriggers::every_line(), move |_lua, debug| {
+        assert_eq!(debug.event(), DebugEvent::Line);
+        *hook_lines.lock().unwrap() += 1;
+        Ok(())
+    })?;
+    lua.load("local x = 1\nlocal y = 2").exec()?;
+    assert!(*lines.lock().unwrap() > 0);
+    lua.remove_hook();
+
+    let interrupter = {
+        let handle = handle.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            handle.interrupt();
+        })
+    };
+    let err = lua
+        .load(
+            r#"
+            while true do
+                pcall(function() for i = 1, 100 do end end)
+            end
+            "#,
+        )
+        .exec()
+        .expect_err("script was not interrupted");
+    interrupter.join().unwrap();
+
+    match err {
+        Error::Interrupted => {}
+        err => panic!("wrong error kind caught: {}", err),
+    };
+
+    // Interrupts are sticky until reset
+    assert!(handle.is_interrupted());
+    assert!(lua.load("for i = 1, 10000 do end").exec().is_err());
+
+    // Interrupts inside nested callbacks are still reported as is
+    let nested = lua.create_function(|lua, ()| lua.load("for i = 1, 10000 do end").exec())?;
+    lua.globals().set("nested", nested)?;
+    match lua.load("nested()").exec() {
+        Err(Error::Interrupted) => {}
+        r => panic!("wrong result of an interrupted nested call: {:?}", r),
+    };
+
+    handle.reset();
+    lua.load("for i = 1, 10000 do end").exec()?;
+
+    // A hook with a large instruction count doesn't delay interrupt checks, and still runs at its
+    // own rate
+    let counts = Arc::new(Mutex::new(0));
+    let hook_counts = counts.clone();
+    lua.set_hook(
+        HookTriggers::every_nth_instruction(1_000_000),
+        move |_lua, _debug| {
+            *hook_counts.lock().unwrap() += 1;
+            Ok(())
+        },
+    )?;
+    lua.load("for i = 1, 100000 do end").exec()?;
+    assert_eq!(*counts.lock().unwrap(), 0);
+
+    handle.interrupt();
+    match lua.load("for i = 1, 10000 do end").exec() {
+        Err(Error::Interrupted) => {}
+        r => panic!("interrupt was not delivered before the hook count: {:?}", r),
+    };
+    handle.reset();
+    lua.remove_hook();
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+#![cfg(feature = "send")]
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use hv::alchemy::Type;
+use hv::lua::{Error, Lua, LuaPool, Result, SendValue, Table, UserData, UserDataMethods, Value};
+
+#[test]
+fn test_pool_call() -> Result<()> {
+    let inits = Arc::new(AtomicUsize::new(0));
+    let inits2 = inits.clone();
+    let pool = LuaPool::new(4, move |lua| {
+        inits2.fetch_add(1, Ordering::SeqCst);
+        lua.load("function square(x) return x * x end").exec()
+    })?;
+    assert_eq!(inits.load(Ordering::SeqCst), 4);
+    assert_eq!(pool.size(), 4);
+
+    let jobs = (1..=16)
+        .map(|i| pool.call::<_, i64>("square", i))
+        .collect::<Vec<_>>();
+    let results = jobs
+        .into_iter()
+        .map(|job| job.wait())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(results, (1..=16).map(|i| i * i).collect::<Vec<_>>());
+    assert_eq!(pool.pending(), 0);
+
+    match pool.call::<_, ()>("missing", ()).wait() {
+        Err(Error::FromLuaConversionError { .. }) => {}
+        r => panic!("expected FromLuaConversionError, got {:?}", r),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_pool_init_error() {
+    match LuaPool::new(2, |lua| lua.load("error('init failed')").exec()) {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("init failed")),
+        r => panic!("expected RuntimeError, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_pool_send_values() -> Result<()> {
+    #[derive(Clone, Debug, PartialEq)]
+    struct Point(i64, i64);
+
+    impl UserData for Point {
+        fn on_metatable_init(table: Type<Self>) {
+            table.add_clone().add_send();
+        }
+
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("sum", |_, this, ()| Ok(this.0 + this.1));
+        }
+    }
+
+    let pool = LuaPool::new(2, |lua| {
+        lua.load(
+            r#"
+            function summarize(data)
+                local total = 0
+                for _, p in ipairs(data.points) do
+                    total = total + p:sum()
+                end
+                return { name = data.name, total = total }
+            end
+            "#,
+        )
+        .exec()
+    })?;
+
+    let lua = Lua::new();
+    let data: Table = lua.load("{ name = 'points', points = {} }").eval()?;
+    let points: Table = data.get("points")?;
+    points.raw_set(1, Point(1, 2))?;
+    points.raw_set(2, Point(3, 4))?;
+
+    let data: SendValue = lua.unpack(Value::Table(data))?;
+    let result = pool.call::<_, SendValue>("summarize", data).wait()?;
+
+    let result: Table = lua.unpack(lua.pack(result)?)?;
+    assert_eq!(result.get::<_, String>("name")?, "points");
+    assert_eq!(result.get::<_, i64>("total")?, 10);
+
+    // Recursive tables and functions cannot be sent
+    let recursive: Table = lua.load("local t = {} t.t = t return t").eval()?;
+    assert!(lua.unpack::<SendValue>(Value::Table(recursive)).is_err());
+    assert!(lua.load("print").eval::<SendValue>().is_err());
+
+    Ok(())
+}
@@ -176,3 +176,126 @@ fn test_coroutine_panic() {
         Err(p) => assert!(*p.downcast::<&str>().unwrap() == "test_panic"),
     }
 }
+
+#[test]
+#[cfg(any(feature = "lua54", feature = "lua53"))]
+fn test_thread_preemption() -> Result<()> {
+    let lua = Lua::new();
+
+    let thread: Thread = lua
+        .load(
+            r#"
+            coroutine.create(function(n)
+                local sum = 0
+                for i = 1,n do
+                    sum = sum + i
+                end
+                local inner = coroutine.wrap(function()
+                    for i = 1,n do
+                        coroutine.yield(i)
+                    end
+                end)
+                return sum, inner()
+            end)
+            "#,
+        )
+        .eval()?;
+
+    thread.set_preemption(Some(100))?;
+
+    let mut slices = 1;
+    let mut result: (Option<i64>, Option<i64>) = thread.resume(10000)?;
+    while thread.status() == ThreadStatus::Preempted {
+        assert_eq!(result, (None, None));
+        slices += 1;
+        result = thread.resume(())?;
+    }
+
+    assert!(slices > 1);
+    assert_eq!(result, (Some(50005000), Some(1)));
+    assert_eq!(thread.status(), ThreadStatus::Unresumable);
+
+    let thread: Thread = lua
+        .load("coroutine.create(function() for i = 1,1000 do end return 1 end)")
+        .eval()?;
+    thread.set_preemption(Some(10))?;
+    thread.set_preemption(None)?;
+    assert_eq!(thread.resume::<_, i64>(())?, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_thread_context() -> Result<()> {
+    #[derive(Debug, PartialEq)]
+    struct Owner(u32);
+
+    let lua = Lua::new();
+
+    let owner = lua.create_function(|lua, ()| Ok(lua.current_context::<Owner>().map(|o| o.0)))?;
+    lua.globals().set("owner", owner)?;
+    let (create, wrap): (Function, Function) =
+        lua.load("coroutine.create, coroutine.wrap").eval()?;
+
+    let func: Function = lua
+        .load(
+            r#"
+            function()
+                local created = coroutine.create(function() return owner() end)
+                local wrapped = coroutine.wrap(function()
+                    coroutine.yield(owner())
+                    return owner()
+                end)
+                local first = wrapped()
+                coroutine.yield(owner(), select(2, coroutine.resume(created)), first)
+                return wrapped()
+            end
+            "#,
+        )
+        .eval()?;
+
+    let thread = lua.create_thread(func)?;
+    assert_eq!(thread.context::<Owner>(), None);
+    thread.set_context(Owner(1))?;
+    assert_eq!(thread.context::<Owner>().as_deref(), Some(&Owner(1)));
+
+    let owners: (u32, u32, u32) = thread.resume(())?;
+    assert_eq!(owners, (1, 1, 1));
+
+    // Coroutines created from Lua see the current context of the thread they run inside
+    thread.set_context(Owner(2))?;
+    assert_eq!(thread.resume::<_, u32>(())?, 2);
+
+    // ... including coroutines created before the context was set, and without replacing the
+    // `coroutine` library functions
+    let early: Function = lua
+        .load("function() return coroutine.wrap(function() return owner() end) end")
+        .eval()?;
+    let early_thread = lua.create_thread(early)?;
+    let early_coroutine: Function = early_thread.resume(())?;
+    let runner =
+        lua.create_thread(lua.create_function(move |_, f: Function| f.call::<_, u32>(()))?)?;
+    runner.set_context(Owner(4))?;
+    assert_eq!(runner.resume::<_, u32>(early_coroutine)?, 4);
+    assert!(lua.load("coroutine.create").eval::<Function>()? == create);
+    assert!(lua.load("coroutine.wrap").eval::<Function>()? == wrap);
+
+    // Threads created from Rust inherit the context of the current thread
+    let setter = lua.create_function(|lua, ()| {
+        let child = lua.create_thread(lua.create_function(|_, ()| Ok(()))?)?;
+        Ok(child.context::<Owner>().map(|o| o.0))
+    })?;
+    let parent = lua.create_thread(setter)?;
+    parent.set_context(Owner(3))?;
+    assert_eq!(parent.resume::<_, Option<u32>>(())?, Some(3));
+
+    assert_eq!(
+        thread.remove_context::<Owner>()?.as_deref(),
+        Some(&Owner(2))
+    );
+    assert_eq!(thread.context::<Owner>(), None);
+    assert_eq!(lua.current_context::<Owner>(), None);
+    assert!(lua.load("owner()").eval::<Option<u32>>()?.is_none());
+
+    Ok(())
+}
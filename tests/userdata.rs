@@ -7,8 +7,8 @@ use std::{cell::RefCell, rc::Rc};
 use std::sync::atomic::{AtomicI64, Ordering};
 
 use hv::lua::{
-    AnyUserData, Error, ExternalError, Function, Lua, MetaMethod, Nil, Result, String, UserData,
-    UserDataFields, UserDataMethods, Value,
+    AnyUserData, Error, ExternalError, Function, Lua, MetaMethod, Nil, Result, String,
+    TryCloneToUserDataExt, UserData, UserDataFields, UserDataMethods, Value,
 };
 
 use hv::alchemy::Type;
@@ -627,3 +627,359 @@ fn test_userdata_wrapped() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_into_user_data_default() -> Result<()> {
+    #[derive(Clone, Copy)]
+    struct Counter(i64);
+    impl UserData for Counter {}
+
+    // Not a `UserData` type itself, so it doesn't get the blanket implementation
+    struct CounterSource(i64);
+
+    impl TryCloneToUserDataExt for CounterSource {
+        fn try_clone_to_user_data<'lua>(&self, lua: &'lua Lua) -> Result<AnyUserData<'lua>> {
+            lua.create_userdata(Counter(self.0))
+        }
+    }
+
+    let lua = Lua::new();
+    let ud = Box::new(CounterSource(7)).into_user_data(&lua)?;
+    assert_eq!(ud.borrow::<Counter>()?.0, 7);
+
+    Ok(())
+}
+
+#[test]
+fn test_register_type() -> Result<()> {
+    use std::any::TypeId;
+
+    struct Thing;
+    impl UserData for Thing {}
+
+    struct Other;
+    impl UserData for Other {}
+
+    let lua = Lua::new();
+    let thing = lua.register_type::<Thing>("game.Thing")?;
+
+    // Registering again under the same path hands out the same type object
+    assert!(lua.register_type::<Thing>("game.Thing")? == thing);
+    // Registering under another path, or another type under the same path, is an error
+    assert!(lua.register_type::<Thing>("game.Renamed").is_err());
+    assert!(lua.register_type::<Other>("game.Thing").is_err());
+
+    assert!(lua.registered_type("game.Thing")?.unwrap() == thing);
+    assert!(lua.registered_type("game.Other")?.is_none());
+    assert!(lua.registered_type_by_id(TypeId::of::<Thing>())?.unwrap() == thing);
+    assert!(lua.registered_type_by_id(TypeId::of::<Other>())?.is_none());
+    assert!(
+        lua.registered_type_by_table(hv_alchemy::TypeTable::of::<Thing>())?
+            .unwrap()
+            == thing
+    );
+    assert_eq!(
+        lua.registered_type_path(TypeId::of::<Thing>()).as_deref(),
+        Some("game.Thing")
+    );
+
+    lua.register_type::<Other>("game.Other")?;
+    assert_eq!(
+        lua.registered_type_paths(),
+        vec!["game.Other", "game.Thing"]
+    );
+
+    // Registering a type whose type object was already created registers that object, and the
+    // registry works while the app data is borrowed
+    struct Early;
+    impl UserData for Early {}
+
+    let early = lua.create_userdata_type::<Early>()?;
+    lua.set_app_data(0u32);
+    let app_data = lua.app_data_mut::<u32>().unwrap();
+    assert!(lua.register_type::<Early>("game.Early")? == early);
+    assert!(lua.registered_type("game.Early")?.unwrap() == early);
+    lua.create_userdata_type::<Other>()?;
+    drop(app_data);
+
+    Ok(())
+}
+
+#[cfg(feature = "ecs")]
+#[test]
+fn test_module_tables_register_types() -> Result<()> {
+    use hv::lua::hv::ecs::Query;
+
+    let lua = Lua::new();
+    lua.globals().set("hv", hv::lua::hv::types(&lua)?)?;
+
+    // Type objects are registered under their path in the module tables when first loaded
+    assert!(lua.registered_type("hv.ecs.Query")?.is_none());
+    let query: AnyUserData = lua.load("hv.ecs.Query").eval()?;
+    assert!(lua.registered_type("hv.ecs.Query")?.unwrap() == query);
+    assert_eq!(
+        lua.registered_type_path(std::any::TypeId::of::<Query>())
+            .as_deref(),
+        Some("hv.ecs.Query")
+    );
+
+    // Members of type families are registered under their family
+    lua.load("local _ = hv.math.Vector3.f32").exec()?;
+    assert!(lua.registered_type("hv.math.Vector3.f32")?.is_some());
+
+    // Module tables built again share the type objects already registered
+    let again = hv::lua::hv::types(&lua)?;
+    lua.globals().set("again", again)?;
+    assert!(lua
+        .load("return rawequal(again.ecs.Query, hv.ecs.Query)")
+        .eval::<bool>()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_lazy_module_tables() -> Result<()> {
+    let lua = Lua::new();
+    lua.globals().set("hv", hv::lua::hv::types(&lua)?)?;
+
+    lua.load(
+        r#"
+        local math = hv.math
+
+        -- Entries are built on first access and stored in the table
+        assert(rawget(math, "Vector2") == nil)
+        local vector2 = math.Vector2
+        assert(vector2 ~= nil)
+        assert(rawequal(rawget(math, "Vector2"), vector2))
+
+        -- Keys without a loader are still missing
+        assert(math.NotAType == nil)
+        assert(math["\xff"] == nil)
+
+        -- After forcing, iteration sees every entry whether or not `__pairs` is honored
+        hv.force_all(math)
+        local names = {}
+        for name in pairs(math) do
+            names[name] = true
+        end
+        for _, name in ipairs({ "Vector2", "Vector3", "Isometry2", "Isometry3", "Velocity2" }) do
+            assert(names[name], name)
+        end
+
+        -- Forcing again keeps the entries already built
+        hv.force_all(math)
+        assert(rawequal(math.Vector2, vector2))
+
+        -- Tables which are not lazy are left alone
+        hv.force_all({})
+
+        -- `__pairs` doesn't need the global `next`, which a sandbox may not have
+        local lazy_pairs, global_next = getmetatable(math).__pairs, next
+        next = nil
+        local n = 0
+        for name, value in lazy_pairs(math) do
+            assert(rawequal(math[name], value))
+            n = n + 1
+        end
+        next = global_next
+        assert(n >= 5)
+    "#,
+    )
+    .exec()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_type_families() -> Result<()> {
+    use hv::lua::hv::{type_family_of, TypeFamily};
+    use hv_math::{Vector2, Velocity2};
+
+    let lua = Lua::new();
+    lua.globals().set("hv", hv::lua::hv::types(&lua)?)?;
+
+    // Velocity2 is declared once the family is loaded, and can be converted between members
+    lua.load(
+        r#"
+        local Vector2, Velocity2 = hv.math.Vector2, hv.math.Velocity2
+
+        assert(rawequal(Velocity2("f64"), Velocity2.f64))
+        assert(Velocity2.f32.family == "Velocity2")
+        assert(Velocity2.f32.parameter == "f32")
+
+        local v = Velocity2.f32.new(Vector2.f32.new(1, 2), 3)
+        w = Velocity2(v, "f64")
+        assert(rawequal(Velocity2(v, "f32"), v))
+
+        -- `convert` is not a member, so it doesn't show up when iterating the family
+        hv.force_all(Velocity2)
+        local n = 0
+        for parameter in pairs(Velocity2) do
+            assert(parameter == "f32" or parameter == "f64", parameter)
+            n = n + 1
+        end
+        assert(n == 2)
+        assert(Velocity2.convert == nil)
+
+        local ok = pcall(Velocity2, v, "f16")
+        assert(not ok)
+    "#,
+    )
+    .exec()?;
+
+    let w = lua.globals().get::<_, AnyUserData>("w")?;
+    let w = *w.borrow::<Velocity2<f64>>()?;
+    assert_eq!(w.linear, Vector2::new(1., 2.));
+    assert_eq!(w.angular, 3.);
+
+    // Membership is recorded per Lua state, once the family is built
+    #[derive(Clone, Copy)]
+    struct Meters(f32);
+    impl UserData for Meters {}
+
+    let family = TypeFamily::new("Length").member::<Meters>("m");
+    assert_eq!(type_family_of(&lua, std::any::TypeId::of::<Meters>()), None);
+    lua.globals()
+        .set("Length", family.into_table(&lua, "Length")?)?;
+    assert_eq!(
+        type_family_of(&lua, std::any::TypeId::of::<Meters>()),
+        Some(("Length", "m"))
+    );
+    assert_eq!(
+        lua.load("Length.m.family").eval::<std::string::String>()?,
+        "Length"
+    );
+
+    // A type can't join a second family, and keeps its first membership
+    let other = TypeFamily::new("Distance").member::<Meters>("m");
+    assert!(other.into_table(&lua, "Distance").is_err());
+    assert_eq!(
+        type_family_of(&lua, std::any::TypeId::of::<Meters>()),
+        Some(("Length", "m"))
+    );
+
+    // Other Lua states are free to put it in another family
+    let other_lua = Lua::new();
+    TypeFamily::new("Distance")
+        .member::<Meters>("m")
+        .into_table(&other_lua, "Distance")?;
+    assert_eq!(
+        type_family_of(&other_lua, std::any::TypeId::of::<Meters>()),
+        Some(("Distance", "m"))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_extend_userdata_type() -> Result<()> {
+    use hv_cell::AtomicRefCell;
+
+    #[derive(Clone, Copy)]
+    struct Counter(i64);
+
+    impl UserData for Counter {
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("value", |_, this| Ok(this.0));
+        }
+
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method_mut("increment", |_, this, ()| {
+                this.0 += 1;
+                Ok(())
+            });
+        }
+    }
+
+    let lua = Lua::new();
+
+    // Extensions added before the metatable exists are applied when it's created
+    lua.extend_userdata_type::<Counter, _>(|methods, fields| {
+        methods.add_method("doubled", |_, this, ()| Ok(this.0 * 2));
+        fields.add_field_method_set("value", |_, this, value: i64| {
+            this.0 = value;
+            Ok(())
+        });
+    })?;
+
+    lua.globals().set("counter", Counter(1))?;
+    lua.load(
+        r#"
+        assert(counter:doubled() == 2)
+        counter.value = 5
+        assert(counter.value == 5)
+        counter:increment()
+        assert(counter.value == 6)
+    "#,
+    )
+    .exec()?;
+
+    // Extensions added once the metatable is cached reach values created before the call, and
+    // replace methods of the same name
+    lua.extend_userdata_type::<Counter, _>(|methods, fields| {
+        methods.add_method("tripled", |_, this, ()| Ok(this.0 * 3));
+        methods.add_method("doubled", |_, this, ()| Ok(this.0 * 20));
+        fields.add_field_method_get("negated", |_, this| Ok(-this.0));
+        methods.add_meta_function(MetaMethod::Index, |_, (_, key): (AnyUserData, String)| {
+            Ok(format!("missing {}", key.to_str()?))
+        });
+    })?;
+
+    lua.load(
+        r#"
+        assert(counter:tripled() == 18)
+        assert(counter:doubled() == 120)
+        assert(counter.negated == -6)
+        counter:increment()
+        assert(counter.value == 7)
+        counter.value = 8
+        assert(counter.value == 8)
+
+        -- `Index` from an extension only sees keys which aren't fields or methods
+        assert(counter.nonexistent == "missing nonexistent")
+    "#,
+    )
+    .exec()?;
+
+    // Later extensions keep the fields, methods and `Index` fallback added before them
+    lua.extend_userdata_type::<Counter, _>(|_, fields| {
+        fields.add_field_method_get("squared", |_, this| Ok(this.0 * this.0));
+    })?;
+
+    lua.globals().set("other", Counter(3))?;
+    lua.load(
+        r#"
+        assert(counter.squared == 64)
+        assert(other.squared == 9)
+        assert(other:tripled() == 9)
+        assert(counter.value == 8)
+        assert(counter.other == "missing other")
+    "#,
+    )
+    .exec()?;
+
+    // Proxies of `Counter` see its extensions, including those added after their metatables
+    lua.globals()
+        .set("shared", Arc::new(AtomicRefCell::new(Counter(2))))?;
+    lua.load(
+        r#"
+        assert(shared.value == 2)
+        shared:increment()
+        assert(shared.value == 3)
+        assert(shared:tripled() == 9 and shared:doubled() == 60)
+        assert(shared.squared == 9 and shared.negated == -3)
+        shared.value = 4
+        assert(shared.value == 4)
+        assert(shared.other == "missing other")
+    "#,
+    )
+    .exec()?;
+
+    lua.extend_userdata_type::<Counter, _>(|methods, _| {
+        methods.add_method("halved", |_, this, ()| Ok(this.0 / 2));
+    })?;
+    lua.load("assert(shared:halved() == 2 and counter:halved() == 4)")
+        .exec()?;
+
+    Ok(())
+}
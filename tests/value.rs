@@ -1,4 +1,5 @@
-use hv::lua::{Lua, Result, Value};
+use hv::alchemy::Type;
+use hv::lua::{Error, Lua, Result, Table, UserData, UserDataFields, Value};
 
 #[test]
 fn test_value_eq() -> Result<()> {
@@ -55,3 +56,71 @@ fn test_value_eq() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_value_transfer() -> Result<()> {
+    #[derive(Clone)]
+    struct Shared(i64);
+
+    impl UserData for Shared {
+        fn on_metatable_init(table: Type<Self>) {
+            table.add_clone().add_send();
+        }
+
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_field_method_get("value", |_, this| Ok(this.0));
+        }
+    }
+
+    struct Local;
+
+    impl UserData for Local {}
+
+    let source = Lua::new();
+    let target = Lua::new();
+
+    source.globals().set("shared", Shared(42))?;
+    let value: Value = source
+        .load(
+            r#"
+            local inner = { 1, 2.5, "three", true }
+            local t = { inner = inner, again = inner, shared = shared, other = shared }
+            t.self = t
+            t[inner] = "table key"
+            return t
+            "#,
+        )
+        .eval()?;
+
+    target.globals().set("t", value.transfer_to(&target)?)?;
+    target
+        .load(
+            r#"
+            assert(t.self == t)
+            assert(t.inner == t.again)
+            assert(t.inner[1] == 1 and t.inner[2] == 2.5 and t.inner[3] == "three" and t.inner[4])
+            assert(t[t.inner] == "table key")
+            assert(t.shared == t.other and t.shared.value == 42)
+            "#,
+        )
+        .exec()?;
+
+    source.globals().set("local_ud", Local)?;
+    let value: Value = source
+        .load(r#"{ list = { 1, { f = print } }, ["a key"] = { local_ud } }"#)
+        .eval()?;
+    let mut paths = Vec::new();
+    for key in ["list", "a key"] {
+        let value: Table = match &value {
+            Value::Table(t) => source.create_table_from([(key, t.get::<_, Value>(key)?)])?,
+            _ => unreachable!(),
+        };
+        match Value::Table(value).transfer_to(&target) {
+            Err(Error::TransferError { path, .. }) => paths.push(path),
+            r => panic!("expected TransferError, got {:?}", r),
+        }
+    }
+    assert_eq!(paths, vec!["value.list[2].f", r#"value["a key"][1]"#]);
+
+    Ok(())
+}